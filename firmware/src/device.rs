@@ -0,0 +1,88 @@
+//! Works out which kind of JukeBox this firmware is running on
+
+use embedded_hal::digital::v2::InputPin;
+use jukebox_util::peripheral::{DeviceSettings, DeviceType};
+use rp2040_flash::flash;
+use rp_pico::hal::gpio::{DynPinId, FunctionSioInput, Pin, PullDown};
+use rp_pico::hal::pac;
+
+use crate::modules::keyboard::DEFAULT_KEY_ROWS;
+
+// The last sector of flash is kept free (see memory.x) for device settings.
+const XIP_BASE: u32 = 0x1000_0000;
+pub const SETTINGS_OFFSET: u32 = 2048 * 1024 - 4096;
+const SETTINGS_SECTOR_SIZE: u32 = 4096;
+const SETTINGS_PAGE_SIZE: usize = 256;
+const SETTINGS_MAGIC: &[u8; 4] = b"JBDT";
+// after the magic: device type ident, key rows
+const SETTINGS_LEN: usize = 2;
+
+fn stored_settings() -> Option<DeviceSettings> {
+    // the settings sector is memory mapped, so we can read it without going through the flash driver
+    let settings = unsafe {
+        core::slice::from_raw_parts(
+            (XIP_BASE + SETTINGS_OFFSET) as *const u8,
+            SETTINGS_MAGIC.len() + SETTINGS_LEN,
+        )
+    };
+
+    if &settings[..SETTINGS_MAGIC.len()] != SETTINGS_MAGIC {
+        return None;
+    }
+
+    DeviceSettings::decode(&settings[SETTINGS_MAGIC.len()..]).ok()
+}
+
+pub fn detect_key_rows() -> usize {
+    match stored_settings() {
+        Some(s) if s.device_type == DeviceType::KeyPad => s.key_rows as usize,
+        _ => DEFAULT_KEY_ROWS,
+    }
+}
+
+pub fn detect_device_type(
+    strap0: &Pin<DynPinId, FunctionSioInput, PullDown>,
+    strap1: &Pin<DynPinId, FunctionSioInput, PullDown>,
+) -> DeviceType {
+    // a device type written to flash by the host wins over the strapping pins
+    if let Some(s) = stored_settings() {
+        return s.device_type;
+    }
+
+    // strapping pins are pulled down, so boards without straps are keypads
+    match (strap0.is_high().unwrap(), strap1.is_high().unwrap()) {
+        (false, false) => DeviceType::KeyPad,
+        (true, false) => DeviceType::KnobPad,
+        (false, true) => DeviceType::PedalPad,
+        (true, true) => DeviceType::Unknown,
+    }
+}
+
+/// Saves the settings to flash and restarts the device, so it comes back up as the new board.
+/// Core 1 runs from flash, which can't be read while it's written, so it's stopped first.
+pub fn write_settings(psm: &pac::PSM, settings: DeviceSettings) -> ! {
+    psm.frce_off().modify(|_, w| w.proc1().set_bit());
+    while !psm.frce_off().read().proc1().bit_is_set() {
+        cortex_m::asm::nop();
+    }
+
+    // flash is programmed a page at a time, the rest of the page stays erased
+    let mut page = [0xFFu8; SETTINGS_PAGE_SIZE];
+    page[..SETTINGS_MAGIC.len()].copy_from_slice(SETTINGS_MAGIC);
+    page[SETTINGS_MAGIC.len()..SETTINGS_MAGIC.len() + SETTINGS_LEN]
+        .copy_from_slice(&settings.encode());
+    unsafe {
+        cortex_m::interrupt::free(|_cs| {
+            flash::flash_range_erase(SETTINGS_OFFSET, SETTINGS_SECTOR_SIZE, true);
+            flash::flash_range_program(SETTINGS_OFFSET, &page, true);
+        })
+    };
+
+    // a watchdog reset restarts everything core 1 might have left locked, and reads back as a
+    // software reset
+    let watchdog = unsafe { &*pac::WATCHDOG::ptr() };
+    watchdog.ctrl().write(|w| w.trigger().set_bit());
+    loop {
+        cortex_m::asm::nop();
+    }
+}
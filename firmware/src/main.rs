@@ -3,24 +3,33 @@
 #![no_std]
 #![no_main]
 
-use jukebox_util::peripheral::JBInputs;
-use mutually_exclusive_features::exactly_one_of;
-exactly_one_of!("keypad", "knobpad", "pedalpad");
+use jukebox_util::{
+    diagnostics::{DiagnosticsReport, DiagnosticsTest},
+    peripheral::{
+        Connection, DeviceSettings, DeviceType, InputReport, KeyLabels, PedalConfig, RgbConfig,
+        ScreenOrientation, ScreenPowerConfig,
+    },
+    protocol::ResetReason,
+    status::DeviceStatus,
+};
 
 #[link_section = ".boot_loader"]
 #[used]
 pub static BOOT_LOADER: [u8; 256] = rp2040_boot2::BOOT_LOADER_GENERIC_03H;
 
+mod device;
 mod mutex;
 mod peripheral;
 mod st7789;
+mod system_control;
 mod uid;
 mod modules {
-    #[cfg(feature = "keypad")]
+    pub mod hid;
     pub mod keyboard;
+    pub mod knob;
     pub mod led;
+    pub mod pedal;
     pub mod rgb;
-    #[cfg(feature = "keypad")]
     pub mod screen;
     pub mod serial;
 }
@@ -30,22 +39,26 @@ use mutex::Mutex;
 
 use embedded_hal::timer::CountDown as _;
 use panic_probe as _;
-use peripheral::inputs_default;
+use peripheral::{inputs_clear_latched, inputs_default, inputs_switch_mask, rgb_len};
 use rp_pico::hal::{
+    adc::AdcPin,
     clocks::init_clocks_and_plls,
+    dma::DMAExt,
     fugit::ExtU32,
     multicore::{Multicore, Stack},
     pac::Peripherals,
     pio::PIOExt,
+    pwm,
     rom_data::reset_to_usb_boot,
     sio::Sio,
     usb,
-    watchdog::Watchdog,
-    Clock, Timer,
+    watchdog::{ScratchRegister, Watchdog},
+    Adc, Clock, Timer,
 };
 use rp_pico::{entry, Pins};
 
 use usb_device::{class_prelude::*, prelude::*};
+use usbd_hid::device::{consumer::ConsumerControl, keyboard::NKROBootKeyboard, mouse::WheelMouse};
 use usbd_hid::prelude::*;
 use usbd_human_interface_device as usbd_hid;
 use usbd_serial::SerialPort;
@@ -57,8 +70,32 @@ use defmt_rtt as _;
 static mut CORE1_STACK: Stack<8192> = Stack::new();
 
 // inter-core mutexes
-static PERIPHERAL_INPUTS: Mutex<1, JBInputs> = Mutex::new(inputs_default());
+static PERIPHERAL_INPUTS: Mutex<1, InputReport> = Mutex::new(InputReport::new());
 static UPDATE_TRIGGER: Mutex<2, bool> = Mutex::new(false);
+static CORE1_HEARTBEAT: Mutex<3, bool> = Mutex::new(false);
+static DIAGNOSTICS: Mutex<4, DiagnosticsReport> = Mutex::new(DiagnosticsReport::default());
+static PEDAL_CONFIG: Mutex<5, PedalConfig> = Mutex::new(PedalConfig::default());
+static SCREEN_ORIENTATION: Mutex<6, ScreenOrientation> = Mutex::new(ScreenOrientation::default());
+static SCREEN_POWER: Mutex<7, ScreenPowerConfig> = Mutex::new(ScreenPowerConfig::default());
+static USB_SUSPENDED: Mutex<8, bool> = Mutex::new(false);
+static SCREEN_TEXT: Mutex<9, screen::TextQueue> = Mutex::new(screen::TextQueue::new());
+static KEY_LABELS: Mutex<10, Option<KeyLabels>> = Mutex::new(None);
+static ANIMATION_CHUNK: Mutex<11, screen::AnimationChunk> =
+    Mutex::new(screen::AnimationChunk::new());
+static RGB_CONFIG: Mutex<12, RgbConfig> = Mutex::new(RgbConfig::default());
+static DEVICE_STATUS: Mutex<13, DeviceStatus> = Mutex::new(DeviceStatus::NotConnected);
+static HID_QUEUE: Mutex<14, hid::HidQueue> = Mutex::new(hid::HidQueue::new());
+static DEVICE_SETTINGS: Mutex<15, Option<DeviceSettings>> = Mutex::new(None);
+
+// watchdog supervision, core 0 feeds the watchdog only while core 1 keeps beating
+const WATCHDOG_TIMEOUT: u32 = 1000;
+const WATCHDOG_CHECK: u32 = 100;
+// written to a watchdog scratch register (survives the reset) to remember who stalled
+const WATCHDOG_STALL_CORE0: u32 = 0x4A42_5730;
+const WATCHDOG_STALL_CORE1: u32 = 0x4A42_5731;
+
+// time for the reply to new device settings to reach the host before they're saved and we restart
+const SETTINGS_WRITE_DELAY: u64 = 100;
 
 #[entry]
 fn main() -> ! {
@@ -69,6 +106,22 @@ fn main() -> ! {
 
     // set up hardware interfaces
     let mut pac = Peripherals::take().unwrap();
+    let reset_reason = {
+        let reason = pac.WATCHDOG.reason().read();
+        if reason.timer().bit_is_set() {
+            match pac.WATCHDOG.scratch0().read().bits() {
+                WATCHDOG_STALL_CORE1 => ResetReason::Core1Stall,
+                _ => ResetReason::Core0Stall,
+            }
+        } else if reason.force().bit_is_set() {
+            ResetReason::Software
+        } else {
+            ResetReason::PowerOn
+        }
+    };
+    if reset_reason.is_watchdog() {
+        warn!("reset by watchdog: {}", reset_reason.encode());
+    }
     let mut watchdog = Watchdog::new(pac.WATCHDOG);
     let clocks = init_clocks_and_plls(
         rp_pico::XOSC_CRYSTAL_FREQ,
@@ -84,6 +137,33 @@ fn main() -> ! {
     let mut mc = Multicore::new(&mut pac.PSM, &mut pac.PPB, &mut sio.fifo);
     let core1 = &mut mc.cores()[1];
 
+    // work out what board we're on, everything after this depends on it
+    let pins = Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+    let device_type = {
+        let strap0 = pins.gpio6.into_function().into_dyn_pin().into_pull_type();
+        let strap1 = pins.gpio7.into_function().into_dyn_pin().into_pull_type();
+        // give the pull downs a moment to settle
+        for _ in 0..100 {
+            cortex_m::asm::nop();
+        }
+        device::detect_device_type(&strap0, &strap1)
+    };
+    let key_rows = device::detect_key_rows();
+    info!(
+        "device type: {}, key rows: {}",
+        device_type.ident(),
+        key_rows
+    );
+    if device_type == DeviceType::Unknown {
+        warn!("board type unknown, set it from Board Setup in the desktop app");
+    }
+    PERIPHERAL_INPUTS.with_mut_lock(|i| *i = inputs_default(device_type, key_rows));
+
     // set up timers
     let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
     let mut serial_timer = timer.count_down();
@@ -92,6 +172,8 @@ fn main() -> ! {
     hid_tick.start(4.millis());
     let mut nkro_tick = timer.count_down();
     nkro_tick.start(1.millis());
+    let mut watchdog_tick = timer.count_down();
+    watchdog_tick.start(WATCHDOG_CHECK.millis());
 
     // set up usb
     let usb_bus = UsbBusAllocator::new(usb::UsbBus::new(
@@ -103,87 +185,141 @@ fn main() -> ! {
     ));
     let mut usb_hid = UsbHidClassBuilder::new()
         .add_device(usbd_hid::device::keyboard::NKROBootKeyboardConfig::default())
-        // .add_device(usbd_hid::device::mouse::WheelMouseConfig::default())
+        .add_device(usbd_hid::device::consumer::ConsumerControlConfig::new(
+            // polled faster than the crate's default, so knob detents keep up
+            usbd_hid::interface::InterfaceBuilder::new(
+                usbd_hid::device::consumer::MULTIPLE_CODE_REPORT_DESCRIPTOR,
+            )
+            .unwrap()
+            .description("Consumer Control")
+            .in_endpoint(10.millis())
+            .unwrap()
+            .without_out_endpoint()
+            .build(),
+        ))
+        .add_device(system_control::SystemControlConfig::default())
+        .add_device(usbd_hid::device::mouse::WheelMouseConfig::default())
         .build(&usb_bus);
     let mut usb_serial = SerialPort::new(&usb_bus);
-    let usb_pid = if cfg!(feature = "keypad") {
-        0xF20A
-    } else if cfg!(feature = "knobpad") {
-        0xF20B
-    } else if cfg!(feature = "pedalpad") {
-        0xF20C
-    } else {
-        0xF209
-    };
-    let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1209, usb_pid))
+    let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1209, device_type.usb_pid()))
         .strings(&[StringDescriptors::default()
             .manufacturer("FriendTeamInc")
             .product("JukeBox V5")
             .serial_number(&uid)])
         .unwrap()
         .composite_with_iads()
+        .supports_remote_wakeup(true)
         .build();
 
     // set up modules
     let mut serial_mod = serial::SerialMod::new(timer.count_down());
+    let mut hid_mod = hid::HidMod::new();
 
     // core 1 event loop (GPIO)
     core1
         .spawn(unsafe { &mut CORE1_STACK.mem }, move || {
             let mut pac = unsafe { Peripherals::steal() };
-            let pins = Pins::new(
-                pac.IO_BANK0,
-                pac.PADS_BANK0,
-                sio.gpio_bank0,
-                &mut pac.RESETS,
-            );
 
             // set up GPIO and modules
-            #[cfg(feature = "keypad")]
-            let mut keyboard_mod = {
-                let kb_col_pins = [
-                    pins.gpio12.into_function().into_dyn_pin().into_pull_type(),
-                    pins.gpio13.into_function().into_dyn_pin().into_pull_type(),
-                    pins.gpio14.into_function().into_dyn_pin().into_pull_type(),
-                    pins.gpio15.into_function().into_dyn_pin().into_pull_type(),
-                ];
-                let kb_row_pins = [
-                    pins.gpio9.into_function().into_dyn_pin().into_pull_type(),
-                    pins.gpio10.into_function().into_dyn_pin().into_pull_type(),
-                    pins.gpio11.into_function().into_dyn_pin().into_pull_type(),
-                ];
-                keyboard::KeyboardMod::new(kb_col_pins, kb_row_pins, timer.count_down())
-            };
-
-            #[cfg(feature = "keypad")]
-            let mut screen_mod = {
-                let screen_pins = (
-                    pins.gpio21.into_function().into_dyn_pin().into_pull_type(), // data
-                    pins.gpio20.into_function().into_dyn_pin().into_pull_type(), // clock
-                    pins.gpio19.into_function().into_dyn_pin().into_pull_type(), // cs
-                    pins.gpio18.into_function().into_dyn_pin().into_pull_type(), // dc
-                    pins.gpio17.into_function().into_dyn_pin().into_pull_type(), // rst
-                    pins.gpio16.into_function().into_dyn_pin().into_pull_type(), // backlight
-                );
-                let (mut pio1, _, sm1, _, _) = pac.PIO1.split(&mut pac.RESETS);
-                let mut st = st7789::St7789::new(
-                    &mut pio1,
-                    sm1,
-                    screen_pins.0,
-                    screen_pins.1,
-                    screen_pins.2,
-                    screen_pins.3,
-                    screen_pins.4,
-                    screen_pins.5,
-                    timer.count_down(),
-                );
-                st.init();
-                screen::ScreenMod::new(st, timer.count_down())
-            };
+            let mut keyboard_mod = None;
+            let mut screen_mod = None;
+            let mut knob_mod = None;
+            let mut pedal_mod = None;
+            match device_type {
+                DeviceType::KeyPad => {
+                    keyboard_mod = Some({
+                        let kb_col_pins = [
+                            pins.gpio12.into_function().into_dyn_pin().into_pull_type(),
+                            pins.gpio13.into_function().into_dyn_pin().into_pull_type(),
+                            pins.gpio14.into_function().into_dyn_pin().into_pull_type(),
+                            pins.gpio15.into_function().into_dyn_pin().into_pull_type(),
+                        ];
+                        let kb_row_pins = [
+                            Some(pins.gpio9.into_function().into_dyn_pin().into_pull_type()),
+                            Some(pins.gpio10.into_function().into_dyn_pin().into_pull_type()),
+                            Some(pins.gpio11.into_function().into_dyn_pin().into_pull_type()),
+                            // only 4x4 boards have the fourth row
+                            (key_rows > 3).then(|| {
+                                pins.gpio8.into_function().into_dyn_pin().into_pull_type()
+                            }),
+                        ];
+                        keyboard::KeyboardMod::new(kb_col_pins, kb_row_pins, timer.count_down())
+                    });
+                    screen_mod = Some({
+                        let screen_pins = (
+                            pins.gpio21.into_function().into_dyn_pin().into_pull_type(), // data
+                            pins.gpio20.into_function().into_dyn_pin().into_pull_type(), // clock
+                            pins.gpio19.into_function().into_dyn_pin().into_pull_type(), // cs
+                            pins.gpio18.into_function().into_dyn_pin().into_pull_type(), // dc
+                            pins.gpio17.into_function().into_dyn_pin().into_pull_type(), // rst
+                        );
+                        // the backlight is dimmed with pwm, gpio16 is slice 0 channel a
+                        let pwm_slices = pwm::Slices::new(pac.PWM, &mut pac.RESETS);
+                        let mut backlight_pwm = pwm_slices.pwm0;
+                        backlight_pwm.set_ph_correct();
+                        backlight_pwm.enable();
+                        let mut backlight = backlight_pwm.channel_a;
+                        backlight.output_to(pins.gpio16);
+                        let (mut pio1, _, sm1, _, _) = pac.PIO1.split(&mut pac.RESETS);
+                        let dma = pac.DMA.split(&mut pac.RESETS);
+                        let mut st = st7789::St7789::new(
+                            &mut pio1,
+                            sm1,
+                            dma.ch0,
+                            screen_pins.0,
+                            screen_pins.1,
+                            screen_pins.2,
+                            screen_pins.3,
+                            screen_pins.4,
+                            backlight,
+                            timer.count_down(),
+                        );
+                        st.init();
+                        screen::ScreenMod::new(st, timer.count_down())
+                    });
+                }
+                DeviceType::KnobPad => {
+                    knob_mod = Some({
+                        let encoder_pins = [
+                            (
+                                pins.gpio12.into_function().into_dyn_pin().into_pull_type(), // left a
+                                pins.gpio13.into_function().into_dyn_pin().into_pull_type(), // left b
+                            ),
+                            (
+                                pins.gpio9.into_function().into_dyn_pin().into_pull_type(), // right a
+                                pins.gpio10.into_function().into_dyn_pin().into_pull_type(), // right b
+                            ),
+                        ];
+                        let switch_pins = [
+                            pins.gpio14.into_function().into_dyn_pin().into_pull_type(), // left
+                            pins.gpio11.into_function().into_dyn_pin().into_pull_type(), // right
+                        ];
+                        knob::KnobMod::new(encoder_pins, switch_pins, timer.count_down())
+                    });
+                }
+                DeviceType::PedalPad => {
+                    pedal_mod = Some({
+                        let switch_pins = [
+                            pins.gpio12.into_function().into_dyn_pin().into_pull_type(), // left
+                            pins.gpio13.into_function().into_dyn_pin().into_pull_type(), // middle
+                            pins.gpio14.into_function().into_dyn_pin().into_pull_type(), // right
+                        ];
+                        let analog_pins = [
+                            pins.gpio26.into_function().into_dyn_pin().into_pull_type(), // left
+                            pins.gpio27.into_function().into_dyn_pin().into_pull_type(), // middle
+                            pins.gpio28.into_function().into_dyn_pin().into_pull_type(), // right
+                        ]
+                        .map(|p| AdcPin::new(p).unwrap());
+                        let adc = Adc::new(pac.ADC, &mut pac.RESETS);
+                        pedal::PedalMod::new(switch_pins, analog_pins, adc, timer.count_down())
+                    });
+                }
+                _ => {}
+            }
 
             let mut led_mod = {
                 let led_pin = pins.led.into_function().into_dyn_pin().into_pull_type();
-                led::LedMod::new(led_pin, timer.count_down())
+                led::LedMod::new(led_pin)
             };
             let mut rgb_mod = {
                 let rgb_pin = pins.gpio2.into_function().into_dyn_pin().into_pull_type();
@@ -195,29 +331,57 @@ fn main() -> ! {
                     clocks.peripheral_clock.freq(),
                     timer.count_down(),
                 );
-                rgb::RgbMod::new(ws, timer.count_down())
+                rgb::RgbMod::new(ws, rgb_len(device_type, key_rows), timer.count_down())
             };
 
+            let mut leds_off = false;
             loop {
+                // let core 0 know we're still alive
+                CORE1_HEARTBEAT.with_mut_lock(|h| *h = true);
+
                 // update input devices
-                #[cfg(feature = "keypad")]
-                keyboard_mod.update();
+                if let Some(k) = &mut keyboard_mod {
+                    k.update();
+                }
+                if let Some(k) = &mut knob_mod {
+                    k.update();
+                }
+                if let Some(p) = &mut pedal_mod {
+                    PEDAL_CONFIG.with_lock(|c| p.set_config(*c));
+                    p.update();
+                }
 
                 // update mutexes
+                let mut switches = 0;
                 PERIPHERAL_INPUTS.with_mut_lock(|i| {
-                    #[cfg(feature = "keypad")]
-                    {
-                        *i = JBInputs::KeyPad(keyboard_mod.get_pressed_keys().into());
+                    if let (Some(k), Some(b)) = (&keyboard_mod, i.keys_mut()) {
+                        b.set_all(&k.get_pressed_keys());
+                    }
+                    if let (Some(k), Some(b)) = (&mut knob_mod, i.encoders_mut()) {
+                        k.update_inputs(b, timer.get_counter());
                     }
+                    if let Some(p) = &pedal_mod {
+                        p.update_inputs(i);
+                    }
+                    switches = inputs_switch_mask(i);
+                });
+                let mut diagnostics = DiagnosticsTest::Stop;
+                DIAGNOSTICS.with_mut_lock(|d| {
+                    d.pressed = switches;
+                    if d.test == DiagnosticsTest::Keys {
+                        d.stuck &= switches;
+                    }
+                    diagnostics = d.test;
                 });
 
                 // check if we need to shutdown "cleanly" for update
                 UPDATE_TRIGGER.with_lock(|u| {
                     if *u {
-                        #[cfg(feature = "keypad")]
-                        screen_mod.clear();
+                        if let Some(s) = &mut screen_mod {
+                            s.clear();
+                        }
 
-                        led_mod.clear();
+                        led_mod.update(DeviceStatus::UpdatePending, timer.get_counter());
                         rgb_mod.clear();
 
                         // wait a few cycles for the IO to finish
@@ -229,50 +393,191 @@ fn main() -> ! {
                     }
                 });
 
-                // update accessories
-                led_mod.update();
-                rgb_mod.update(timer.get_counter());
+                // update accessories, or run their self-test
+                let mut suspended = false;
+                USB_SUSPENDED.with_lock(|u| suspended = *u);
+                let resumed = !suspended && leds_off;
+                if suspended {
+                    // the leds stay dark while the host sleeps, to keep within suspend current
+                    if !leds_off {
+                        led_mod.clear();
+                        rgb_mod.clear();
+                        leds_off = true;
+                    }
+                } else {
+                    leds_off = false;
+                    match diagnostics {
+                        DiagnosticsTest::StatusLed => {
+                            led_mod.update_diagnostics(timer.get_counter())
+                        }
+                        _ => {
+                            let mut status = DeviceStatus::NotConnected;
+                            DEVICE_STATUS.with_lock(|s| status = *s);
+                            led_mod.update(status, timer.get_counter());
+                        }
+                    }
+                    match diagnostics {
+                        DiagnosticsTest::Rgb => rgb_mod.update_diagnostics(timer.get_counter()),
+                        _ => {
+                            RGB_CONFIG.with_lock(|c| rgb_mod.set_config(*c));
+                            rgb_mod.update(timer.get_counter())
+                        }
+                    }
+                }
 
-                #[cfg(feature = "keypad")]
-                screen_mod.update(timer.get_counter(), &timer);
+                if let Some(s) = &mut screen_mod {
+                    SCREEN_ORIENTATION.with_lock(|o| s.set_orientation(*o));
+                    SCREEN_POWER.with_lock(|c| s.set_power_config(*c));
+                    // labels first, they redraw the whole screen and texts go on top
+                    let mut labels = None;
+                    KEY_LABELS.with_mut_lock(|l| labels = l.take());
+                    if let Some(l) = labels {
+                        s.show_key_labels(&l);
+                    }
+                    let mut chunk = None;
+                    ANIMATION_CHUNK.with_mut_lock(|c| {
+                        if !c.is_empty() {
+                            chunk = Some(c.take());
+                        }
+                    });
+                    if let Some(c) = chunk {
+                        s.play_animation_chunk(c.data());
+                    }
+                    let mut texts = screen::TextQueue::new();
+                    SCREEN_TEXT.with_mut_lock(|q| texts = q.take());
+                    for t in texts.iter() {
+                        s.show_text(t);
+                    }
+                    // the screen test counts as activity so it isn't run on a dark screen, and the
+                    // host waking up brings the screen back with it
+                    let activity =
+                        switches != 0 || resumed || diagnostics == DiagnosticsTest::Screen;
+                    s.update_power(timer.get_counter(), activity, suspended);
+
+                    match diagnostics {
+                        DiagnosticsTest::Screen => s.update_diagnostics(timer.get_counter()),
+                        _ => s.update(timer.get_counter()),
+                    }
+                }
             }
         })
         .expect("failed to start core1");
 
+    // start the watchdog, if either core stalls the device will reset
+    watchdog.write_scratch(ScratchRegister::Scratch0, WATCHDOG_STALL_CORE0);
+    watchdog.pause_on_debug(true);
+    watchdog.start((WATCHDOG_TIMEOUT * 1000).micros());
+
     // main event loop (USB comms)
+    let serial_shared = serial::SerialShared {
+        firmware_version: ver,
+        device_uid: uid,
+        reset_reason,
+        device_type,
+        key_rows,
+        peripheral_inputs: &PERIPHERAL_INPUTS,
+        update_trigger: &UPDATE_TRIGGER,
+        diagnostics: &DIAGNOSTICS,
+        pedal_config: &PEDAL_CONFIG,
+        screen_orientation: &SCREEN_ORIENTATION,
+        screen_power: &SCREEN_POWER,
+        screen_text: &SCREEN_TEXT,
+        key_labels: &KEY_LABELS,
+        animation_chunk: &ANIMATION_CHUNK,
+        rgb_config: &RGB_CONFIG,
+        hid_queue: &HID_QUEUE,
+        device_settings: &DEVICE_SETTINGS,
+    };
+    let mut usb_suspended = false;
+    // shown on the status led until the host has seen the device again
+    let mut recovered = reset_reason.is_watchdog();
+    let mut new_settings = None;
+    let mut wakeup_pressed = false;
     loop {
+        // check core 1's heartbeat and feed the watchdog
+        if watchdog_tick.wait().is_ok() {
+            let mut core1_alive = false;
+            CORE1_HEARTBEAT.with_mut_lock(|h| {
+                core1_alive = *h;
+                *h = false;
+            });
+
+            if core1_alive {
+                watchdog.write_scratch(ScratchRegister::Scratch0, WATCHDOG_STALL_CORE0);
+                watchdog.feed();
+            } else {
+                watchdog.write_scratch(ScratchRegister::Scratch0, WATCHDOG_STALL_CORE1);
+            }
+        }
+
         // tick for hid devices
         if hid_tick.wait().is_ok() {
-            // handle keyboard
-            // #[cfg(feature = "keypad")]
-            // let pressed = keyboard::KeyboardMod::get_keyboard_keys(
-            //     serial_mod.get_connection_status().connected(),
-            //     &PERIPHERAL_INPUTS,
-            // );
-            // match usb_hid
-            //     .device::<NKROBootKeyboard<'_, _>, _>()
-            //     .write_report(pressed)
-            // {
-            //     Ok(_) => {}
-            //     Err(UsbHidError::Duplicate) => {}
-            //     Err(UsbHidError::WouldBlock) => {}
-            //     Err(e) => {
-            //         core::panic!("Failed to write keyboard report: {:?}", e)
-            //     }
-            // }
-
-            // handle mouse
-            // match usb_hid
-            //     .device::<WheelMouse<'_, _>, _>()
-            //     .write_report(&WheelMouseReport::default())
-            // {
-            //     Ok(_) => {}
-            //     Err(UsbHidError::Duplicate) => {}
-            //     Err(UsbHidError::WouldBlock) => {}
-            //     Err(e) => {
-            //         core::panic!("Failed to write mouse report: {:?}", e)
-            //     }
-            // }
+            // with no host app to handle them, the inputs go out as hid reports. a suspended host
+            // is woken up instead, and the press isn't played back once it's awake
+            let standalone = serial_mod.get_connection_status() != Connection::Connected;
+            PERIPHERAL_INPUTS.with_mut_lock(|i| {
+                if usb_suspended {
+                    inputs_clear_latched(i);
+                }
+                hid_mod.update(standalone && !usb_suspended, device_type, i)
+            });
+            // the host app's own reports
+            hid_mod.play(!standalone, timer.get_counter(), &HID_QUEUE);
+
+            if let Some(report) = hid_mod.mouse_report() {
+                match usb_hid
+                    .device::<WheelMouse<'_, _>, _>()
+                    .write_report(&report)
+                {
+                    Ok(_) => hid_mod.mouse_sent(),
+                    Err(UsbHidError::WouldBlock) => {}
+                    Err(e) => {
+                        core::panic!("Failed to write mouse report: {:?}", e)
+                    }
+                }
+            }
+
+            let mut sent = true;
+            match usb_hid
+                .device::<NKROBootKeyboard<'_, _>, _>()
+                .write_report(hid_mod.keyboard_report())
+            {
+                Ok(_) => {}
+                Err(UsbHidError::Duplicate) => {}
+                Err(UsbHidError::WouldBlock) => sent = false,
+                Err(e) => {
+                    core::panic!("Failed to write keyboard report: {:?}", e)
+                }
+            }
+            // the consumer endpoint is polled slower than this tick, so only changes are written,
+            // and one that doesn't fit is tried again next tick
+            if let Some(report) = hid_mod.consumer_report() {
+                match usb_hid
+                    .device::<ConsumerControl<'_, _>, _>()
+                    .write_report(&report)
+                {
+                    Ok(_) => hid_mod.consumer_sent(report),
+                    Err(UsbHidError::Duplicate) => hid_mod.consumer_sent(report),
+                    Err(UsbHidError::WouldBlock) => sent = false,
+                    Err(e) => {
+                        core::panic!("Failed to write consumer report: {:?}", e)
+                    }
+                }
+            }
+            match usb_hid
+                .device::<system_control::SystemControl<'_, _>, _>()
+                .write_report(hid_mod.system_report())
+            {
+                Ok(_) => {}
+                Err(UsbHidError::Duplicate) => {}
+                Err(UsbHidError::WouldBlock) => sent = false,
+                Err(e) => {
+                    core::panic!("Failed to write system control report: {:?}", e)
+                }
+            }
+            if sent {
+                hid_mod.sent(timer.get_counter());
+            }
         }
 
         // tick for n-key rollover
@@ -289,17 +594,59 @@ fn main() -> ! {
         // update usb devices
         if usb_dev.poll(&mut [&mut usb_hid, &mut usb_serial]) {
             // handle serial
-            serial_mod.update(
-                &mut usb_serial,
-                ver,
-                uid,
-                &PERIPHERAL_INPUTS,
-                &UPDATE_TRIGGER,
-            );
+            serial_mod.update(&mut usb_serial, &serial_shared);
             match usb_serial.flush() {
                 Ok(_) => {}
                 Err(_) => {}
             }
         }
+
+        // save new device settings once the reply to them has gone out, this doesn't return
+        if new_settings.is_none() {
+            DEVICE_SETTINGS.with_mut_lock(|s| {
+                new_settings = s.take().map(|s| (s, timer.get_counter()));
+            });
+        }
+        if let Some((settings, since)) = new_settings {
+            if (timer.get_counter() - since).to_millis() >= SETTINGS_WRITE_DELAY {
+                info!("saving device settings and restarting");
+                device::write_settings(&pac.PSM, settings);
+            }
+        }
+
+        // pick what the status led shows
+        let connection = serial_mod.get_connection_status();
+        if connection == Connection::Connected {
+            recovered = false;
+        }
+        let mut update_pending = false;
+        UPDATE_TRIGGER.with_lock(|u| update_pending = *u);
+        let unconfigured = device_type == DeviceType::Unknown;
+        let status = DeviceStatus::current(connection, update_pending, unconfigured, recovered);
+        DEVICE_STATUS.with_mut_lock(|s| *s = status);
+
+        // let core 1 know when the host suspends us, so the screen can sleep with it
+        let suspended = usb_dev.state() == UsbDeviceState::Suspend;
+        if suspended != usb_suspended {
+            usb_suspended = suspended;
+            USB_SUSPENDED.with_mut_lock(|u| *u = suspended);
+            if suspended {
+                info!("usb suspended");
+                serial_mod.suspend();
+            } else {
+                info!("usb resumed");
+            }
+        }
+
+        // a key going down wakes the host, if it let us
+        if usb_suspended && usb_dev.remote_wakeup_enabled() {
+            let mut pressed = false;
+            PERIPHERAL_INPUTS.with_lock(|i| pressed = inputs_switch_mask(i) != 0);
+            if pressed && !wakeup_pressed {
+                info!("remote wakeup");
+                usb_dev.bus().remote_wakeup();
+            }
+            wakeup_pressed = pressed;
+        }
     }
 }
@@ -0,0 +1,289 @@
+//! HID module, turns the inputs into keyboard, media and system reports in standalone mode, and
+//! plays the report sequences the host sends
+
+use jukebox_util::hid::{
+    standalone_key, standalone_knob, HidReport, HidSequence, HidUsage, MouseReport,
+    HID_CONSUMER_MAX_CODES, HID_KEYBOARD_MAX_KEYS,
+};
+use jukebox_util::peripheral::{DeviceType, InputBlock, InputReport, MAX_ENCODERS, MAX_KEYS};
+use rp_pico::hal::timer::Instant;
+use usbd_human_interface_device::device::consumer::MultipleConsumerReport;
+use usbd_human_interface_device::device::mouse::WheelMouseReport;
+use usbd_human_interface_device::page::{Consumer, Keyboard};
+
+use crate::mutex::Mutex;
+
+const CONSUMER_CODES: usize = 4; // codes in one consumer report
+const HID_QUEUE_LEN: usize = 4;
+const MAX_QUEUED_TAPS: i16 = 16; // a fast spin is cut short, rather than played out for seconds
+
+#[derive(PartialEq, Clone, Copy)]
+enum Tap {
+    Idle,
+    Pressed(HidUsage),
+    Released, // the release has to go out before the next press, or the host sees one long press
+}
+
+/// Sequences from the host waiting to be played, oldest first. When it's full the host has to
+/// wait and send the sequence again.
+#[derive(Clone, Copy)]
+pub struct HidQueue {
+    sequences: [HidSequence; HID_QUEUE_LEN],
+    len: usize,
+}
+impl HidQueue {
+    pub const fn new() -> Self {
+        HidQueue {
+            sequences: [HidSequence::new(); HID_QUEUE_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, sequence: HidSequence) -> Result<(), ()> {
+        if self.len == HID_QUEUE_LEN {
+            return Err(());
+        }
+        self.sequences[self.len] = sequence;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<HidSequence> {
+        if self.len == 0 {
+            return None;
+        }
+        let s = self.sequences[0];
+        self.sequences.copy_within(1..self.len, 0);
+        self.len -= 1;
+        Some(s)
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+pub struct HidMod {
+    held: [HidUsage; MAX_KEYS + MAX_ENCODERS], // keys and knob switches that are down
+    taps: [i16; MAX_ENCODERS],                 // knob detents still to send, positive is clockwise
+    tap: [Tap; MAX_ENCODERS],
+
+    playing: Option<HidSequence>,
+    step: usize,
+    step_sent: Option<Instant>, // when the step's report went out, it's held from then
+    host_keys: [u8; HID_KEYBOARD_MAX_KEYS],
+    host_codes: [u16; HID_CONSUMER_MAX_CODES],
+    host_mouse: Option<MouseReport>, // waiting to go out
+    host_system: u8,
+
+    consumer_sent: Option<MultipleConsumerReport>, // the last one to go out
+}
+
+impl HidMod {
+    pub fn new() -> Self {
+        HidMod {
+            held: [HidUsage::None; MAX_KEYS + MAX_ENCODERS],
+            taps: [0; MAX_ENCODERS],
+            tap: [Tap::Idle; MAX_ENCODERS],
+            playing: None,
+            step: 0,
+            step_sent: None,
+            host_keys: [0; HID_KEYBOARD_MAX_KEYS],
+            host_codes: [0; HID_CONSUMER_MAX_CODES],
+            host_mouse: None,
+            host_system: 0,
+            consumer_sent: None,
+        }
+    }
+
+    /// Reads what's held down. In standalone mode knob detents are taken out of the inputs and
+    /// queued up as taps, the same as the host takes them when it reads the inputs. Otherwise the
+    /// host app handles the inputs, and everything is let go.
+    pub fn update(&mut self, standalone: bool, device_type: DeviceType, inputs: &mut InputReport) {
+        self.held = [HidUsage::None; MAX_KEYS + MAX_ENCODERS];
+        if !standalone {
+            self.taps = [0; MAX_ENCODERS];
+            return;
+        }
+
+        let mut n = 0;
+        for block in inputs.iter_mut() {
+            match block {
+                InputBlock::Keys(k) => {
+                    for key in 0..k.count as usize {
+                        if let Some(h) = self.held.get_mut(n) {
+                            if k.is_down(key) {
+                                *h = standalone_key(device_type, key, k.count as usize);
+                            }
+                        }
+                        n += 1;
+                    }
+                }
+                InputBlock::Encoders(e) => {
+                    for (knob, enc) in e.iter_mut().enumerate() {
+                        let usages = standalone_knob(knob);
+                        if let Some(h) = self.held.get_mut(n) {
+                            if enc.switch.is_down() {
+                                *h = usages.press;
+                            }
+                        }
+                        n += 1;
+
+                        if let Some(t) = self.taps.get_mut(knob) {
+                            *t = (*t + enc.steps).clamp(-MAX_QUEUED_TAPS, MAX_QUEUED_TAPS);
+                        }
+                        enc.steps = 0;
+                    }
+                }
+                InputBlock::Axes(_) => {}
+            }
+        }
+
+        for knob in 0..MAX_ENCODERS {
+            let t = self.taps[knob];
+            if self.tap[knob] == Tap::Idle && t != 0 {
+                let usages = standalone_knob(knob);
+                self.tap[knob] = Tap::Pressed(if t > 0 { usages.cw } else { usages.ccw });
+                self.taps[knob] -= t.signum();
+            }
+        }
+    }
+
+    /// Plays the host's sequences a step at a time. Without the host, whatever it was holding
+    /// down is let go and anything still queued is dropped.
+    pub fn play(&mut self, connected: bool, t: Instant, queue: &Mutex<14, HidQueue>) {
+        if !connected {
+            queue.with_mut_lock(|q| q.clear());
+            self.playing = None;
+            self.step_sent = None;
+            self.host_keys = [0; HID_KEYBOARD_MAX_KEYS];
+            self.host_codes = [0; HID_CONSUMER_MAX_CODES];
+            self.host_mouse = None;
+            self.host_system = 0;
+            return;
+        }
+
+        // move on once the step has gone out and been held long enough
+        let step = self.playing.as_ref().map(|s| s.steps()[self.step]);
+        if let (Some(step), Some(sent)) = (step, self.step_sent) {
+            if (t - sent).to_millis() >= step.hold as u64 {
+                self.step += 1;
+                self.step_sent = None;
+                if self.step < self.playing.as_ref().map_or(0, |s| s.steps().len()) {
+                    self.start_step();
+                } else {
+                    self.playing = None;
+                }
+            }
+        }
+
+        if self.playing.is_none() {
+            let mut next = None;
+            queue.with_mut_lock(|q| next = q.pop());
+            if let Some(s) = next.filter(|s| !s.is_empty()) {
+                self.playing = Some(s);
+                self.step = 0;
+                self.start_step();
+            }
+        }
+    }
+
+    fn start_step(&mut self) {
+        let report = match &self.playing {
+            Some(s) => s.steps()[self.step].report,
+            None => return,
+        };
+        match report {
+            HidReport::Keyboard(k) => self.host_keys = k,
+            HidReport::Consumer(c) => self.host_codes = c,
+            HidReport::Mouse(m) => self.host_mouse = Some(m),
+            HidReport::System(s) => self.host_system = s,
+        }
+    }
+
+    /// Moves the knob taps and the host's sequence along, once the keyboard, consumer and system
+    /// reports carrying them have gone out.
+    pub fn sent(&mut self, t: Instant) {
+        for tap in self.tap.iter_mut() {
+            *tap = match tap {
+                Tap::Pressed(_) => Tap::Released,
+                _ => Tap::Idle,
+            };
+        }
+        if self.playing.is_some() && self.step_sent.is_none() && self.host_mouse.is_none() {
+            self.step_sent = Some(t);
+        }
+    }
+
+    /// The mouse report went out, it's only sent once.
+    pub fn mouse_sent(&mut self) {
+        self.host_mouse = None;
+    }
+
+    fn usages(&self) -> impl Iterator<Item = HidUsage> + '_ {
+        let taps = self.tap.iter().filter_map(|t| match t {
+            Tap::Pressed(u) => Some(*u),
+            _ => None,
+        });
+        self.held.iter().copied().chain(taps)
+    }
+
+    pub fn keyboard_report(&self) -> [Keyboard; MAX_KEYS] {
+        let mut keys = [Keyboard::NoEventIndicated; MAX_KEYS];
+        let host = self.host_keys.iter().filter(|k| **k != 0);
+        let pressed = self
+            .usages()
+            .filter_map(|u| match u {
+                HidUsage::Key(k) => Some(Keyboard::from(k)),
+                _ => None,
+            })
+            .chain(host.map(|k| Keyboard::from(*k)));
+        for (k, p) in keys.iter_mut().zip(pressed) {
+            *k = p;
+        }
+        keys
+    }
+
+    /// The consumer report, if it's changed since the last one went out.
+    pub fn consumer_report(&self) -> Option<MultipleConsumerReport> {
+        let mut codes = [Consumer::Unassigned; CONSUMER_CODES];
+        let host = self.host_codes.iter().filter(|c| **c != 0);
+        let pressed = self
+            .usages()
+            .filter_map(|u| match u {
+                HidUsage::Consumer(c) => Some(Consumer::from(c)),
+                _ => None,
+            })
+            .chain(host.map(|c| Consumer::from(*c)));
+        for (c, p) in codes.iter_mut().zip(pressed) {
+            *c = p;
+        }
+        let report = MultipleConsumerReport { codes };
+        (self.consumer_sent != Some(report)).then_some(report)
+    }
+
+    pub fn consumer_sent(&mut self, report: MultipleConsumerReport) {
+        self.consumer_sent = Some(report);
+    }
+
+    /// The system control being pressed, or 0 for none. Only one goes out at a time, the inputs'
+    /// own before the host's.
+    pub fn system_report(&self) -> u8 {
+        self.usages()
+            .find_map(|u| match u {
+                HidUsage::System(s) => Some(s),
+                _ => None,
+            })
+            .unwrap_or(self.host_system)
+    }
+
+    pub fn mouse_report(&self) -> Option<WheelMouseReport> {
+        self.host_mouse.map(|m| WheelMouseReport {
+            buttons: m.buttons,
+            x: m.x,
+            y: m.y,
+            vertical_wheel: m.wheel,
+            horizontal_wheel: m.pan,
+        })
+    }
+}
@@ -0,0 +1,158 @@
+//! Rotary encoder (knob) processing module
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use embedded_hal::digital::v2::InputPin;
+use embedded_hal::timer::CountDown as _;
+use jukebox_util::{peripheral::EncoderBlock, quadrature::QuadratureDecoder};
+use rp_pico::hal::{
+    fugit::ExtU32,
+    gpio::{DynPinId, FunctionSioInput, Interrupt, Pin, PullUp},
+    pac::{self, interrupt},
+    timer::{CountDown, Instant},
+};
+
+const POLL_RATE: u32 = 5;
+// a knob that hasn't clicked in this long is considered stopped
+const VELOCITY_TIMEOUT: u64 = 250_000; // in microseconds
+pub const KNOB_COUNT: usize = 2;
+
+type KnobPin = Pin<DynPinId, FunctionSioInput, PullUp>;
+
+struct Encoder {
+    a_pin: KnobPin,
+    b_pin: KnobPin,
+    decoder: QuadratureDecoder,
+}
+
+// The encoders are decoded from the GPIO interrupt on core 1, so that fast spins don't lose
+// steps while the main loop is busy. The interrupt and the module only ever run on core 1.
+static ENCODERS: Mutex<RefCell<Option<[Encoder; KNOB_COUNT]>>> = Mutex::new(RefCell::new(None));
+
+pub struct KnobMod<'timer> {
+    switch_pins: [KnobPin; KNOB_COUNT],
+    poll_timer: CountDown<'timer>,
+    pressed_switches: [bool; KNOB_COUNT],
+    last_turn: [Option<Instant>; KNOB_COUNT],
+    velocity: [u16; KNOB_COUNT],
+}
+
+impl<'timer> KnobMod<'timer> {
+    pub fn new(
+        encoder_pins: [(KnobPin, KnobPin); KNOB_COUNT],
+        switch_pins: [KnobPin; KNOB_COUNT],
+        mut count_down: CountDown<'timer>,
+    ) -> Self {
+        count_down.start(POLL_RATE.millis());
+
+        let encoders = encoder_pins.map(|(a_pin, b_pin)| {
+            for p in [&a_pin, &b_pin] {
+                p.set_interrupt_enabled(Interrupt::EdgeLow, true);
+                p.set_interrupt_enabled(Interrupt::EdgeHigh, true);
+            }
+            let decoder =
+                QuadratureDecoder::new(a_pin.is_high().unwrap(), b_pin.is_high().unwrap());
+
+            Encoder {
+                a_pin: a_pin,
+                b_pin: b_pin,
+                decoder: decoder,
+            }
+        });
+        cortex_m::interrupt::free(|cs| ENCODERS.borrow(cs).replace(Some(encoders)));
+
+        unsafe {
+            pac::NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0);
+        }
+
+        KnobMod {
+            switch_pins: switch_pins,
+            poll_timer: count_down,
+            pressed_switches: [false; KNOB_COUNT],
+            last_turn: [None; KNOB_COUNT],
+            velocity: [0; KNOB_COUNT],
+        }
+    }
+
+    fn check_pressed_switches(&mut self) {
+        let mut switches = [false; KNOB_COUNT];
+
+        for (s, p) in switches.iter_mut().zip(self.switch_pins.iter()) {
+            // switches pull to ground when pressed
+            *s = p.is_low().unwrap();
+        }
+
+        self.pressed_switches = switches;
+    }
+
+    pub fn update(&mut self) {
+        if !self.poll_timer.wait().is_ok() {
+            return;
+        }
+
+        self.check_pressed_switches();
+    }
+
+    fn take_detents(&mut self) -> [i32; KNOB_COUNT] {
+        let mut detents = [0; KNOB_COUNT];
+
+        cortex_m::interrupt::free(|cs| {
+            if let Some(encoders) = ENCODERS.borrow(cs).borrow_mut().as_mut() {
+                for (d, e) in detents.iter_mut().zip(encoders.iter_mut()) {
+                    *d = e.decoder.take_detents();
+                }
+            }
+        });
+
+        detents
+    }
+
+    fn update_velocity(&mut self, n: usize, detents: i32, t: Instant) {
+        let since = self.last_turn[n].map(|l| (t - l).to_micros());
+
+        if detents != 0 {
+            self.velocity[n] = match since {
+                Some(s) if s < VELOCITY_TIMEOUT => (detents.unsigned_abs() as u64 * 1_000_000
+                    / s.max(1))
+                .min(u16::MAX as u64) as u16,
+                // first click after a pause, we can't tell how fast it's going yet
+                _ => 0,
+            };
+            self.last_turn[n] = Some(t);
+        } else if since.map_or(false, |s| s >= VELOCITY_TIMEOUT) {
+            self.velocity[n] = 0;
+            self.last_turn[n] = None;
+        }
+    }
+
+    pub fn update_inputs(&mut self, inputs: &mut EncoderBlock, t: Instant) {
+        let detents = self.take_detents();
+        for (n, d) in detents.iter().enumerate() {
+            self.update_velocity(n, *d, t);
+        }
+
+        // steps pile up until the host reads them, then they're cleared
+        for (n, e) in inputs.iter_mut().enumerate().take(KNOB_COUNT) {
+            e.switch = self.pressed_switches[n].into();
+            e.steps = (e.steps as i32 + detents[n]).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+            e.velocity = self.velocity[n];
+        }
+    }
+}
+
+#[interrupt]
+fn IO_IRQ_BANK0() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(encoders) = ENCODERS.borrow(cs).borrow_mut().as_mut() {
+            for e in encoders.iter_mut() {
+                for p in [&mut e.a_pin, &mut e.b_pin] {
+                    p.clear_interrupt(Interrupt::EdgeLow);
+                    p.clear_interrupt(Interrupt::EdgeHigh);
+                }
+                e.decoder
+                    .update(e.a_pin.is_high().unwrap(), e.b_pin.is_high().unwrap());
+            }
+        }
+    });
+}
@@ -1,50 +1,54 @@
-//! Blinken Light for debugging module
+//! Status LED module, blinks a pattern for what the device is up to
 
 use embedded_hal::digital::v2::OutputPin;
-use embedded_hal::timer::CountDown as _;
+use jukebox_util::status::DeviceStatus;
 use rp_pico::hal::{
-    fugit::ExtU32,
     gpio::{DynPinId, FunctionSioOutput, Pin, PullDown},
-    timer::CountDown,
+    timer::Instant,
 };
 
-const BLINK_TIME: u32 = 500;
+const DIAGNOSTICS_BLINK_TIME: u64 = 100_000; // in microseconds
 
-pub struct LedMod<'timer> {
+pub struct LedMod {
     led_pin: Pin<DynPinId, FunctionSioOutput, PullDown>,
-    timer: CountDown<'timer>,
-    led_on: bool,
+    status: Option<DeviceStatus>,
+    since: Instant, // when the current status began, patterns start from the top
 }
 
-impl<'timer> LedMod<'timer> {
-    pub fn new(
-        led_pin: Pin<DynPinId, FunctionSioOutput, PullDown>,
-        mut count_down: CountDown<'timer>,
-    ) -> Self {
-        count_down.start(BLINK_TIME.millis());
-
+impl LedMod {
+    pub fn new(led_pin: Pin<DynPinId, FunctionSioOutput, PullDown>) -> Self {
         LedMod {
             led_pin: led_pin,
-            timer: count_down,
-            led_on: true,
+            status: None,
+            since: Instant::from_ticks(0),
         }
     }
 
     pub fn clear(&mut self) {
+        self.status = None;
         self.led_pin.set_low().unwrap();
     }
 
-    pub fn update(&mut self) {
-        if !self.timer.wait().is_ok() {
-            return;
+    pub fn update(&mut self, status: DeviceStatus, t: Instant) {
+        if self.status != Some(status) {
+            self.status = Some(status);
+            self.since = t;
         }
 
-        if self.led_on {
+        let ms = (t - self.since).to_millis();
+        if status.is_lit(ms) {
             self.led_pin.set_high().unwrap();
         } else {
             self.led_pin.set_low().unwrap();
         }
+    }
 
-        self.led_on = !self.led_on;
+    pub fn update_diagnostics(&mut self, t: Instant) {
+        let on = (t.duration_since_epoch().to_micros() / DIAGNOSTICS_BLINK_TIME) % 2 == 0;
+        if on {
+            self.led_pin.set_high().unwrap();
+        } else {
+            self.led_pin.set_low().unwrap();
+        }
     }
 }
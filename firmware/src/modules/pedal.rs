@@ -0,0 +1,161 @@
+//! Pedal processing module
+
+use embedded_hal::adc::OneShot;
+use embedded_hal::digital::v2::InputPin;
+use embedded_hal::timer::CountDown as _;
+use jukebox_util::peripheral::{InputReport, PedalConfig, PEDAL_ANALOG_MAX, PEDAL_COUNT};
+use rp_pico::hal::{
+    adc::AdcPin,
+    fugit::ExtU32,
+    gpio::{DynPinId, FunctionNull, FunctionSioInput, Pin, PullNone, PullUp},
+    timer::CountDown,
+    Adc,
+};
+
+const POLL_RATE: u32 = 5;
+// a switch has to hold its new state for this many polls before we believe it
+const DEBOUNCE_POLLS: u8 = 4;
+// analog pedals have to come back up this far past the actuation point to release
+const ANALOG_HYSTERESIS: u16 = 30;
+// raw adc travel needed before an analog pedal is considered calibrated
+const ANALOG_MIN_SPAN: u16 = 256;
+
+type PedalPin = Pin<DynPinId, FunctionSioInput, PullUp>;
+type PedalAnalogPin = AdcPin<Pin<DynPinId, FunctionNull, PullNone>>;
+
+#[derive(Clone, Copy)]
+struct Calibration {
+    rest: u16,
+    low: u16,
+    high: u16,
+}
+impl Calibration {
+    fn new(rest: u16) -> Self {
+        Calibration {
+            rest: rest,
+            low: rest,
+            high: rest,
+        }
+    }
+
+    fn position(&mut self, raw: u16) -> u16 {
+        self.low = self.low.min(raw);
+        self.high = self.high.max(raw);
+
+        // the pedal is assumed to be at rest on boot, whichever way it travels furthest from there
+        // is pressed. this covers hall sensors where the reading drops as the pedal goes down.
+        let (travel, span) = if self.high - self.rest >= self.rest - self.low {
+            (raw.saturating_sub(self.rest), self.high - self.rest)
+        } else {
+            (self.rest.saturating_sub(raw), self.rest - self.low)
+        };
+
+        if span < ANALOG_MIN_SPAN {
+            return 0;
+        }
+
+        (travel as u32 * PEDAL_ANALOG_MAX as u32 / span as u32) as u16
+    }
+}
+
+pub struct PedalMod<'timer> {
+    switch_pins: [PedalPin; PEDAL_COUNT],
+    analog_pins: [PedalAnalogPin; PEDAL_COUNT],
+    adc: Adc,
+    poll_timer: CountDown<'timer>,
+    config: PedalConfig,
+    calibration: [Calibration; PEDAL_COUNT],
+    debounce: [u8; PEDAL_COUNT],
+    pressed_pedals: [bool; PEDAL_COUNT],
+    positions: [u16; PEDAL_COUNT],
+}
+
+impl<'timer> PedalMod<'timer> {
+    pub fn new(
+        switch_pins: [PedalPin; PEDAL_COUNT],
+        mut analog_pins: [PedalAnalogPin; PEDAL_COUNT],
+        mut adc: Adc,
+        mut count_down: CountDown<'timer>,
+    ) -> Self {
+        count_down.start(POLL_RATE.millis());
+
+        let mut calibration = [Calibration::new(0); PEDAL_COUNT];
+        for (c, p) in calibration.iter_mut().zip(analog_pins.iter_mut()) {
+            *c = Calibration::new(adc.read(p).unwrap_or(0));
+        }
+
+        PedalMod {
+            switch_pins: switch_pins,
+            analog_pins: analog_pins,
+            adc: adc,
+            poll_timer: count_down,
+            config: PedalConfig::default(),
+            calibration: calibration,
+            debounce: [0; PEDAL_COUNT],
+            pressed_pedals: [false; PEDAL_COUNT],
+            positions: [0; PEDAL_COUNT],
+        }
+    }
+
+    pub fn set_config(&mut self, config: PedalConfig) {
+        self.config = config;
+    }
+
+    fn check_switch(&mut self, n: usize) {
+        // switches pull to ground when pressed
+        let down = self.switch_pins[n].is_low().unwrap();
+
+        if down == self.pressed_pedals[n] {
+            self.debounce[n] = 0;
+        } else {
+            self.debounce[n] += 1;
+            if self.debounce[n] >= DEBOUNCE_POLLS {
+                self.debounce[n] = 0;
+                self.pressed_pedals[n] = down;
+            }
+        }
+
+        self.positions[n] = if self.pressed_pedals[n] {
+            PEDAL_ANALOG_MAX
+        } else {
+            0
+        };
+    }
+
+    fn check_analog(&mut self, n: usize) {
+        let raw: u16 = self.adc.read(&mut self.analog_pins[n]).unwrap_or(0);
+        let position = self.calibration[n].position(raw);
+        // smooth out some of the adc noise
+        self.positions[n] = ((self.positions[n] as u32 * 3 + position as u32) / 4) as u16;
+
+        let actuation = self.config.actuation[n];
+        self.pressed_pedals[n] = if self.pressed_pedals[n] {
+            self.positions[n] >= actuation.saturating_sub(ANALOG_HYSTERESIS)
+        } else {
+            self.positions[n] >= actuation
+        };
+    }
+
+    pub fn update(&mut self) {
+        if !self.poll_timer.wait().is_ok() {
+            return;
+        }
+
+        for n in 0..PEDAL_COUNT {
+            if self.config.analog[n] {
+                self.check_analog(n);
+            } else {
+                self.check_switch(n);
+            }
+        }
+    }
+
+    pub fn update_inputs(&self, inputs: &mut InputReport) {
+        if let Some(k) = inputs.keys_mut() {
+            k.set_all(&self.pressed_pedals);
+        }
+        if let Some(a) = inputs.axes_mut() {
+            a.axes[..PEDAL_COUNT].copy_from_slice(&self.positions);
+        }
+    }
+}
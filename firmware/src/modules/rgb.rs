@@ -1,7 +1,10 @@
 //! RGB LEDs under the keys
 
 use embedded_hal::timer::CountDown as _;
-use jukebox_util::color::hsv2rgb;
+use jukebox_util::{
+    color::{blend, gamma, hsv2rgb},
+    peripheral::RgbConfig,
+};
 use rp_pico::{
     hal::{
         fugit::ExtU32,
@@ -11,41 +14,76 @@ use rp_pico::{
     },
     pac::PIO0,
 };
-use smart_leds::brightness;
 use smart_leds_trait::{SmartLedsWrite, RGB8};
 use ws2812_pio::Ws2812;
 
-const RGB_LEN: usize = 12;
+// one led under each key, in the same order as the keys
+pub const MAX_RGB_LEN: usize = 16;
+pub const DEFAULT_RGB_LEN: usize = 12;
 const FRAME_TIME: u32 = 33;
+const DIAGNOSTICS_STEP_TIME: u64 = 250_000; // in microseconds
+
+// rough draw of a WS2812, for each channel at full duty and for the led's own logic
+const LED_CHANNEL_MA: u32 = 20;
+const LED_IDLE_MA: u32 = 1;
 
 pub struct RgbMod<'timer> {
     ws: Ws2812<PIO0, SM0, CountDown<'timer>, Pin<DynPinId, FunctionPio0, PullDown>>,
-    brightness: u8,
-    buffer: [RGB8; RGB_LEN],
+    config: RgbConfig,
+    buffer: [RGB8; MAX_RGB_LEN],
+    len: usize,
     timer: CountDown<'timer>,
 }
 
 impl<'timer> RgbMod<'timer> {
     pub fn new(
         ws: Ws2812<PIO0, SM0, CountDown<'timer>, Pin<DynPinId, FunctionPio0, PullDown>>,
+        len: usize,
         mut count_down: CountDown<'timer>,
     ) -> Self {
         count_down.start(FRAME_TIME.millis());
 
         RgbMod {
             ws: ws,
-            brightness: 40,
-            buffer: [(0, 0, 0).into(); RGB_LEN],
+            config: RgbConfig::default(),
+            buffer: [(0, 0, 0).into(); MAX_RGB_LEN],
+            len: len.min(MAX_RGB_LEN),
             timer: count_down,
         }
     }
 
+    pub fn set_config(&mut self, config: RgbConfig) {
+        self.config = config;
+    }
+
+    // brightness and gamma first, then everything is dimmed together if the frame would draw
+    // more than the current limit
+    fn write(&mut self) {
+        let mut out = [RGB8::default(); MAX_RGB_LEN];
+        let mut duty = 0;
+        for (o, c) in out.iter_mut().zip(&self.buffer[..self.len]) {
+            let (r, g, b) = gamma(blend((0, 0, 0), (c.r, c.g, c.b), self.config.brightness));
+            duty += r as u32 + g as u32 + b as u32;
+            *o = (r, g, b).into();
+        }
+
+        // the idle draw is there whatever, so the channels share what's left of the limit
+        let draw = duty * LED_CHANNEL_MA / 255;
+        let limit = self.config.current_limit as u32;
+        let budget = limit.saturating_sub(self.len as u32 * LED_IDLE_MA);
+        if limit > 0 && draw > budget {
+            for o in out[..self.len].iter_mut() {
+                let scale = |v: u8| (v as u32 * budget / draw) as u8;
+                *o = (scale(o.r), scale(o.g), scale(o.b)).into();
+            }
+        }
+
+        self.ws.write(out[..self.len].iter().copied()).unwrap();
+    }
+
     pub fn clear(&mut self) {
-        self.brightness = 0;
-        self.buffer = [(0, 0, 0).into(); RGB_LEN];
-        self.ws
-            .write(brightness(self.buffer.iter().copied(), self.brightness))
-            .unwrap();
+        self.buffer = [(0, 0, 0).into(); MAX_RGB_LEN];
+        self.write();
     }
 
     pub fn update(&mut self, t: Instant) {
@@ -55,12 +93,31 @@ impl<'timer> RgbMod<'timer> {
 
         let t = ((t.duration_since_epoch().ticks() >> 14) % 360) as f32;
 
+        for (i, led) in self.buffer[..self.len].iter_mut().enumerate() {
+            *led = hsv2rgb((t + (10 * (self.len - i)) as f32) % 360.0, 1.0, 1.0).into();
+        }
+
+        self.write();
+    }
+
+    pub fn update_diagnostics(&mut self, t: Instant) {
+        if !self.timer.wait().is_ok() {
+            return;
+        }
+
+        // light one led at a time, stepping it through red, green, then blue
+        let step = (t.duration_since_epoch().to_micros() / DIAGNOSTICS_STEP_TIME) as usize;
+        let lit = (step / 3) % self.len;
+        let color = match step % 3 {
+            0 => (255, 0, 0),
+            1 => (0, 255, 0),
+            _ => (0, 0, 255),
+        };
+
         for (i, led) in self.buffer.iter_mut().enumerate() {
-            *led = hsv2rgb((t + (10 * (RGB_LEN - i)) as f32) % 360.0, 1.0, 1.0).into();
+            *led = if i == lit { color } else { (0, 0, 0) }.into();
         }
 
-        self.ws
-            .write(brightness(self.buffer.iter().copied(), self.brightness))
-            .unwrap();
+        self.write();
     }
 }
@@ -3,38 +3,291 @@
 #[allow(unused_imports)]
 use defmt::*;
 
+use embedded_graphics::{
+    pixelcolor::{raw::RawU16, Rgb565},
+    prelude::*,
+    primitives::Rectangle,
+};
 use embedded_hal::timer::CountDown as _;
-use jukebox_util::color::{hsv2rgb, rgb565};
+use jukebox_util::{
+    animation::{FrameOp, FrameOps, ANIM_CHUNK_END, ANIM_CHUNK_START},
+    color::{hsv2rgb, rgb565},
+    graphics::{
+        draw_checkerboard, draw_color_bars, draw_gradients, draw_key_labels, draw_text, TextStyle,
+    },
+    peripheral::{KeyLabels, ScreenOrientation, ScreenPowerConfig, ScreenText},
+    protocol::CMD_PAYLOAD_MAX_SIZE,
+};
 use rp_pico::{
     hal::{
+        dma::{Channel, CH0},
         fugit::ExtU32,
         gpio::{DynPinId, FunctionPio1, Pin, PullDown},
         pio::SM1,
+        pwm::{Channel as PwmChannel, FreeRunning, Pwm0, Slice, A},
         timer::{CountDown, Instant},
-        Timer,
     },
     pac::PIO1,
 };
 
-use crate::st7789::St7789;
+use crate::st7789::{Rect, St7789};
 
 const REFRESH_RATE: u32 = 50;
+const DIAGNOSTICS_PATTERN_TIME: u64 = 1_000_000; // in microseconds
+const TEXT_QUEUE_LEN: usize = 4;
+
+type Screen<'timer> = St7789<
+    'timer,
+    PIO1,
+    SM1,
+    Pin<DynPinId, FunctionPio1, PullDown>,
+    Channel<CH0>,
+    PwmChannel<Slice<Pwm0, FreeRunning>, A>,
+>;
+
+/// Texts from the host waiting to be drawn. Core 1 takes the whole queue each tick, so it only
+/// fills up if the host sends faster than that.
+#[derive(Clone, Copy)]
+pub struct TextQueue {
+    texts: [ScreenText; TEXT_QUEUE_LEN],
+    len: usize,
+}
+impl TextQueue {
+    pub const fn new() -> Self {
+        TextQueue {
+            texts: [ScreenText::clear(); TEXT_QUEUE_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, text: ScreenText) -> Result<(), ()> {
+        if self.len == TEXT_QUEUE_LEN {
+            return Err(());
+        }
+        self.texts[self.len] = text;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn take(&mut self) -> Self {
+        let q = *self;
+        self.len = 0;
+        q
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ScreenText> {
+        self.texts[..self.len].iter()
+    }
+}
+
+/// A chunk of an animation frame from the host, handed from core 0 to core 1. Core 0 only fills
+/// it once core 1 has emptied it, so the host has to wait and send it again when it's busy.
+#[derive(Clone, Copy)]
+pub struct AnimationChunk {
+    data: [u8; CMD_PAYLOAD_MAX_SIZE],
+    len: usize,
+}
+impl AnimationChunk {
+    pub const fn new() -> Self {
+        AnimationChunk {
+            data: [0u8; CMD_PAYLOAD_MAX_SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn fill(&mut self, b: &[u8]) -> Result<(), ()> {
+        if !self.is_empty() || b.is_empty() || b.len() > self.data.len() {
+            return Err(());
+        }
+        self.data[..b.len()].copy_from_slice(b);
+        self.len = b.len();
+        Ok(())
+    }
+
+    pub fn take(&mut self) -> Self {
+        let c = *self;
+        self.len = 0;
+        c
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum PowerState {
+    Awake,
+    Dimmed,
+    Asleep,
+}
+
+// Reads the low half of the 1MHz timer, which is plenty for timing a frame. It's read straight from
+// the peripheral so the screen doesn't need a hold of the timer just for this.
+#[cfg(feature = "screen-timing")]
+fn timing_micros() -> u32 {
+    let timer = unsafe { &*rp_pico::pac::TIMER::ptr() };
+    timer.timerawl().read().bits()
+}
 
 pub struct ScreenMod<'timer> {
-    st: St7789<'timer, PIO1, SM1, Pin<DynPinId, FunctionPio1, PullDown>>,
+    st: Screen<'timer>,
     timer: CountDown<'timer>,
+    power: ScreenPowerConfig,
+    power_state: PowerState,
+    last_activity: Instant,
+    showing_host: bool, // texts, key labels, or an animation from the host are up instead
+    anim_cursor: usize, // where the next op of the frame being streamed goes
+    anim_rows: Option<(usize, usize)>, // the rows it has changed so far, top and bottom
 }
 
 impl<'timer> ScreenMod<'timer> {
-    pub fn new(
-        st: St7789<'timer, PIO1, SM1, Pin<DynPinId, FunctionPio1, PullDown>>,
-        mut count_down: CountDown<'timer>,
-    ) -> Self {
+    pub fn new(st: Screen<'timer>, mut count_down: CountDown<'timer>) -> Self {
         count_down.start(REFRESH_RATE.millis());
 
-        ScreenMod {
+        let mut s = ScreenMod {
             st: st,
             timer: count_down,
+            power: ScreenPowerConfig::default(),
+            power_state: PowerState::Awake,
+            last_activity: Instant::from_ticks(0),
+            showing_host: false,
+            anim_cursor: 0,
+            anim_rows: None,
+        };
+        s.set_backlight();
+        s
+    }
+
+    fn set_backlight(&mut self) {
+        match self.power_state {
+            PowerState::Awake => self.st.set_backlight(self.power.brightness),
+            PowerState::Dimmed => self.st.set_backlight(self.power.dim_brightness),
+            PowerState::Asleep => {}
+        }
+    }
+
+    pub fn set_power_config(&mut self, config: ScreenPowerConfig) {
+        if config != self.power {
+            self.power = config;
+            self.set_backlight();
+        }
+    }
+
+    /// Dims and sleeps the screen once nothing has happened for a while. Activity wakes it
+    /// straight back up, unless usb is suspended, which keeps it asleep.
+    pub fn update_power(&mut self, t: Instant, activity: bool, suspended: bool) {
+        if activity {
+            self.last_activity = t;
+        }
+
+        let idle = (t - self.last_activity).to_secs();
+        let timed_out = |timeout: u16| timeout != 0 && idle >= timeout as u64;
+        let state = if suspended || timed_out(self.power.sleep_timeout) {
+            PowerState::Asleep
+        } else if timed_out(self.power.dim_timeout) {
+            PowerState::Dimmed
+        } else {
+            PowerState::Awake
+        };
+
+        if state == self.power_state {
+            return;
+        }
+
+        if self.power_state == PowerState::Asleep {
+            self.st.exit_sleep();
+        }
+        self.power_state = state;
+        match state {
+            PowerState::Asleep => self.st.enter_sleep(),
+            _ => self.set_backlight(),
+        }
+    }
+
+    pub fn set_orientation(&mut self, orientation: ScreenOrientation) {
+        self.st.set_orientation(orientation);
+    }
+
+    /// Draws a text from the host. The first one replaces the animation with a blank screen,
+    /// and later ones draw over it until a clear text brings the animation back.
+    pub fn show_text(&mut self, text: &ScreenText) {
+        if text.is_clear() {
+            self.showing_host = false;
+            return;
+        }
+        if !self.showing_host {
+            self.showing_host = true;
+            self.st.clear_framebuffer();
+        }
+
+        let (font, scale) = text.size.font();
+        let style = TextStyle {
+            color: Rgb565::from(RawU16::new(text.color)),
+            background: text.background.map(|c| Rgb565::from(RawU16::new(c))),
+            scale: scale,
+            align: text.align,
+            wrap: text.wrap,
+        };
+        let area = Rectangle::new(
+            Point::new(text.x as i32, text.y as i32),
+            Size::new(text.w as u32, text.h as u32),
+        );
+
+        // drawing to the screen can't fail
+        let _ = draw_text(&mut self.st, &font, text.text(), &area, &style);
+    }
+
+    /// Draws what each key does over the whole screen, texts can still be drawn on top of it.
+    pub fn show_key_labels(&mut self, labels: &KeyLabels) {
+        self.showing_host = !labels.is_clear();
+        if self.showing_host {
+            // drawing to the screen can't fail
+            let _ = draw_key_labels(&mut self.st, labels);
+        }
+    }
+
+    /// Decodes a chunk of an animation frame straight into the framebuffer. The rows it changed
+    /// are sent once the frame's last chunk is in.
+    pub fn play_animation_chunk(&mut self, chunk: &[u8]) {
+        let Some((&flags, ops)) = chunk.split_first() else {
+            return;
+        };
+        if flags & ANIM_CHUNK_START != 0 {
+            self.anim_cursor = 0;
+        }
+        self.showing_host = true;
+
+        let w = self.st.width();
+        for op in FrameOps::new(ops) {
+            // a bad op leaves the cursor somewhere unknown, wait for the next frame
+            let Ok(op) = op else {
+                break;
+            };
+
+            let start = self.anim_cursor;
+            self.anim_cursor += op.count();
+            match op {
+                FrameOp::Skip(_) => continue,
+                FrameOp::Run(n, c) => self.st.write_span(start, core::iter::repeat(c).take(n)),
+                FrameOp::Literal(_) => self.st.write_span(start, op.colors()),
+            }
+
+            let (top, bottom) = (start / w, (self.anim_cursor - 1) / w);
+            self.anim_rows = Some(match self.anim_rows {
+                Some((t, b)) => (t.min(top), b.max(bottom)),
+                None => (top, bottom),
+            });
+        }
+
+        if flags & ANIM_CHUNK_END != 0 {
+            if let Some((top, bottom)) = self.anim_rows.take() {
+                self.st.mark_dirty(Rect::new(0, top, w, bottom - top + 1));
+            }
         }
     }
 
@@ -42,10 +295,22 @@ impl<'timer> ScreenMod<'timer> {
         self.st.backlight_off();
         self.st.clear_framebuffer();
         self.st.push_framebuffer();
+        self.st.wait_transfer();
     }
 
-    pub fn update(&mut self, t: Instant, _timer: &Timer) {
-        if !self.timer.wait().is_ok() {
+    pub fn update(&mut self, t: Instant) {
+        // no point drawing to a panel nobody can see
+        if self.power_state == PowerState::Asleep {
+            return;
+        }
+        // the last frame is still going out, try again on the next poll
+        if self.st.is_busy() || !self.timer.wait().is_ok() {
+            return;
+        }
+
+        // texts only change when the host sends new ones, just send what they touched
+        if self.showing_host {
+            self.st.push_framebuffer();
             return;
         }
 
@@ -53,14 +318,42 @@ impl<'timer> ScreenMod<'timer> {
         let rgb = hsv2rgb(t, 1.0, 1.0);
         let rgb = rgb565(rgb.0, rgb.1, rgb.2);
 
-        // let time_start = _timer.get_counter();
+        #[cfg(feature = "screen-timing")]
+        let fill_start = timing_micros();
         self.st.fill_framebuffer(rgb);
-        // let elapse1 = (_timer.get_counter() - time_start).to_micros();
-
-        // let time_start = _timer.get_counter();
+        #[cfg(feature = "screen-timing")]
+        let push_start = timing_micros();
         self.st.push_framebuffer();
-        // let elapse2 = (_timer.get_counter() - time_start).to_micros();
 
-        // info!("times: fill-fb={}us, write-fb={}us", elapse1, elapse2);
+        // the push only starts the dma, so wait on it here to time the whole frame
+        #[cfg(feature = "screen-timing")]
+        {
+            let send_start = timing_micros();
+            self.st.wait_transfer();
+            let send_end = timing_micros();
+            info!(
+                "times: fill-fb={}us, start-fb={}us, send-fb={}us",
+                push_start.wrapping_sub(fill_start),
+                send_start.wrapping_sub(push_start),
+                send_end.wrapping_sub(send_start)
+            );
+        }
+    }
+
+    pub fn update_diagnostics(&mut self, t: Instant) {
+        // the test patterns draw over any texts, go back to the animation once it's done
+        self.showing_host = false;
+        if self.st.is_busy() || !self.timer.wait().is_ok() {
+            return;
+        }
+
+        // drawing to the screen can't fail
+        let _ = match (t.duration_since_epoch().to_micros() / DIAGNOSTICS_PATTERN_TIME) % 3 {
+            0 => draw_color_bars(&mut self.st),
+            1 => draw_gradients(&mut self.st),
+            _ => draw_checkerboard(&mut self.st),
+        };
+
+        self.st.push_framebuffer();
     }
 }
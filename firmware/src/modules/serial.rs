@@ -6,28 +6,64 @@ use defmt::*;
 use embedded_hal::timer::{Cancel as _, CountDown as _};
 use itertools::Itertools;
 use jukebox_util::{
+    diagnostics::{DiagnosticsReport, DiagnosticsTest},
+    hid::HidSequence,
     peripheral::{
-        Connection, JBInputs, IDENT_KEY_INPUT, IDENT_KNOB_INPUT, IDENT_PEDAL_INPUT,
-        IDENT_UNKNOWN_INPUT,
+        Connection, DeviceSettings, DeviceType, InputReport, KeyLabels, PedalConfig, RgbConfig,
+        ScreenOrientation, ScreenPowerConfig, ScreenText,
     },
     protocol::{
-        Command, CMD_END, RSP_DISCONNECTED, RSP_END, RSP_INPUT_HEADER, RSP_LINK_DELIMITER,
-        RSP_LINK_HEADER, RSP_UNKNOWN,
+        decode_payload_len, Command, ResetReason, CMD_END, CMD_PAYLOAD_HEADER_SIZE,
+        CMD_PAYLOAD_MAX_SIZE, RSP_ANIMATION_FRAME_HEADER, RSP_DEVICE_SETTINGS_HEADER,
+        RSP_DIAGNOSTICS_HEADER, RSP_DISCONNECTED, RSP_END, RSP_HID_SEQUENCE_HEADER,
+        RSP_INPUT_HEADER, RSP_KEY_LABELS_HEADER, RSP_LINK_DELIMITER, RSP_LINK_HEADER,
+        RSP_PEDAL_CONFIG_HEADER, RSP_PERIPHERALS_HEADER, RSP_RGB_CONFIG_HEADER,
+        RSP_SCREEN_ORIENTATION_HEADER, RSP_SCREEN_POWER_HEADER, RSP_SCREEN_TEXT_HEADER,
+        RSP_UNKNOWN,
     },
 };
 use ringbuffer::{ConstGenericRingBuffer, RingBuffer};
 use rp_pico::hal::{fugit::ExtU32, timer::CountDown, usb::UsbBus};
 use usbd_serial::SerialPort;
 
+use crate::modules::hid::HidQueue;
+use crate::modules::screen::{AnimationChunk, TextQueue};
 use crate::mutex::Mutex;
-use crate::peripheral::{inputs_default, inputs_write_report};
+use crate::peripheral::{inputs_clear_latched, inputs_write_report, peripherals_descriptor};
 
 const BUFFER_SIZE: usize = 2048;
 
 const KEEPALIVE: u32 = 250;
+// tries at writing a response before giving up on it, well inside the watchdog timeout
+const SEND_ATTEMPTS: u32 = 1000;
+
+/// What the serial module reads and writes on behalf of the rest of the firmware: the device's
+/// details, and the state it hands over to core 1 and the hid module.
+#[derive(Clone, Copy)]
+pub struct SerialShared<'a> {
+    pub firmware_version: &'a str,
+    pub device_uid: &'a str,
+    pub reset_reason: ResetReason,
+    pub device_type: DeviceType,
+    pub key_rows: usize,
+    pub peripheral_inputs: &'a Mutex<1, InputReport>,
+    pub update_trigger: &'a Mutex<2, bool>,
+    pub diagnostics: &'a Mutex<4, DiagnosticsReport>,
+    pub pedal_config: &'a Mutex<5, PedalConfig>,
+    pub screen_orientation: &'a Mutex<6, ScreenOrientation>,
+    pub screen_power: &'a Mutex<7, ScreenPowerConfig>,
+    pub screen_text: &'a Mutex<9, TextQueue>,
+    pub key_labels: &'a Mutex<10, Option<KeyLabels>>,
+    pub animation_chunk: &'a Mutex<11, AnimationChunk>,
+    pub rgb_config: &'a Mutex<12, RgbConfig>,
+    pub hid_queue: &'a Mutex<14, HidQueue>,
+    pub device_settings: &'a Mutex<15, Option<DeviceSettings>>,
+}
 
 pub struct SerialMod<'timer> {
     buffer: ConstGenericRingBuffer<u8, BUFFER_SIZE>,
+    payload: [u8; CMD_PAYLOAD_MAX_SIZE],
+    payload_len: usize,
     state: Connection,
     keepalive_timer: CountDown<'timer>,
 }
@@ -38,12 +74,31 @@ impl<'timer> SerialMod<'timer> {
 
         SerialMod {
             buffer: ConstGenericRingBuffer::new(),
+            payload: [0u8; CMD_PAYLOAD_MAX_SIZE],
+            payload_len: 0,
             state: Connection::NotConnected(true),
             keepalive_timer: timer,
         }
     }
 
     fn check_cmd(&mut self) -> Option<usize> {
+        // commands with a payload tell us their length up front, so we wait for the whole frame
+        if Command::decode(*self.buffer.get(0)?).has_payload() {
+            let w1 = self.buffer.get(1)?;
+            let w2 = self.buffer.get(2)?;
+            let len = decode_payload_len(*w1, *w2);
+            if len > CMD_PAYLOAD_MAX_SIZE {
+                // bogus length, throw out the header and let decode_cmd reject it
+                return Some(CMD_PAYLOAD_HEADER_SIZE);
+            }
+
+            let size = CMD_PAYLOAD_HEADER_SIZE + len + CMD_END.len();
+            if self.buffer.len() < size {
+                return None;
+            }
+            return Some(size);
+        }
+
         // we measure out a command token by looking for the end-of-command string: "\r\n"
         // if one is not found, we do not have a valid command ready to be read
         // TODO: better match the characters in CMD_END
@@ -57,17 +112,25 @@ impl<'timer> SerialMod<'timer> {
     }
 
     fn send(serial: &mut SerialPort<UsbBus>, rsp: &[u8]) {
-        // TODO: its possible for write to drop some characters, if we're not careful.
-        // we should probably handle that before we take on larger communications.
-        while let Err(_) = serial.write(rsp) {
-            let _ = serial.flush();
-            cortex_m::asm::nop();
+        // write may take only part of the response, keep going until it's all out. a host that
+        // has stopped reading would have us spin here until the watchdog fires, so after enough
+        // tries the rest is dropped and the host times out instead
+        let mut rsp = rsp;
+        let mut attempts = 0;
+        while !rsp.is_empty() {
+            match serial.write(rsp) {
+                Ok(n) if n > 0 => rsp = &rsp[n..],
+                _ => {
+                    attempts += 1;
+                    if attempts == SEND_ATTEMPTS {
+                        warn!("Host isn't reading, dropped {} bytes", rsp.len());
+                        return;
+                    }
+                    let _ = serial.flush();
+                    cortex_m::asm::nop();
+                }
+            }
         }
-
-        // match serial.write(rsp) {
-        //     Err(_) => core::todo!(),
-        //     Ok(_) => {}
-        // };
     }
 
     fn send_full_response(serial: &mut SerialPort<UsbBus>, rsp: &[u8]) {
@@ -79,7 +142,49 @@ impl<'timer> SerialMod<'timer> {
         Self::send(serial, RSP_END);
     }
 
+    // binary data goes out with its length ahead of it, so the host never mistakes it for RSP_END
+    fn send_sized_response(serial: &mut SerialPort<UsbBus>, header: u8, data: &[u8]) {
+        Self::send(serial, &[header]);
+        Self::send(serial, &(data.len() as u16).to_be_bytes());
+        Self::send(serial, data);
+        Self::send_end_response(serial);
+    }
+
+    fn decode_payload_cmd(&mut self, cmd: Command, size: usize) -> Command {
+        let w1 = self.buffer.get(1).unwrap_or(&b'\0');
+        let w2 = self.buffer.get(2).unwrap_or(&b'\0');
+        let len = decode_payload_len(*w1, *w2);
+
+        debug!("cmd: {} (payload:{}, size:{})", cmd as u8, len, size);
+
+        let valid = len <= CMD_PAYLOAD_MAX_SIZE
+            && size == CMD_PAYLOAD_HEADER_SIZE + len + CMD_END.len()
+            && self.buffer.get(size - 2) == Some(&CMD_END[0])
+            && self.buffer.get(size - 1) == Some(&CMD_END[1]);
+
+        // keep the payload only if the whole frame checks out
+        for i in 0..size {
+            let b = self.buffer.dequeue().unwrap_or(b'\0');
+            let p = i.wrapping_sub(CMD_PAYLOAD_HEADER_SIZE);
+            if valid && p < len {
+                self.payload[p] = b;
+            }
+        }
+        self.payload_len = if valid { len } else { 0 };
+
+        if !valid {
+            return Command::Unknown;
+        }
+
+        cmd
+    }
+
     fn decode_cmd(&mut self, size: usize) -> Command {
+        let cmd = Command::decode(*self.buffer.get(0).unwrap_or(&b'\0'));
+        if cmd.has_payload() {
+            return self.decode_payload_cmd(cmd, size);
+        }
+
         if size > 4 {
             return Command::Unknown;
         }
@@ -90,8 +195,6 @@ impl<'timer> SerialMod<'timer> {
 
         debug!("cmd: {} {} {} (size:{})", w1, w2, w3, size);
 
-        let cmd = Command::decode(*w1);
-
         if cmd != Command::Unknown && !(*w2 == CMD_END[0] && *w3 == CMD_END[1]) {
             for _ in 0..size {
                 self.buffer.dequeue();
@@ -107,7 +210,16 @@ impl<'timer> SerialMod<'timer> {
         cmd
     }
 
-    #[allow(dead_code)]
+    /// Drops the link when usb suspends, so the host starts again with a greeting once it wakes.
+    pub fn suspend(&mut self) {
+        if self.state == Connection::Connected {
+            info!("Serial suspended");
+        }
+        self.state = Connection::NotConnected(true);
+        self.buffer.clear();
+        self.payload_len = 0;
+    }
+
     pub fn get_connection_status(&self) -> Connection {
         self.state.clone()
     }
@@ -119,14 +231,27 @@ impl<'timer> SerialMod<'timer> {
         update_trigger.with_mut_lock(|u| *u = true);
     }
 
-    pub fn update(
-        &mut self,
-        serial: &mut SerialPort<UsbBus>,
-        firmware_version: &str,
-        device_uid: &str,
-        peripheral_inputs: &Mutex<1, JBInputs>,
-        update_trigger: &Mutex<2, bool>,
-    ) {
+    pub fn update(&mut self, serial: &mut SerialPort<UsbBus>, shared: &SerialShared) {
+        let SerialShared {
+            firmware_version,
+            device_uid,
+            reset_reason,
+            device_type,
+            key_rows,
+            peripheral_inputs,
+            update_trigger,
+            diagnostics,
+            pedal_config,
+            screen_orientation,
+            screen_power,
+            screen_text,
+            key_labels,
+            animation_chunk,
+            rgb_config,
+            hid_queue,
+            device_settings,
+        } = *shared;
+
         if self.state == Connection::Connected && self.keepalive_timer.wait().is_ok() {
             warn!("Keepalive triggered, disconnecting.");
             self.state = Connection::NotConnected(false);
@@ -163,21 +288,14 @@ impl<'timer> SerialMod<'timer> {
                 }
                 Command::Greeting => {
                     Self::send(serial, &[RSP_LINK_HEADER, RSP_LINK_DELIMITER]);
-                    let dtype = if cfg!(feature = "keypad") {
-                        IDENT_KEY_INPUT
-                    } else if cfg!(feature = "knobpad") {
-                        IDENT_KNOB_INPUT
-                    } else if cfg!(feature = "pedalpad") {
-                        IDENT_PEDAL_INPUT
-                    } else {
-                        IDENT_UNKNOWN_INPUT
-                    };
-                    Self::send(serial, &[dtype]);
+                    Self::send(serial, &[device_type.ident()]);
                     Self::send(serial, &[RSP_LINK_DELIMITER]);
                     Self::send(serial, firmware_version.as_bytes());
                     Self::send(serial, &[RSP_LINK_DELIMITER]);
                     Self::send(serial, device_uid.as_bytes());
                     Self::send(serial, &[RSP_LINK_DELIMITER]);
+                    Self::send(serial, &[reset_reason.encode()]);
+                    Self::send(serial, &[RSP_LINK_DELIMITER]);
                     Self::send_end_response(serial);
 
                     self.state = Connection::Connected;
@@ -194,9 +312,10 @@ impl<'timer> SerialMod<'timer> {
                 Command::GetInputKeys => {
                     // copy peripherals and inputs out
                     let inputs = {
-                        let mut inputs = inputs_default(); // JBInputs::default();
-                        peripheral_inputs.with_lock(|i| {
+                        let mut inputs = InputReport::new();
+                        peripheral_inputs.with_mut_lock(|i| {
                             inputs = *i;
+                            inputs_clear_latched(i);
                         });
                         inputs
                     };
@@ -208,6 +327,227 @@ impl<'timer> SerialMod<'timer> {
 
                     true
                 }
+                Command::Diagnostics => {
+                    let test = match self.payload_len {
+                        1 => DiagnosticsTest::decode(self.payload[0]),
+                        _ => DiagnosticsTest::Unknown,
+                    };
+
+                    match test {
+                        DiagnosticsTest::Unknown => unknown(),
+                        test => {
+                            info!("Command Diagnostics: {}", test.encode());
+                            let mut report = DiagnosticsReport::default();
+                            diagnostics.with_mut_lock(|d| {
+                                if test == DiagnosticsTest::Keys {
+                                    // anything already held is a candidate for being stuck
+                                    d.stuck = d.pressed;
+                                }
+                                d.test = test;
+                                report = *d;
+                            });
+
+                            Self::send_sized_response(
+                                serial,
+                                RSP_DIAGNOSTICS_HEADER,
+                                &report.encode(),
+                            );
+
+                            true
+                        }
+                    }
+                }
+                Command::GetPeripherals => {
+                    let descriptor = peripherals_descriptor(device_type, key_rows).encode();
+
+                    Self::send_sized_response(serial, RSP_PERIPHERALS_HEADER, &descriptor);
+
+                    true
+                }
+                Command::PedalConfig => {
+                    let config = match device_type {
+                        DeviceType::PedalPad => {
+                            PedalConfig::decode(&self.payload[..self.payload_len])
+                        }
+                        _ => Err(()),
+                    };
+
+                    match config {
+                        Ok(config) => {
+                            info!("Command PedalConfig");
+                            pedal_config.with_mut_lock(|c| *c = config);
+
+                            Self::send_sized_response(
+                                serial,
+                                RSP_PEDAL_CONFIG_HEADER,
+                                &config.encode(),
+                            );
+
+                            true
+                        }
+                        Err(_) => unknown(),
+                    }
+                }
+                Command::ScreenOrientation => {
+                    let orientation = match device_type {
+                        DeviceType::KeyPad => {
+                            ScreenOrientation::decode(&self.payload[..self.payload_len])
+                        }
+                        _ => Err(()),
+                    };
+
+                    match orientation {
+                        Ok(orientation) => {
+                            info!("Command ScreenOrientation");
+                            screen_orientation.with_mut_lock(|o| *o = orientation);
+
+                            Self::send_sized_response(
+                                serial,
+                                RSP_SCREEN_ORIENTATION_HEADER,
+                                &orientation.encode(),
+                            );
+
+                            true
+                        }
+                        Err(_) => unknown(),
+                    }
+                }
+                Command::ScreenPower => {
+                    let config = match device_type {
+                        DeviceType::KeyPad => {
+                            ScreenPowerConfig::decode(&self.payload[..self.payload_len])
+                        }
+                        _ => Err(()),
+                    };
+
+                    match config {
+                        Ok(config) => {
+                            info!("Command ScreenPower");
+                            screen_power.with_mut_lock(|c| *c = config);
+
+                            Self::send_sized_response(
+                                serial,
+                                RSP_SCREEN_POWER_HEADER,
+                                &config.encode(),
+                            );
+
+                            true
+                        }
+                        Err(_) => unknown(),
+                    }
+                }
+                Command::ScreenText => {
+                    let text = match device_type {
+                        DeviceType::KeyPad => ScreenText::decode(&self.payload[..self.payload_len]),
+                        _ => Err(()),
+                    };
+
+                    // a full queue is refused, so the host knows the text wasn't shown
+                    let mut queued = Err(());
+                    if let Ok(text) = text {
+                        screen_text.with_mut_lock(|q| queued = q.push(text));
+                    }
+
+                    match queued {
+                        Ok(_) => {
+                            info!("Command ScreenText");
+                            Self::send_full_response(serial, &[RSP_SCREEN_TEXT_HEADER]);
+
+                            true
+                        }
+                        Err(_) => unknown(),
+                    }
+                }
+                Command::KeyLabels => {
+                    let labels = match device_type {
+                        DeviceType::KeyPad => KeyLabels::decode(&self.payload[..self.payload_len]),
+                        _ => Err(()),
+                    };
+
+                    match labels {
+                        Ok(labels) => {
+                            info!("Command KeyLabels");
+                            // only the newest labels matter, they replace the whole screen
+                            key_labels.with_mut_lock(|l| *l = Some(labels));
+                            Self::send_full_response(serial, &[RSP_KEY_LABELS_HEADER]);
+
+                            true
+                        }
+                        Err(_) => unknown(),
+                    }
+                }
+                Command::AnimationFrame => match device_type {
+                    DeviceType::KeyPad => {
+                        // core 1 is still drawing the last chunk, the host sends this one again
+                        let mut accepted = false;
+                        animation_chunk.with_mut_lock(|c| {
+                            accepted = c.fill(&self.payload[..self.payload_len]).is_ok()
+                        });
+
+                        Self::send(serial, &[RSP_ANIMATION_FRAME_HEADER, accepted as u8]);
+                        Self::send_end_response(serial);
+
+                        true
+                    }
+                    _ => unknown(),
+                },
+                Command::RgbConfig => {
+                    // every board has the leds
+                    match RgbConfig::decode(&self.payload[..self.payload_len]) {
+                        Ok(config) => {
+                            info!("Command RgbConfig");
+                            rgb_config.with_mut_lock(|c| *c = config);
+
+                            Self::send_sized_response(
+                                serial,
+                                RSP_RGB_CONFIG_HEADER,
+                                &config.encode(),
+                            );
+
+                            true
+                        }
+                        Err(_) => unknown(),
+                    }
+                }
+                Command::HidSequence => {
+                    match HidSequence::decode(&self.payload[..self.payload_len]) {
+                        Ok(sequence) => {
+                            info!("Command HidSequence");
+                            // a full queue is refused, the host sends it again later
+                            let mut accepted = false;
+                            hid_queue.with_mut_lock(|q| accepted = q.push(sequence).is_ok());
+
+                            Self::send(serial, &[RSP_HID_SEQUENCE_HEADER, accepted as u8]);
+                            Self::send_end_response(serial);
+
+                            true
+                        }
+                        Err(_) => unknown(),
+                    }
+                }
+                Command::DeviceSettings => {
+                    match DeviceSettings::decode(&self.payload[..self.payload_len]) {
+                        Ok(settings) => {
+                            info!(
+                                "Command DeviceSettings: {}, key rows: {}",
+                                settings.device_type.ident(),
+                                settings.key_rows
+                            );
+                            // the main loop saves them once this has gone out, then restarts
+                            device_settings.with_mut_lock(|s| *s = Some(settings));
+
+                            Self::send_sized_response(
+                                serial,
+                                RSP_DEVICE_SETTINGS_HEADER,
+                                &settings.encode(),
+                            );
+                            self.state = Connection::NotConnected(true);
+
+                            true
+                        }
+                        Err(_) => unknown(),
+                    }
+                }
                 Command::NegativeAck => {
                     // we sent something in error, better bail
                     self.state = Connection::NotConnected(false);
@@ -1,23 +1,115 @@
-use jukebox_util::peripheral::{JBInputs, KeyInputs, KnobInputs, PedalInputs};
+use jukebox_util::peripheral::{
+    AxisBlock, DeviceType, EncoderBlock, InputBlock, InputReport, KeyBlock, PeripheralDescriptor,
+    INPUT_REPORT_MAX_SIZE, PEDAL_COUNT,
+};
 use rp_pico::hal::usb::UsbBus;
 use usbd_serial::SerialPort;
 
-pub const fn inputs_default() -> JBInputs {
-    if cfg!(feature = "keypad") {
-        JBInputs::KeyPad(KeyInputs::default())
-    } else if cfg!(feature = "knobpad") {
-        JBInputs::KnobPad(KnobInputs::default())
-    } else if cfg!(feature = "pedalpad") {
-        JBInputs::PedalPad(PedalInputs::default())
-    } else {
-        JBInputs::KeyPad(KeyInputs::default())
+use crate::modules::keyboard::KEY_COLS;
+use crate::modules::knob::KNOB_COUNT;
+use crate::modules::rgb::DEFAULT_RGB_LEN;
+use crate::st7789::{SCR_H, SCR_W};
+
+pub fn rgb_len(device_type: DeviceType, key_rows: usize) -> usize {
+    match device_type {
+        DeviceType::KeyPad => key_rows * KEY_COLS,
+        _ => DEFAULT_RGB_LEN,
     }
 }
 
-pub fn inputs_write_report(inputs: JBInputs, serial: &mut SerialPort<UsbBus>) {
-    let _ = match inputs {
-        JBInputs::KeyPad(i) => serial.write(&i.encode()),
-        JBInputs::KnobPad(i) => serial.write(&i.encode()),
-        JBInputs::PedalPad(i) => serial.write(&i.encode()),
+pub fn peripherals_descriptor(device_type: DeviceType, key_rows: usize) -> PeripheralDescriptor {
+    // every board has the rgb leds, and the settings sector the host writes the board type to
+    let common = PeripheralDescriptor {
+        led_count: rgb_len(device_type, key_rows) as u8,
+        flash_settings: true,
+        ..PeripheralDescriptor::default()
     };
+
+    match device_type {
+        DeviceType::KeyPad => PeripheralDescriptor {
+            key_rows: key_rows as u8,
+            key_cols: KEY_COLS as u8,
+            screen_width: SCR_W as u16,
+            screen_height: SCR_H as u16,
+            ..common
+        },
+        DeviceType::KnobPad => PeripheralDescriptor {
+            knob_count: KNOB_COUNT as u8,
+            ..common
+        },
+        DeviceType::PedalPad => PeripheralDescriptor {
+            pedal_count: PEDAL_COUNT as u8,
+            ..common
+        },
+        DeviceType::Unknown => common,
+    }
+}
+
+pub fn inputs_default(device_type: DeviceType, key_rows: usize) -> InputReport {
+    let blocks: &[InputBlock] = match device_type {
+        DeviceType::KeyPad | DeviceType::Unknown => {
+            &[InputBlock::Keys(KeyBlock::new((key_rows * KEY_COLS) as u8))]
+        }
+        DeviceType::KnobPad => &[InputBlock::Encoders(EncoderBlock::new(KNOB_COUNT as u8))],
+        DeviceType::PedalPad => &[
+            InputBlock::Keys(KeyBlock::new(PEDAL_COUNT as u8)),
+            InputBlock::Axes(AxisBlock::new(PEDAL_COUNT as u8)),
+        ],
+    };
+
+    let mut inputs = InputReport::new();
+    for b in blocks {
+        inputs.push(*b).unwrap();
+    }
+    inputs
+}
+
+pub fn inputs_switch_mask(inputs: &InputReport) -> u16 {
+    // bitmap of every switch that is down, used by diagnostics to find stuck switches
+    let mut switches = [false; 16];
+    let mut n = 0;
+    for block in inputs.iter() {
+        match block {
+            InputBlock::Keys(k) => {
+                for i in 0..k.count as usize {
+                    if let Some(s) = switches.get_mut(n) {
+                        *s = k.is_down(i);
+                    }
+                    n += 1;
+                }
+            }
+            InputBlock::Encoders(e) => {
+                for enc in e.iter() {
+                    if let Some(s) = switches.get_mut(n) {
+                        *s = enc.switch.is_down();
+                    }
+                    n += 1;
+                }
+            }
+            InputBlock::Axes(_) => {}
+        }
+    }
+
+    switches
+        .iter()
+        .enumerate()
+        .fold(0, |m, (n, s)| if *s { m | 1 << n } else { m })
+}
+
+pub fn inputs_clear_latched(inputs: &mut InputReport) {
+    // knob steps are held until the host reads them, after that they've been handled
+    for block in inputs.iter_mut() {
+        if let InputBlock::Encoders(e) = block {
+            for enc in e.iter_mut() {
+                enc.steps = 0;
+            }
+        }
+    }
+}
+
+pub fn inputs_write_report(inputs: InputReport, serial: &mut SerialPort<UsbBus>) {
+    let mut buf = [0u8; INPUT_REPORT_MAX_SIZE];
+    let size = inputs.encode(&mut buf).unwrap_or(0);
+    let _ = serial.write(&(size as u16).to_be_bytes());
+    let _ = serial.write(&buf[..size]);
 }
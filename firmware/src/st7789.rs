@@ -1,53 +1,208 @@
 use core::u32;
 
+use core::convert::Infallible;
+
 use cortex_m::prelude::_embedded_hal_timer_CountDown;
-use embedded_hal::digital::v2::OutputPin as _;
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*, primitives::Rectangle};
+use embedded_hal::{digital::v2::OutputPin as _, PwmPin};
+use jukebox_util::peripheral::{ScreenOrientation, ScreenRotation};
 use rp_pico::hal::{
+    dma::{single_buffer, HalfWord, SingleChannel},
     fugit::{ExtU64, MicrosDurationU64},
     gpio::{AnyPin, DynPinId, FunctionSioOutput, Pin, PullDown},
+    pac,
     pio::{PIOBuilder, PIOExt, StateMachineIndex, Tx, UninitStateMachine, PIO},
     timer::CountDown,
 };
 
-const SCR_W: usize = 240;
-const SCR_H: usize = 320;
-static mut FB: [[u16; SCR_W]; SCR_H] = [[0x00FFu16; SCR_W]; SCR_H];
+pub const SCR_W: usize = 240;
+pub const SCR_H: usize = 320;
+const FB_LEN: usize = SCR_W * SCR_H;
 // The framebuffer is a static so that it does not end up on core1's stack.
+static mut FB: [FbPixel; FB_LEN] = [to_pixel(0x00FF); FB_LEN];
+
+// Pixels go to the PIO a halfword at a time and are shifted out high byte first, the way the panel
+// wants them, so they're stored as plain RGB565.
+#[cfg(not(feature = "palette-framebuffer"))]
+type FbPixel = u16;
+
+#[cfg(not(feature = "palette-framebuffer"))]
+const fn to_pixel(c: u16) -> FbPixel {
+    c
+}
+
+// With the palette framebuffer each pixel is an RGB332 index, which halves the framebuffer at the
+// cost of colour depth. Rows are expanded back to RGB565 in a line buffer as they're sent.
+#[cfg(feature = "palette-framebuffer")]
+type FbPixel = u8;
+
+#[cfg(feature = "palette-framebuffer")]
+const fn to_pixel(c: u16) -> FbPixel {
+    // each channel rounded to the nearest of the palette's levels, rrrgggbb. the levels are spread
+    // evenly enough that scaling and rounding always lands on the closest one
+    let r = ((c >> 11) * 7 + 15) / 31;
+    let g = (((c >> 5) & 0x3F) * 7 + 31) / 63;
+    let b = ((c & 0x1F) * 3 + 15) / 31;
+    (r << 5 | g << 2 | b) as u8
+}
+
+#[cfg(feature = "palette-framebuffer")]
+static PALETTE: [u16; 256] = {
+    let mut palette = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        // repeat the high bits into the low ones so full intensity stays full
+        let (r, g, b) = ((i >> 5) as u16, ((i >> 2) & 0x7) as u16, (i & 0x3) as u16);
+        let r = r << 2 | r >> 1;
+        let g = g << 3 | g;
+        let b = b << 3 | b << 1 | b >> 1;
+        palette[i] = r << 11 | g << 5 | b;
+        i += 1;
+    }
+    palette
+};
+
+// long enough for a row whichever way round the screen is
+#[cfg(feature = "palette-framebuffer")]
+static mut LINE: [u16; SCR_H] = [0; SCR_H];
+
+// regions that fit in the list are sent separately, after that they get merged together
+const MAX_DIRTY: usize = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, w: usize, h: usize) -> Self {
+        Rect { x, y, w, h }
+    }
+
+    fn right(&self) -> usize {
+        self.x + self.w
+    }
 
-pub struct St7789<'timer, P, SM, I>
+    fn bottom(&self) -> usize {
+        self.y + self.h
+    }
+
+    fn is_empty(&self) -> bool {
+        self.w == 0 || self.h == 0
+    }
+
+    fn clip(self, w: usize, h: usize) -> Self {
+        let x = self.x.min(w);
+        let y = self.y.min(h);
+        Rect::new(x, y, self.right().min(w) - x, self.bottom().min(h) - y)
+    }
+
+    fn union(self, other: Rect) -> Self {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(
+            x,
+            y,
+            self.right().max(other.right()) - x,
+            self.bottom().max(other.bottom()) - y,
+        )
+    }
+
+    fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right()
+            && other.x <= self.right()
+            && self.y <= other.bottom()
+            && other.y <= self.bottom()
+    }
+}
+
+// Commands go out a byte at a time and pixels a halfword at a time, the state machine pulls this
+// many bits from each fifo entry.
+const CMD_PULL_BITS: u8 = 8;
+const PIXEL_PULL_BITS: u8 = 16;
+
+type ScreenTx<P, SM> = Tx<(P, SM), HalfWord>;
+type FramebufferTransfer<CH, P, SM> = single_buffer::Transfer<CH, &'static [u16], ScreenTx<P, SM>>;
+
+// Returns how many rows of the region are ready to send, and their pixels.
+#[cfg(not(feature = "palette-framebuffer"))]
+fn region_pixels(r: Rect, width: usize) -> (usize, &'static [u16]) {
+    // full width rows sit back to back in the framebuffer, so they can all go in one transfer
+    let fb = unsafe { &*core::ptr::addr_of!(FB) };
+    let rows = if r.w == width { r.h } else { 1 };
+    let start = r.y * width + r.x;
+    let len = (rows - 1) * width + r.w;
+    (rows, &fb[start..start + len])
+}
+
+#[cfg(feature = "palette-framebuffer")]
+fn region_pixels(r: Rect, width: usize) -> (usize, &'static [u16]) {
+    // only one row is in flight at a time, so the line buffer is free to refill
+    let fb = unsafe { &*core::ptr::addr_of!(FB) };
+    let line = unsafe { &mut *core::ptr::addr_of_mut!(LINE) };
+    let start = r.y * width + r.x;
+    for (l, p) in line.iter_mut().zip(&fb[start..start + r.w]) {
+        *l = PALETTE[*p as usize];
+    }
+    (1, &line[..r.w])
+}
+
+pub struct St7789<'timer, P, SM, I, CH, BL>
 where
     I: AnyPin<Function = P::PinFunction>,
     SM: StateMachineIndex,
     P: PIOExt,
+    CH: SingleChannel,
+    BL: PwmPin<Duty = u16>,
 {
-    tx: Tx<(P, SM)>,
+    // the fifo and dma channel are handed to the transfer while a frame is being sent
+    tx: Option<ScreenTx<P, SM>>,
+    dma_ch: Option<CH>,
+    transfer: Option<FramebufferTransfer<CH, P, SM>>,
+    pull_bits: u8,
+    // regions drawn to since the last push, and the ones still waiting to go out
+    dirty: [Option<Rect>; MAX_DIRTY],
+    queued: [Option<Rect>; MAX_DIRTY],
+    // rows of the region being sent that haven't been handed to the dma yet
+    sending: Option<Rect>,
+    orientation: ScreenOrientation,
+    // drawing size, these swap over when the screen is on its side
+    width: usize,
+    height: usize,
     _data_pin: I,
     _clock_pin: I,
-    backlight_pin: Pin<DynPinId, FunctionSioOutput, PullDown>,
+    backlight: BL,
     dc_pin: Pin<DynPinId, FunctionSioOutput, PullDown>,
     cs_pin: Pin<DynPinId, FunctionSioOutput, PullDown>,
     _rst_pin: Pin<DynPinId, FunctionSioOutput, PullDown>,
     timer: CountDown<'timer>,
 }
 
-impl<'timer, P, SM, I> St7789<'timer, P, SM, I>
+impl<'timer, P, SM, I, CH, BL> St7789<'timer, P, SM, I, CH, BL>
 where
     I: AnyPin<Function = P::PinFunction>,
     P: PIOExt,
     SM: StateMachineIndex,
+    CH: SingleChannel,
+    BL: PwmPin<Duty = u16>,
 {
     pub fn new(
         pio: &mut PIO<P>,
         sm: UninitStateMachine<(P, SM)>,
+        dma_ch: CH,
         data_pin: I,
         clock_pin: I,
         mut cs_pin: Pin<DynPinId, FunctionSioOutput, PullDown>,
         mut dc_pin: Pin<DynPinId, FunctionSioOutput, PullDown>,
         mut rst_pin: Pin<DynPinId, FunctionSioOutput, PullDown>,
-        mut backlight_pin: Pin<DynPinId, FunctionSioOutput, PullDown>,
+        mut backlight: BL,
         timer: CountDown<'timer>,
     ) -> Self {
-        backlight_pin.set_low().unwrap();
+        backlight.set_duty(0);
+        backlight.enable();
         dc_pin.set_low().unwrap();
         cs_pin.set_high().unwrap();
         rst_pin.set_high().unwrap();
@@ -73,7 +228,7 @@ where
             .buffers(rp_pico::hal::pio::Buffers::OnlyTx)
             .out_shift_direction(rp_pico::hal::pio::ShiftDirection::Left)
             .autopull(true)
-            .pull_threshold(8)
+            .pull_threshold(CMD_PULL_BITS)
             // misc config
             .clock_divisor_fixed_point(1, 0)
             .build(sm);
@@ -85,11 +240,26 @@ where
 
         sm.start();
 
+        // whatever the panel has in it on power up has to be overwritten
+        let mut dirty = [None; MAX_DIRTY];
+        dirty[0] = Some(Rect::new(0, 0, SCR_W, SCR_H));
+
         Self {
-            tx: tx,
+            // one pixel per fifo entry, the dma replicates it across the word so the top half is
+            // what gets shifted out
+            tx: Some(tx.transfer_size(HalfWord)),
+            dma_ch: Some(dma_ch),
+            transfer: None,
+            pull_bits: CMD_PULL_BITS,
+            dirty: dirty,
+            queued: [None; MAX_DIRTY],
+            sending: None,
+            orientation: ScreenOrientation::default(),
+            width: SCR_W,
+            height: SCR_H,
             _data_pin: data_pin.into(),
             _clock_pin: clock_pin.into(),
-            backlight_pin: backlight_pin,
+            backlight: backlight,
             dc_pin: dc_pin,
             cs_pin: cs_pin,
             _rst_pin: rst_pin,
@@ -97,12 +267,33 @@ where
         }
     }
 
-    pub fn backlight_on(&mut self) {
-        self.backlight_pin.set_high().unwrap();
+    /// Sets the backlight, from 0 (off) to 255 (full brightness).
+    pub fn set_backlight(&mut self, level: u8) {
+        // squaring the level makes the steps look roughly even
+        let max = self.backlight.get_max_duty() as u32;
+        let level = level as u32;
+        self.backlight
+            .set_duty((level * level * max / (255 * 255)) as u16);
     }
 
     pub fn backlight_off(&mut self) {
-        self.backlight_pin.set_low().unwrap();
+        self.set_backlight(0);
+    }
+
+    /// Turns the backlight off and puts the panel to sleep (SLPIN). The framebuffer can still be
+    /// drawn to, it shows up once the panel wakes.
+    pub fn enter_sleep(&mut self) {
+        self.backlight_off();
+        self.write_cmd(&[0x10]); // Enter sleep mode
+        self.sleep(5_000.micros());
+    }
+
+    /// Wakes the panel (SLPOUT). The backlight is left for the caller to turn back on.
+    pub fn exit_sleep(&mut self) {
+        self.write_cmd(&[0x11]); // Exit sleep mode
+
+        // the panel needs 5ms after waking before it takes more commands
+        self.sleep(5_000.micros());
     }
 
     pub fn init(&mut self) {
@@ -122,20 +313,193 @@ where
         self.write_cmd(&[0x01]); // Software reset
         self.write_cmd(&[0x11]); // Exit sleep mode
         self.write_cmd(&[0x3A, 0x55]); // Set colour mode to 16 bit
-        self.write_cmd(&[0x36, 0x00]); // Set MADCTL: row then column, refresh is bottom to top ????
+        self.write_cmd(&[0x36, self.madctl()]); // Set MADCTL: memory access order, see set_orientation
         self.write_cmd(&[0x2A, 0x00, 0x00, (SCR_W >> 8) as u8, (SCR_W & 0xFF) as u8]); // CASET: column addresses
         self.write_cmd(&[0x2B, 0x00, 0x00, (SCR_H >> 8) as u8, (SCR_H & 0xFF) as u8]); // RASET: row addresses
-        self.write_cmd(&[0x21]); // Inversion on (supposedly a hack?)
+        self.write_cmd(&[0x21]); // Inversion on, this IPS panel shows inverted colours without it
         self.write_cmd(&[0x13]); // Normal display on
         self.write_cmd(&[0x29]); // Main screen turn on
 
         self.push_framebuffer();
-        self.backlight_on();
+        self.set_backlight(u8::MAX);
+    }
+
+    fn tx(&mut self) -> &mut ScreenTx<P, SM> {
+        self.wait_transfer();
+        self.tx.as_mut().unwrap()
+    }
+
+    fn reclaim(&mut self, transfer: FramebufferTransfer<CH, P, SM>) {
+        let (ch, _, tx) = transfer.wait();
+        self.dma_ch = Some(ch);
+        self.tx = Some(tx);
+    }
+
+    /// Blocks until the last framebuffer push has gone out.
+    pub fn wait_transfer(&mut self) {
+        while let Some(t) = self.transfer.take() {
+            self.reclaim(t);
+            self.send_next();
+        }
+    }
+
+    /// Returns true while a framebuffer push is still running. This also moves the push on to the
+    /// next row or region, so it needs calling regularly while busy.
+    pub fn is_busy(&mut self) -> bool {
+        match self.transfer.take() {
+            Some(t) if t.is_done() => {
+                self.reclaim(t);
+                self.send_next();
+            }
+            t => self.transfer = t,
+        }
+        self.transfer.is_some()
+    }
+
+    fn send_next(&mut self) {
+        let r = match self.sending {
+            Some(r) if !r.is_empty() => r,
+            _ => match self.queued.iter_mut().find_map(|q| q.take()) {
+                Some(r) => {
+                    self.set_window(r);
+                    self.start_pixels();
+                    r
+                }
+                None => {
+                    self.sending = None;
+                    return;
+                }
+            },
+        };
+
+        let (rows, pixels) = region_pixels(r, self.width);
+        self.sending = Some(Rect::new(r.x, r.y + rows, r.w, r.h - rows));
+
+        let ch = self.dma_ch.take().unwrap();
+        let tx = self.tx.take().unwrap();
+        self.transfer = Some(single_buffer::Config::new(ch, pixels, tx).start());
+    }
+
+    /// Marks a region of the framebuffer as changed so the next push sends it.
+    pub fn mark_dirty(&mut self, r: Rect) {
+        let mut r = r.clip(self.width, self.height);
+        if r.is_empty() {
+            return;
+        }
+
+        // fold any regions this one touches into it, so overlapping draws are only sent once
+        for d in self.dirty.iter_mut() {
+            if let Some(o) = d {
+                if o.touches(&r) {
+                    r = r.union(*o);
+                    *d = None;
+                }
+            }
+        }
+
+        match self.dirty.iter_mut().find(|d| d.is_none()) {
+            Some(d) => *d = Some(r),
+            // out of room, merge it into the first one rather than lose either
+            None => self.dirty[0] = self.dirty[0].map(|d| d.union(r)),
+        }
+    }
+
+    fn madctl(&self) -> u8 {
+        // MY, MX and MV flip the rows, flip the columns, and swap rows with columns
+        const MY: u8 = 0x80;
+        const MX: u8 = 0x40;
+        const MV: u8 = 0x20;
+
+        let rotation = match self.orientation.rotation {
+            ScreenRotation::Deg0 => 0,
+            ScreenRotation::Deg90 => MX | MV,
+            ScreenRotation::Deg180 => MX | MY,
+            ScreenRotation::Deg270 => MY | MV,
+        };
+        // left to right runs along the panel's rows when MV is set
+        let mirror = match (self.orientation.mirrored, self.orientation.swaps_axes()) {
+            (false, _) => 0,
+            (true, false) => MX,
+            (true, true) => MY,
+        };
+        rotation ^ mirror
+    }
+
+    /// Rotates and mirrors the screen. Width and height swap for 90 and 270 degrees, and the
+    /// whole screen is resent, so the framebuffer needs redrawing for the new size.
+    pub fn set_orientation(&mut self, orientation: ScreenOrientation) {
+        if orientation == self.orientation {
+            return;
+        }
+
+        self.wait_transfer();
+        self.orientation = orientation;
+        (self.width, self.height) = if orientation.swaps_axes() {
+            (SCR_H, SCR_W)
+        } else {
+            (SCR_W, SCR_H)
+        };
+        self.write_cmd(&[0x36, self.madctl()]);
+
+        self.dirty = [None; MAX_DIRTY];
+        self.mark_dirty(self.screen());
+    }
+
+    fn set_window(&mut self, r: Rect) {
+        let (x0, x1) = (r.x as u16, (r.right() - 1) as u16);
+        let (y0, y1) = (r.y as u16, (r.bottom() - 1) as u16);
+        let x0 = x0.to_be_bytes();
+        let x1 = x1.to_be_bytes();
+        let y0 = y0.to_be_bytes();
+        let y1 = y1.to_be_bytes();
+        self.write_cmd(&[0x2A, x0[0], x0[1], x1[0], x1[1]]); // CASET: column addresses
+        self.write_cmd(&[0x2B, y0[0], y0[1], y1[0], y1[1]]); // RASET: row addresses
     }
 
     fn wait_idle(&mut self) {
-        self.tx.clear_stalled_flag();
-        while !self.tx.has_stalled() {}
+        let tx = self.tx();
+        tx.clear_stalled_flag();
+        while !tx.has_stalled() {}
+    }
+
+    // Changes how many bits are pulled from each fifo entry, once everything already written has
+    // gone out.
+    fn set_pull_bits(&mut self, bits: u8) {
+        self.wait_idle();
+        if bits == self.pull_bits {
+            return;
+        }
+
+        let block = unsafe {
+            &*match P::id() {
+                0 => pac::PIO0::ptr(),
+                _ => pac::PIO1::ptr(),
+            }
+        };
+        let sm = block.sm(SM::id());
+        let mask = 1u8 << SM::id();
+
+        // the osr counts as empty once the threshold's worth of bits has been shifted out. when
+        // the threshold goes up it'd be part full again, and the state machine would clock out
+        // zeros, so it's paused and the rest is shifted out to nowhere first
+        block
+            .ctrl()
+            .modify(|r, w| unsafe { w.sm_enable().bits(r.sm_enable().bits() & !mask) });
+        sm.sm_shiftctrl()
+            .modify(|_, w| unsafe { w.pull_thresh().bits(bits) });
+        if bits > self.pull_bits {
+            let empty_osr = pio::InstructionOperands::OUT {
+                destination: pio::OutDestination::NULL,
+                bit_count: 32,
+            };
+            sm.sm_instr()
+                .write(|w| unsafe { w.sm0_instr().bits(empty_osr.encode()) });
+        }
+        block
+            .ctrl()
+            .modify(|r, w| unsafe { w.sm_enable().bits(r.sm_enable().bits() | mask) });
+
+        self.pull_bits = bits;
     }
 
     fn sleep(&mut self, t: MicrosDurationU64) {
@@ -150,13 +514,14 @@ where
 
     fn write(&mut self, word: u8) {
         let w = (word as u32) << 24;
-        while !self.tx.write(w) {
+        let tx = self.tx();
+        while !tx.write(w) {
             cortex_m::asm::nop();
         }
     }
 
     fn write_cmd(&mut self, cmd: &[u8]) {
-        self.wait_idle();
+        self.set_pull_bits(CMD_PULL_BITS);
         self.set_dc_cs(false, false);
 
         self.write(cmd[0]);
@@ -191,37 +556,144 @@ where
 
     fn start_pixels(&mut self) {
         self.write_cmd(&[0x002C]);
+        self.set_pull_bits(PIXEL_PULL_BITS);
         self.set_dc_cs(true, false);
     }
 
-    pub fn fill_framebuffer(&mut self, color: u16) {
-        for y in 0..SCR_H {
-            for x in 0..SCR_W {
-                // doing unchecked access did not meaningfully improve performance
-                unsafe {
-                    FB[y][x] = color;
-                }
+    pub fn fill_rect(&mut self, r: Rect, color: u16) {
+        self.fill_rect_with(r, |_, _| color);
+    }
+
+    pub fn fill_rect_with(&mut self, r: Rect, f: impl Fn(usize, usize) -> u16) {
+        // don't draw under a frame that's still being sent
+        self.wait_transfer();
+
+        let r = r.clip(self.width, self.height);
+        let w = self.width;
+        let fb = unsafe { &mut *core::ptr::addr_of_mut!(FB) };
+        for y in r.y..r.bottom() {
+            let row = &mut fb[y * w..(y + 1) * w];
+            for x in r.x..r.right() {
+                row[x] = to_pixel(f(x, y));
             }
         }
+
+        self.mark_dirty(r);
+    }
+
+    /// Writes pixels in row order, starting `start` pixels into the screen. Nothing is marked
+    /// dirty, callers streaming whole frames mark the rows they touched once they're done.
+    pub fn write_span(&mut self, start: usize, colors: impl Iterator<Item = u16>) {
+        self.wait_transfer();
+
+        let len = self.width * self.height;
+        let fb = unsafe { &mut *core::ptr::addr_of_mut!(FB) };
+        for (p, c) in fb[start.min(len)..len].iter_mut().zip(colors) {
+            *p = to_pixel(c);
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: u16) {
+        self.fill_rect(Rect::new(x, y, 1, 1), color);
+    }
+
+    pub fn fill_framebuffer(&mut self, color: u16) {
+        self.fill_rect(self.screen(), color);
+    }
+
+    pub fn fill_framebuffer_with(&mut self, f: impl Fn(usize, usize) -> u16) {
+        self.fill_rect_with(self.screen(), f);
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn screen(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
     }
 
     pub fn clear_framebuffer(&mut self) {
         self.fill_framebuffer(0);
     }
 
+    /// Starts sending the regions marked dirty since the last push and returns straight away, the
+    /// DMA feeds the PIO from here. Anything else that touches the screen waits for the push to
+    /// finish.
     pub fn push_framebuffer(&mut self) {
-        self.start_pixels();
-        for y in 0..SCR_H {
-            let y = unsafe { FB.get_unchecked(y) };
-            for x in 0..SCR_W {
-                let w = unsafe { y.get_unchecked(x) };
-                // self.write(*w);
-                let w1 = (*w >> 8) as u8;
-                let w2 = *w as u8;
-
-                self.write(w1);
-                self.write(w2);
+        self.wait_transfer();
+
+        self.queued = self.dirty;
+        self.dirty = [None; MAX_DIRTY];
+        self.send_next();
+    }
+}
+
+impl<'timer, P, SM, I, CH, BL> OriginDimensions for St7789<'timer, P, SM, I, CH, BL>
+where
+    I: AnyPin<Function = P::PinFunction>,
+    P: PIOExt,
+    SM: StateMachineIndex,
+    CH: SingleChannel,
+    BL: PwmPin<Duty = u16>,
+{
+    fn size(&self) -> Size {
+        Size::new(self.width as u32, self.height as u32)
+    }
+}
+
+impl<'timer, P, SM, I, CH, BL> DrawTarget for St7789<'timer, P, SM, I, CH, BL>
+where
+    I: AnyPin<Function = P::PinFunction>,
+    P: PIOExt,
+    SM: StateMachineIndex,
+    CH: SingleChannel,
+    BL: PwmPin<Duty = u16>,
+{
+    type Color = Rgb565;
+    type Error = Infallible;
+
+    fn draw_iter<It>(&mut self, pixels: It) -> Result<(), Self::Error>
+    where
+        It: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.wait_transfer();
+
+        // marking every pixel on its own would be slow, so only the bounds of the drawing are marked
+        let (w, h) = (self.width, self.height);
+        let fb = unsafe { &mut *core::ptr::addr_of_mut!(FB) };
+        let mut bounds: Option<Rect> = None;
+        for Pixel(p, c) in pixels {
+            if p.x < 0 || p.y < 0 || p.x as usize >= w || p.y as usize >= h {
+                continue;
             }
+
+            let (x, y) = (p.x as usize, p.y as usize);
+            fb[y * w + x] = to_pixel(c.into_storage());
+
+            let r = Rect::new(x, y, 1, 1);
+            bounds = Some(bounds.map_or(r, |b| b.union(r)));
         }
+
+        if let Some(b) = bounds {
+            self.mark_dirty(b);
+        }
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.bounding_box());
+        let r = Rect::new(
+            area.top_left.x as usize,
+            area.top_left.y as usize,
+            area.size.width as usize,
+            area.size.height as usize,
+        );
+        self.fill_rect(r, color.into_storage());
+        Ok(())
     }
 }
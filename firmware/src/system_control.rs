@@ -0,0 +1,89 @@
+//! System control HID device, for power down, sleep and wake up. usbd-human-interface-device has
+//! no device for these, so this one is put together the same way as its consumer control device.
+
+use jukebox_util::hid::{SYSTEM_POWER_DOWN, SYSTEM_WAKE_UP};
+use rp_pico::hal::fugit::ExtU32;
+use usbd_human_interface_device::usb_class::prelude::*;
+
+// one byte, the control being pressed or 0 for none
+#[rustfmt::skip]
+pub const SYSTEM_CONTROL_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x80, // Usage (System Control)
+    0xA1, 0x01, // Collection (Application)
+    0x19, 0x81, //   Usage Minimum (System Power Down)
+    0x29, 0x83, //   Usage Maximum (System Wake Up)
+    0x15, 0x01, //   Logical Minimum (1)
+    0x25, 0x03, //   Logical Maximum (3)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x01, //   Report Count (1)
+    0x81, 0x00, //   Input (Data, Array, Absolute)
+    0xC0,       // End Collection
+];
+
+pub struct SystemControl<'a, B: UsbBus> {
+    interface: Interface<'a, B, InBytes8, OutNone, ReportSingle>,
+    last: Option<u8>,
+}
+
+impl<'a, B: UsbBus> SystemControl<'a, B> {
+    /// Presses one of the system control usages, or releases with anything else.
+    pub fn write_report(&mut self, usage: u8) -> Result<(), UsbHidError> {
+        let report = match usage {
+            SYSTEM_POWER_DOWN..=SYSTEM_WAKE_UP => usage - SYSTEM_POWER_DOWN + 1,
+            _ => 0,
+        };
+        if self.last == Some(report) {
+            return Err(UsbHidError::Duplicate);
+        }
+
+        self.interface.write_report(&[report])?;
+        self.last = Some(report);
+        Ok(())
+    }
+}
+
+pub struct SystemControlConfig<'a> {
+    interface: InterfaceConfig<'a, InBytes8, OutNone, ReportSingle>,
+}
+
+impl<'a> Default for SystemControlConfig<'a> {
+    fn default() -> Self {
+        SystemControlConfig {
+            interface: InterfaceBuilder::new(SYSTEM_CONTROL_REPORT_DESCRIPTOR)
+                .unwrap()
+                .description("System Control")
+                .in_endpoint(10.millis())
+                .unwrap()
+                .without_out_endpoint()
+                .build(),
+        }
+    }
+}
+
+impl<'a, B: UsbBus + 'a> UsbAllocatable<'a, B> for SystemControlConfig<'a> {
+    type Allocated = SystemControl<'a, B>;
+
+    fn allocate(self, usb_alloc: &'a UsbBusAllocator<B>) -> Self::Allocated {
+        SystemControl {
+            interface: Interface::new(usb_alloc, self.interface),
+            last: None,
+        }
+    }
+}
+
+impl<'a, B: UsbBus> DeviceClass<'a> for SystemControl<'a, B> {
+    type I = Interface<'a, B, InBytes8, OutNone, ReportSingle>;
+
+    fn interface(&mut self) -> &mut Self::I {
+        &mut self.interface
+    }
+
+    fn reset(&mut self) {
+        self.last = None;
+    }
+
+    fn tick(&mut self) -> Result<(), UsbHidError> {
+        Ok(())
+    }
+}
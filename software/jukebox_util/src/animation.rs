@@ -0,0 +1,178 @@
+//! Animation frames streamed to the screen. A frame is a list of ops over the screen's pixels in
+//! row order, so after the first frame only what changed has to be sent.
+//!
+//! Each op starts with a u16 (big endian), the top two bits pick the op and the rest is the
+//! pixel count minus one:
+//!   skip:    the pixels stay as they were in the last frame
+//!   run:     one color follows, for all of the pixels
+//!   literal: a color follows for each pixel
+//! Colors are rgb565, big endian.
+//!
+//! Frames are too big for one command, so they're sent in chunks of whole ops. Every chunk
+//! starts with a flags byte saying whether it starts or ends a frame.
+
+pub const ANIM_CHUNK_START: u8 = 0x01; // first chunk of a frame, ops start at the top left pixel
+pub const ANIM_CHUNK_END: u8 = 0x02; // last chunk of a frame, it can be shown
+pub const ANIM_CHUNK_HEADER_SIZE: usize = 1;
+
+pub const ANIM_OP_HEADER_SIZE: usize = 2;
+pub const ANIM_OP_MAX_COUNT: usize = 1 << 14;
+// keeps the largest op well under a command payload
+pub const ANIM_MAX_LITERAL: usize = 256;
+pub const ANIM_OP_MAX_SIZE: usize = ANIM_OP_HEADER_SIZE + ANIM_MAX_LITERAL * 2;
+
+const OP_SKIP: u16 = 0b00;
+const OP_RUN: u16 = 0b01;
+const OP_LITERAL: u16 = 0b10;
+
+// shorter stretches than these are cheaper as part of a literal
+const MIN_SKIP: usize = 2;
+const MIN_RUN: usize = 3;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum FrameOp<'a> {
+    Skip(usize),
+    Run(usize, u16),
+    Literal(&'a [u8]), // the colors, still encoded
+}
+impl<'a> FrameOp<'a> {
+    pub fn count(&self) -> usize {
+        match self {
+            Self::Skip(n) | Self::Run(n, _) => *n,
+            Self::Literal(b) => b.len() / 2,
+        }
+    }
+
+    /// The colors of a literal, nothing for the other ops.
+    pub fn colors(&self) -> impl Iterator<Item = u16> + 'a {
+        let b: &'a [u8] = match self {
+            Self::Literal(b) => b,
+            _ => &[],
+        };
+        b.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]]))
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn encode(&self, b: &mut [u8]) -> Result<usize, ()> {
+        let count = self.count();
+        if count == 0 || count > ANIM_OP_MAX_COUNT {
+            return Err(());
+        }
+
+        let run_color;
+        let (op, data): (u16, &[u8]) = match self {
+            Self::Skip(_) => (OP_SKIP, &[]),
+            Self::Run(_, c) => {
+                run_color = c.to_be_bytes();
+                (OP_RUN, &run_color)
+            }
+            Self::Literal(d) => (OP_LITERAL, d),
+        };
+        let size = ANIM_OP_HEADER_SIZE + data.len();
+        if b.len() < size {
+            return Err(());
+        }
+
+        let header = op << 14 | (count - 1) as u16;
+        b[..ANIM_OP_HEADER_SIZE].copy_from_slice(&header.to_be_bytes());
+        b[ANIM_OP_HEADER_SIZE..size].copy_from_slice(data);
+        Ok(size)
+    }
+}
+
+/// Reads the ops out of a chunk, after its flags byte. Anything malformed ends it with an error.
+pub struct FrameOps<'a> {
+    b: &'a [u8],
+}
+impl<'a> FrameOps<'a> {
+    pub fn new(b: &'a [u8]) -> Self {
+        FrameOps { b }
+    }
+
+    fn decode(&mut self) -> Result<FrameOp<'a>, ()> {
+        if self.b.len() < ANIM_OP_HEADER_SIZE {
+            return Err(());
+        }
+
+        let header = u16::from_be_bytes([self.b[0], self.b[1]]);
+        let count = (header & 0x3FFF) as usize + 1;
+        let data = &self.b[ANIM_OP_HEADER_SIZE..];
+        let (op, size) = match header >> 14 {
+            OP_SKIP => (FrameOp::Skip(count), 0),
+            OP_RUN if data.len() >= 2 => (
+                FrameOp::Run(count, u16::from_be_bytes([data[0], data[1]])),
+                2,
+            ),
+            OP_LITERAL if data.len() >= count * 2 => {
+                (FrameOp::Literal(&data[..count * 2]), count * 2)
+            }
+            _ => return Err(()),
+        };
+
+        self.b = &data[size..];
+        Ok(op)
+    }
+}
+impl<'a> Iterator for FrameOps<'a> {
+    type Item = Result<FrameOp<'a>, ()>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.b.is_empty() {
+            return None;
+        }
+
+        let op = self.decode();
+        if op.is_err() {
+            self.b = &[];
+        }
+        Some(op)
+    }
+}
+
+/// Encodes `frame` as ops, handing each one to `emit` as it's made. With the previous frame the
+/// pixels that didn't change are skipped, without it every pixel is drawn.
+pub fn encode_frame(prev: Option<&[u16]>, frame: &[u16], mut emit: impl FnMut(&[u8])) {
+    let unchanged = |i: usize| prev.is_some_and(|p| p.get(i) == Some(&frame[i]));
+    // how many pixels from `i` on pass `f`, up to the most one op can hold
+    let stretch = |i: usize, f: &dyn Fn(usize) -> bool| {
+        (i..frame.len().min(i + ANIM_OP_MAX_COUNT))
+            .take_while(|j| f(*j))
+            .count()
+    };
+
+    let mut buf = [0u8; ANIM_OP_MAX_SIZE];
+    let mut literal = [0u8; ANIM_MAX_LITERAL * 2];
+    let mut i = 0;
+    while i < frame.len() {
+        let skip = stretch(i, &unchanged);
+        let run = stretch(i, &|j| frame[j] == frame[i]);
+
+        let op = if skip >= MIN_SKIP {
+            FrameOp::Skip(skip)
+        } else if run >= MIN_RUN {
+            FrameOp::Run(run, frame[i])
+        } else {
+            // carry on until something cheaper starts
+            let mut n = 0;
+            while i + n < frame.len() && n < ANIM_MAX_LITERAL {
+                let j = i + n;
+                let starts = |min: usize, f: &dyn Fn(usize) -> bool| {
+                    (j..j + min).all(|k| k < frame.len() && f(k))
+                };
+                if n > 0
+                    && (starts(MIN_SKIP, &unchanged) || starts(MIN_RUN, &|k| frame[k] == frame[j]))
+                {
+                    break;
+                }
+                literal[n * 2..n * 2 + 2].copy_from_slice(&frame[j].to_be_bytes());
+                n += 1;
+            }
+            FrameOp::Literal(&literal[..n * 2])
+        };
+
+        i += op.count();
+        // counts are kept in range above, so encoding can't fail
+        let size = op.encode(&mut buf).unwrap();
+        emit(&buf[..size]);
+    }
+}
@@ -1,3 +1,54 @@
+//! Color conversions shared by the firmware and the desktop app. Nothing here leans on std or a
+//! float math library, so the keypad LEDs and the app's preview of them come out the same.
+
+pub type Rgb = (u8, u8, u8);
+
+// (v / 255)^2.8 * 255, rounded. WS2812s are linear in PWM duty, which looks far too bright at the
+// low end, so fades need the curve to look even.
+pub const GAMMA_WS2812: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 5, 5, 5,
+    5, 6, 6, 6, 6, 7, 7, 7, 7, 8, 8, 8, 9, 9, 9, 10, 10, 10, 11, 11, 11, 12, 12, 13, 13, 13, 14,
+    14, 15, 15, 16, 16, 17, 17, 18, 18, 19, 19, 20, 20, 21, 21, 22, 22, 23, 24, 24, 25, 25, 26, 27,
+    27, 28, 29, 29, 30, 31, 32, 32, 33, 34, 35, 35, 36, 37, 38, 39, 39, 40, 41, 42, 43, 44, 45, 46,
+    47, 48, 49, 50, 50, 51, 52, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 66, 67, 68, 69, 70, 72,
+    73, 74, 75, 77, 78, 79, 81, 82, 83, 85, 86, 87, 89, 90, 92, 93, 95, 96, 98, 99, 101, 102, 104,
+    105, 107, 109, 110, 112, 114, 115, 117, 119, 120, 122, 124, 126, 127, 129, 131, 133, 135, 137,
+    138, 140, 142, 144, 146, 148, 150, 152, 154, 156, 158, 160, 162, 164, 167, 169, 171, 173, 175,
+    177, 180, 182, 184, 186, 189, 191, 193, 196, 198, 200, 203, 205, 208, 210, 213, 215, 218, 220,
+    223, 225, 228, 231, 233, 236, 239, 241, 244, 247, 249, 252, 255,
+];
+
+// 1000K to 12000K in 500K steps, from Tanner Helland's fit of the blackbody colors
+const KELVIN_MIN: u16 = 1000;
+const KELVIN_MAX: u16 = 12000;
+const KELVIN_STEP: u16 = 500;
+const KELVIN_TABLE: [Rgb; 23] = [
+    (255, 68, 0),
+    (255, 108, 0),
+    (255, 137, 14),
+    (255, 159, 70),
+    (255, 177, 110),
+    (255, 193, 141),
+    (255, 206, 166),
+    (255, 218, 187),
+    (255, 228, 206),
+    (255, 237, 222),
+    (255, 246, 237),
+    (255, 254, 250),
+    (243, 242, 255),
+    (230, 235, 255),
+    (221, 230, 255),
+    (215, 226, 255),
+    (210, 223, 255),
+    (205, 220, 255),
+    (202, 218, 255),
+    (199, 216, 255),
+    (196, 214, 255),
+    (193, 213, 255),
+    (191, 211, 255),
+];
+
 pub fn hsv2rgb(hue: f32, sat: f32, val: f32) -> (u8, u8, u8) {
     let c = val * sat;
     let v = (hue / 60.0) % 2.0 - 1.0;
@@ -18,9 +69,9 @@ pub fn hsv2rgb(hue: f32, sat: f32, val: f32) -> (u8, u8, u8) {
         (c, 0.0, x)
     };
 
-    let r = ((r + m) * 255.0) as u8;
-    let g = ((g + m) * 255.0) as u8;
-    let b = ((b + m) * 255.0) as u8;
+    let r = ((r + m) * 255.0 + 0.5) as u8;
+    let g = ((g + m) * 255.0 + 0.5) as u8;
+    let b = ((b + m) * 255.0 + 0.5) as u8;
 
     (r, g, b)
 }
@@ -31,3 +82,206 @@ pub fn rgb565(r: u8, g: u8, b: u8) -> u16 {
     let b = (b as u16) >> 3;
     r | g | b
 }
+
+/// Hue in degrees (0-360), saturation and value 0-1. The inverse of `hsv2rgb`.
+pub fn rgb2hsv(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let d = max - min;
+
+    let hue = if d == 0.0 {
+        0.0
+    } else if max == r {
+        let h = 60.0 * (g - b) / d;
+        if h < 0.0 {
+            h + 360.0
+        } else {
+            h
+        }
+    } else if max == g {
+        60.0 * (b - r) / d + 120.0
+    } else {
+        60.0 * (r - g) / d + 240.0
+    };
+    let sat = if max == 0.0 { 0.0 } else { d / max };
+
+    (hue, sat, max)
+}
+
+/// Expands rgb565 back to 8 bits a channel, repeating the high bits so white stays white.
+pub fn rgb565_to_rgb888(c: u16) -> Rgb {
+    let r = (c >> 11) as u8;
+    let g = ((c >> 5) & 0x3F) as u8;
+    let b = (c & 0x1F) as u8;
+    (r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2)
+}
+
+/// Corrects a color for WS2812 LEDs, see `GAMMA_WS2812`.
+pub fn gamma(c: Rgb) -> Rgb {
+    (
+        GAMMA_WS2812[c.0 as usize],
+        GAMMA_WS2812[c.1 as usize],
+        GAMMA_WS2812[c.2 as usize],
+    )
+}
+
+/// Mixes from `a` at t = 0 to `b` at t = 255.
+pub fn blend(a: Rgb, b: Rgb, t: u8) -> Rgb {
+    let mix =
+        |a: u8, b: u8| ((a as u16 * (255 - t as u16) + b as u16 * t as u16 + 127) / 255) as u8;
+    (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+}
+
+/// The color `t` of the way along evenly spaced stops, 0 is the first stop and 255 the last.
+pub fn gradient(stops: &[Rgb], t: u8) -> Rgb {
+    match stops.len() {
+        0 => (0, 0, 0),
+        1 => stops[0],
+        n => {
+            // position in 1/255ths of a stop
+            let pos = t as usize * (n - 1);
+            let i = (pos / 255).min(n - 2);
+            blend(stops[i], stops[i + 1], (pos - i * 255) as u8)
+        }
+    }
+}
+
+/// The color of a light at a temperature, warm candlelight at 1000K to blue sky past 10000K.
+pub fn kelvin2rgb(kelvin: u16) -> Rgb {
+    let k = kelvin.clamp(KELVIN_MIN, KELVIN_MAX) - KELVIN_MIN;
+    let i = (k / KELVIN_STEP) as usize;
+    if i + 1 >= KELVIN_TABLE.len() {
+        return KELVIN_TABLE[KELVIN_TABLE.len() - 1];
+    }
+
+    let t = (k % KELVIN_STEP) as u32 * 255 / KELVIN_STEP as u32;
+    blend(KELVIN_TABLE[i], KELVIN_TABLE[i + 1], t as u8)
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Palette {
+    Rainbow,
+    Fire,
+    Ocean,
+    Forest,
+    Sunset,
+    Ice,
+}
+impl Palette {
+    pub const ALL: [Palette; 6] = [
+        Palette::Rainbow,
+        Palette::Fire,
+        Palette::Ocean,
+        Palette::Forest,
+        Palette::Sunset,
+        Palette::Ice,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rainbow => "Rainbow",
+            Self::Fire => "Fire",
+            Self::Ocean => "Ocean",
+            Self::Forest => "Forest",
+            Self::Sunset => "Sunset",
+            Self::Ice => "Ice",
+        }
+    }
+
+    pub fn stops(self) -> &'static [Rgb] {
+        match self {
+            Self::Rainbow => &[
+                (255, 0, 0),
+                (255, 255, 0),
+                (0, 255, 0),
+                (0, 255, 255),
+                (0, 0, 255),
+                (255, 0, 255),
+                (255, 0, 0),
+            ],
+            Self::Fire => &[
+                (0, 0, 0),
+                (128, 0, 0),
+                (255, 64, 0),
+                (255, 160, 0),
+                (255, 255, 128),
+            ],
+            Self::Ocean => &[(0, 0, 32), (0, 32, 128), (0, 128, 192), (0, 224, 224)],
+            Self::Forest => &[(0, 32, 0), (0, 128, 32), (96, 192, 0), (32, 96, 0)],
+            Self::Sunset => &[(64, 0, 128), (192, 0, 96), (255, 64, 0), (255, 160, 32)],
+            Self::Ice => &[
+                (255, 255, 255),
+                (160, 224, 255),
+                (32, 128, 255),
+                (0, 32, 128),
+            ],
+        }
+    }
+
+    /// The color `t` of the way through the palette, see `gradient`.
+    pub fn color(self, t: u8) -> Rgb {
+        gradient(self.stops(), t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hsv_round_trip() {
+        for r in 0..=255 {
+            for g in 0..=255 {
+                for b in 0..=255 {
+                    let (h, s, v) = rgb2hsv(r, g, b);
+                    assert_eq!(hsv2rgb(h, s, v), (r, g, b), "hsv {h} {s} {v}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rgb565_round_trip() {
+        for c in 0..=u16::MAX {
+            let (r, g, b) = rgb565_to_rgb888(c);
+            assert_eq!(rgb565(r, g, b), c);
+        }
+        assert_eq!(rgb565_to_rgb888(0xFFFF), (255, 255, 255));
+        assert_eq!(rgb565_to_rgb888(0x0000), (0, 0, 0));
+    }
+
+    #[test]
+    fn gamma_keeps_the_ends_and_order() {
+        assert_eq!(gamma((0, 0, 0)), (0, 0, 0));
+        assert_eq!(gamma((255, 255, 255)), (255, 255, 255));
+        assert!(GAMMA_WS2812.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn blend_ends() {
+        let (a, b) = ((10, 200, 30), (250, 0, 128));
+        assert_eq!(blend(a, b, 0), a);
+        assert_eq!(blend(a, b, 255), b);
+        assert_eq!(blend((0, 0, 0), (255, 255, 255), 128), (128, 128, 128));
+    }
+
+    #[test]
+    fn gradient_ends() {
+        assert_eq!(gradient(&[], 100), (0, 0, 0));
+        assert_eq!(gradient(&[(1, 2, 3)], 100), (1, 2, 3));
+        for p in Palette::ALL {
+            let stops = p.stops();
+            assert_eq!(p.color(0), stops[0], "{}", p.name());
+            assert_eq!(p.color(255), stops[stops.len() - 1], "{}", p.name());
+        }
+    }
+
+    #[test]
+    fn kelvin_is_clamped() {
+        assert_eq!(kelvin2rgb(0), kelvin2rgb(KELVIN_MIN));
+        assert_eq!(kelvin2rgb(u16::MAX), kelvin2rgb(KELVIN_MAX));
+        assert_eq!(kelvin2rgb(KELVIN_MIN), KELVIN_TABLE[0]);
+        assert_eq!(kelvin2rgb(KELVIN_MAX), KELVIN_TABLE[KELVIN_TABLE.len() - 1]);
+    }
+}
@@ -0,0 +1,121 @@
+// All the utilities for the device self-test
+
+pub const DIAG_STOP: u8 = b'S';
+pub const DIAG_STATUS_LED: u8 = b'L';
+pub const DIAG_RGB: u8 = b'R';
+pub const DIAG_SCREEN: u8 = b'C';
+pub const DIAG_KEYS: u8 = b'K';
+pub const DIAG_UNKNOWN: u8 = b'?';
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum DiagnosticsTest {
+    Stop,      // return to normal operation
+    StatusLed, // blink the status led quickly
+    Rgb,       // cycle each rgb led through red, green, and blue
+    Screen,    // cycle through color bars, a gradient, and a checkerboard
+    Keys,      // track which switches stay held for the duration of the test
+    Unknown,
+}
+impl DiagnosticsTest {
+    pub const fn default() -> Self {
+        Self::Stop
+    }
+
+    pub fn encode(self) -> u8 {
+        match self {
+            Self::Stop => DIAG_STOP,
+            Self::StatusLed => DIAG_STATUS_LED,
+            Self::Rgb => DIAG_RGB,
+            Self::Screen => DIAG_SCREEN,
+            Self::Keys => DIAG_KEYS,
+            Self::Unknown => DIAG_UNKNOWN,
+        }
+    }
+
+    pub fn decode(w: u8) -> Self {
+        match w {
+            DIAG_STOP => Self::Stop,
+            DIAG_STATUS_LED => Self::StatusLed,
+            DIAG_RGB => Self::Rgb,
+            DIAG_SCREEN => Self::Screen,
+            DIAG_KEYS => Self::Keys,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct DiagnosticsReport {
+    pub test: DiagnosticsTest,
+    pub pressed: u16, // bitmap of switches currently down, switch 1 is bit 0
+    pub stuck: u16,   // bitmap of switches held down since the keys test started
+}
+impl DiagnosticsReport {
+    pub const fn default() -> Self {
+        DiagnosticsReport {
+            test: DiagnosticsTest::default(),
+            pressed: 0,
+            stuck: 0,
+        }
+    }
+
+    pub fn encode(self) -> [u8; 5] {
+        [
+            self.test.encode(),
+            (self.pressed >> 8) as u8,
+            self.pressed as u8,
+            (self.stuck >> 8) as u8,
+            self.stuck as u8,
+        ]
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn decode(b: &[u8]) -> Result<Self, ()> {
+        if b.len() != 5 {
+            return Err(());
+        }
+
+        Ok(DiagnosticsReport {
+            test: DiagnosticsTest::decode(b[0]),
+            pressed: (b[1] as u16) << 8 | b[2] as u16,
+            stuck: (b[3] as u16) << 8 | b[4] as u16,
+        })
+    }
+
+    pub fn is_stuck(&self, switch: usize) -> bool {
+        self.stuck & (1 << switch) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_round_trip() {
+        let tests = [
+            DiagnosticsTest::Stop,
+            DiagnosticsTest::StatusLed,
+            DiagnosticsTest::Rgb,
+            DiagnosticsTest::Screen,
+            DiagnosticsTest::Keys,
+            DiagnosticsTest::Unknown,
+        ];
+        for test in tests {
+            let r = DiagnosticsReport {
+                test,
+                pressed: 0x8001,
+                stuck: 0x0F0F,
+            };
+            assert_eq!(DiagnosticsReport::decode(&r.encode()), Ok(r));
+        }
+        assert_eq!(DiagnosticsTest::decode(b'z'), DiagnosticsTest::Unknown);
+    }
+
+    #[test]
+    fn report_rejects_bad_lengths() {
+        let b = DiagnosticsReport::default().encode();
+        assert!(DiagnosticsReport::decode(&b[..4]).is_err());
+        assert!(DiagnosticsReport::decode(&[&b[..], &[0]].concat()).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! Compact bitmap fonts, stored in the JBF format written by the desktop app's `jbfont` tool.
+//!
+//! A JBF font is laid out as (multi-byte values are big endian):
+//!   "JBF", version
+//!   height, ascent (rows above the baseline), first char, char count
+//!   a glyph table, char count * [advance width, bitmap offset (u16)]
+//!   the bitmaps, `height` rows of ceil(width / 8) bytes per glyph, msb is the leftmost pixel
+//!
+//! Glyphs are the full advance width by the font height, so drawing a string is just placing
+//! glyphs side by side. Only single byte chars are covered, which is plenty for labels.
+
+pub const FONT_MAGIC: &[u8] = b"JBF";
+pub const FONT_VERSION: u8 = 1;
+pub const FONT_HEADER_SIZE: usize = 8;
+pub const GLYPH_ENTRY_SIZE: usize = 3;
+
+// drawn in place of anything the font doesn't have
+const FALLBACK_CHAR: char = '?';
+
+// converted from the public domain X11 misc-fixed fonts
+pub const FONT_6X10: &[u8] = include_bytes!("../fonts/6x10.jbf");
+pub const FONT_10X20: &[u8] = include_bytes!("../fonts/10x20.jbf");
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+impl TextAlign {
+    pub const ALL: [TextAlign; 3] = [TextAlign::Left, TextAlign::Center, TextAlign::Right];
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TextSize {
+    Small,  // 6x10
+    Medium, // 10x20
+    Large,  // 10x20, doubled
+}
+impl TextSize {
+    pub const ALL: [TextSize; 3] = [TextSize::Small, TextSize::Medium, TextSize::Large];
+
+    /// The built in font for this size, and how many times to scale it up.
+    pub fn font(self) -> (Font<'static>, u8) {
+        // the built in fonts are checked when they're converted, parsing them can't fail
+        let (data, scale) = match self {
+            Self::Small => (FONT_6X10, 1),
+            Self::Medium => (FONT_10X20, 1),
+            Self::Large => (FONT_10X20, 2),
+        };
+        (Font::parse(data).unwrap(), scale)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Glyph<'a> {
+    pub width: u8,
+    pub height: u8,
+    rows: &'a [u8],
+}
+impl<'a> Glyph<'a> {
+    pub fn is_set(&self, x: u32, y: u32) -> bool {
+        if x >= self.width as u32 || y >= self.height as u32 {
+            return false;
+        }
+
+        let stride = (self.width as usize).div_ceil(8);
+        let b = self.rows[y as usize * stride + x as usize / 8];
+        b & (0x80 >> (x % 8)) != 0
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Font<'a> {
+    pub height: u8,
+    pub ascent: u8,
+    first: u8,
+    count: u8,
+    table: &'a [u8],
+    bitmaps: &'a [u8],
+}
+impl<'a> Font<'a> {
+    #[allow(clippy::result_unit_err)]
+    pub fn parse(b: &'a [u8]) -> Result<Self, ()> {
+        if b.len() < FONT_HEADER_SIZE || &b[..3] != FONT_MAGIC || b[3] != FONT_VERSION {
+            return Err(());
+        }
+
+        let (height, ascent, first, count) = (b[4], b[5], b[6], b[7]);
+        if height == 0 || ascent > height || first as usize + count as usize > 256 {
+            return Err(());
+        }
+
+        let table_end = FONT_HEADER_SIZE + count as usize * GLYPH_ENTRY_SIZE;
+        if b.len() < table_end {
+            return Err(());
+        }
+
+        let font = Font {
+            height,
+            ascent,
+            first,
+            count,
+            table: &b[FONT_HEADER_SIZE..table_end],
+            bitmaps: &b[table_end..],
+        };
+
+        // make sure every glyph is in bounds, so drawing never has to check
+        for i in 0..count as usize {
+            let (width, offset) = font.entry(i);
+            let size = (width as usize).div_ceil(8) * height as usize;
+            if offset + size > font.bitmaps.len() {
+                return Err(());
+            }
+        }
+
+        Ok(font)
+    }
+
+    fn entry(&self, i: usize) -> (u8, usize) {
+        let e = &self.table[i * GLYPH_ENTRY_SIZE..(i + 1) * GLYPH_ENTRY_SIZE];
+        (e[0], u16::from_be_bytes([e[1], e[2]]) as usize)
+    }
+
+    fn lookup(&self, c: char) -> Option<Glyph<'a>> {
+        let c = c as u32;
+        if c < self.first as u32 || c >= self.first as u32 + self.count as u32 {
+            return None;
+        }
+
+        let (width, offset) = self.entry((c - self.first as u32) as usize);
+        if width == 0 {
+            return None;
+        }
+
+        let size = (width as usize).div_ceil(8) * self.height as usize;
+        Some(Glyph {
+            width,
+            height: self.height,
+            rows: &self.bitmaps[offset..offset + size],
+        })
+    }
+
+    /// The glyph for `c`, or the font's '?' if it doesn't have one.
+    pub fn glyph(&self, c: char) -> Option<Glyph<'a>> {
+        self.lookup(c).or_else(|| self.lookup(FALLBACK_CHAR))
+    }
+
+    pub fn char_width(&self, c: char) -> u32 {
+        self.glyph(c).map_or(0, |g| g.width as u32)
+    }
+
+    pub fn text_width(&self, s: &str) -> u32 {
+        s.chars().map(|c| self.char_width(c)).sum()
+    }
+}
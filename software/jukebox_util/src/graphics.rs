@@ -0,0 +1,441 @@
+//! Drawing shared between the screen firmware and the host. Everything here draws to any
+//! embedded-graphics target, so layouts can be rendered into a MemoryScreen off the device.
+
+use embedded_graphics::{geometry::AnchorY, pixelcolor::Rgb565, prelude::*, primitives::Rectangle};
+
+use crate::font::{Font, TextAlign, TextSize};
+use crate::peripheral::KeyLabels;
+
+// white, yellow, cyan, green, magenta, red, blue, black
+const COLOR_BARS: [Rgb565; 8] = [
+    Rgb565::WHITE,
+    Rgb565::YELLOW,
+    Rgb565::CYAN,
+    Rgb565::GREEN,
+    Rgb565::MAGENTA,
+    Rgb565::RED,
+    Rgb565::BLUE,
+    Rgb565::BLACK,
+];
+const CHECKER_SIZE: u32 = 16;
+
+const KEY_LABELS_TITLE_COLOR: Rgb565 = Rgb565::new(4, 8, 12);
+const KEY_LABELS_KEY_COLOR: Rgb565 = Rgb565::new(6, 12, 6);
+const KEY_LABELS_GAP: u32 = 4;
+
+/// A framebuffer in plain memory, for drawing on the host.
+pub struct MemoryScreen<const W: usize, const H: usize> {
+    pixels: [[Rgb565; W]; H],
+}
+
+impl<const W: usize, const H: usize> Default for MemoryScreen<W, H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const W: usize, const H: usize> MemoryScreen<W, H> {
+    pub const fn new() -> Self {
+        MemoryScreen {
+            pixels: [[Rgb565::BLACK; W]; H],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Rgb565 {
+        self.pixels[y][x]
+    }
+
+    pub fn rows(&self) -> &[[Rgb565; W]; H] {
+        &self.pixels
+    }
+}
+
+impl<const W: usize, const H: usize> OriginDimensions for MemoryScreen<W, H> {
+    fn size(&self) -> Size {
+        Size::new(W as u32, H as u32)
+    }
+}
+
+impl<const W: usize, const H: usize> DrawTarget for MemoryScreen<W, H> {
+    type Color = Rgb565;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(p, c) in pixels {
+            if p.x >= 0 && p.y >= 0 && (p.x as usize) < W && (p.y as usize) < H {
+                self.pixels[p.y as usize][p.x as usize] = c;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn draw_color_bars<D>(target: &mut D) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565> + OriginDimensions,
+{
+    let size = target.size();
+    let bar_w = size.width / COLOR_BARS.len() as u32;
+    for (i, c) in COLOR_BARS.iter().enumerate() {
+        // the last bar takes whatever is left over
+        let w = if i == COLOR_BARS.len() - 1 {
+            size.width - bar_w * i as u32
+        } else {
+            bar_w
+        };
+        let bar = Rectangle::new(
+            Point::new((bar_w * i as u32) as i32, 0),
+            Size::new(w, size.height),
+        );
+        target.fill_solid(&bar, *c)?;
+    }
+    Ok(())
+}
+
+/// Four bands of red, green, blue, and white, fading in from left to right.
+pub fn draw_gradients<D>(target: &mut D) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565> + OriginDimensions,
+{
+    let size = target.size();
+    let area = Rectangle::new(Point::zero(), size);
+    let (w, h) = (size.width.max(2), size.height);
+    target.fill_contiguous(
+        &area,
+        area.points().map(|p| {
+            let v = (p.x as u32 * 255 / (w - 1)) as u8;
+            let (r, g, b) = match p.y as u32 * 4 / h {
+                0 => (v, 0, 0),
+                1 => (0, v, 0),
+                2 => (0, 0, v),
+                _ => (v, v, v),
+            };
+            Rgb565::new(r >> 3, g >> 2, b >> 3)
+        }),
+    )
+}
+
+pub fn draw_checkerboard<D>(target: &mut D) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565> + OriginDimensions,
+{
+    let area = Rectangle::new(Point::zero(), target.size());
+    target.fill_contiguous(
+        &area,
+        area.points().map(|p| {
+            if (p.x as u32 / CHECKER_SIZE + p.y as u32 / CHECKER_SIZE).is_multiple_of(2) {
+                Rgb565::WHITE
+            } else {
+                Rgb565::BLACK
+            }
+        }),
+    )
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct TextStyle {
+    pub color: Rgb565,
+    pub background: Option<Rgb565>, // fills the whole area first, otherwise text is drawn over
+    pub scale: u8,
+    pub align: TextAlign,
+    pub wrap: bool, // break lines between words to fit the area, otherwise they're cut off
+}
+impl TextStyle {
+    pub const fn new(color: Rgb565) -> Self {
+        TextStyle {
+            color,
+            background: None,
+            scale: 1,
+            align: TextAlign::Left,
+            wrap: true,
+        }
+    }
+}
+
+// Takes the next line off the front of `text`, returning it and what's left. Lines end at a
+// newline, or when wrapping, at the last space that fits (or mid-word if a word doesn't fit).
+fn next_line<'t>(font: &Font, text: &'t str, max_width: u32, wrap: bool) -> (&'t str, &'t str) {
+    let (para, rest) = match text.find('\n') {
+        Some(i) => (&text[..i], &text[i + 1..]),
+        None => (text, ""),
+    };
+    if !wrap {
+        return (para, rest);
+    }
+
+    let mut width = 0;
+    let mut last_space = None;
+    for (i, c) in para.char_indices() {
+        width += font.char_width(c);
+        if width > max_width && i > 0 {
+            return match last_space {
+                Some(s) => (&para[..s], &text[s + 1..]),
+                None => (&para[..i], &text[i..]),
+            };
+        }
+        if c == ' ' {
+            last_space = Some(i);
+        }
+    }
+    (para, rest)
+}
+
+/// How tall `text` comes out when drawn `width` wide.
+pub fn text_height(font: &Font, text: &str, width: u32, style: &TextStyle) -> u32 {
+    let scale = style.scale.max(1) as u32;
+    let mut lines = 1;
+    let mut rest = text;
+    loop {
+        let (_, next) = next_line(font, rest, width / scale, style.wrap);
+        if next.is_empty() {
+            break;
+        }
+        rest = next;
+        lines += 1;
+    }
+    lines * font.height as u32 * scale
+}
+
+/// Draws `text` inside `area`, one line under the other from the top. Anything that doesn't
+/// fit is clipped.
+pub fn draw_text<D>(
+    target: &mut D,
+    font: &Font,
+    text: &str,
+    area: &Rectangle,
+    style: &TextStyle,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    let mut target = target.clipped(area);
+    if let Some(bg) = style.background {
+        target.fill_solid(area, bg)?;
+    }
+
+    let scale = style.scale.max(1) as u32;
+    let line_height = font.height as i32 * scale as i32;
+    let bottom = area.top_left.y + area.size.height as i32;
+
+    let mut y = area.top_left.y;
+    let mut rest = text;
+    while y < bottom {
+        let (line, next) = next_line(font, rest, area.size.width / scale, style.wrap);
+        // trailing spaces shouldn't push a line off center
+        let line = line.trim_end_matches(' ');
+
+        let width = (font.text_width(line) * scale) as i32;
+        let mut x = match style.align {
+            TextAlign::Left => area.top_left.x,
+            TextAlign::Center => area.top_left.x + (area.size.width as i32 - width) / 2,
+            TextAlign::Right => area.top_left.x + area.size.width as i32 - width,
+        };
+
+        for c in line.chars() {
+            let Some(glyph) = font.glyph(c) else { continue };
+            let cell = Rectangle::new(
+                Point::new(x, y),
+                Size::new(glyph.width as u32 * scale, font.height as u32 * scale),
+            );
+            let is_set = |p: Point| {
+                let p = p - cell.top_left;
+                glyph.is_set(p.x as u32 / scale, p.y as u32 / scale)
+            };
+
+            match style.background {
+                // whole cells at a time are much quicker, when there's a color for the gaps
+                Some(bg) => target.fill_contiguous(
+                    &cell,
+                    cell.points()
+                        .map(|p| if is_set(p) { style.color } else { bg }),
+                )?,
+                None => target.draw_iter(
+                    cell.points()
+                        .filter(|p| is_set(*p))
+                        .map(|p| Pixel(p, style.color)),
+                )?,
+            }
+            x += cell.size.width as i32;
+        }
+
+        if next.is_empty() {
+            break;
+        }
+        rest = next;
+        y += line_height;
+    }
+    Ok(())
+}
+
+/// The profile's name along the top, then a tile for each key with what it does.
+pub fn draw_key_labels<D>(target: &mut D, labels: &KeyLabels) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565> + OriginDimensions,
+{
+    let size = target.size();
+    target.clear(Rgb565::BLACK)?;
+    if labels.is_clear() {
+        return Ok(());
+    }
+
+    let (small, _) = TextSize::Small.font();
+    let (medium, _) = TextSize::Medium.font();
+
+    let title_height = small.height as u32 + KEY_LABELS_GAP * 2;
+    let title = Rectangle::new(Point::zero(), Size::new(size.width, title_height));
+    let mut style = TextStyle::new(Rgb565::WHITE);
+    style.background = Some(KEY_LABELS_TITLE_COLOR);
+    style.align = TextAlign::Center;
+    target.fill_solid(&title, KEY_LABELS_TITLE_COLOR)?;
+    draw_text(
+        target,
+        &small,
+        labels.title(),
+        &title.resized_height(small.height as u32, AnchorY::Center),
+        &style,
+    )?;
+
+    let (rows, cols) = (labels.rows as u32, labels.cols as u32);
+    let key_w = size.width / cols;
+    let key_h = (size.height - title_height) / rows;
+    style.background = Some(KEY_LABELS_KEY_COLOR);
+
+    for key in 0..labels.key_count() as u32 {
+        let (x, y) = (key % cols, key / cols);
+        let tile = Rectangle::new(
+            Point::new((x * key_w) as i32, (title_height + y * key_h) as i32),
+            Size::new(key_w, key_h),
+        )
+        .offset(-(KEY_LABELS_GAP as i32 / 2));
+        target.fill_solid(&tile, KEY_LABELS_KEY_COLOR)?;
+
+        // the big font when the label fits on one line, otherwise the small one, wrapped
+        let label = labels.label(key as usize);
+        let inner = tile.offset(-(KEY_LABELS_GAP as i32 / 2));
+        let font = match medium.text_width(label) <= inner.size.width {
+            true => &medium,
+            false => &small,
+        };
+
+        let height = text_height(font, label, inner.size.width, &style).min(inner.size.height);
+        let area = inner.resized_height(height, AnchorY::Center);
+        draw_text(target, font, label, &area, &style)?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::{boxed::Box, format, path::PathBuf, vec::Vec};
+
+    const W: usize = 240;
+    const H: usize = 320;
+
+    // Compares the screen against a PNG in snapshots/. A missing snapshot is written out instead,
+    // as is every one when UPDATE_SNAPSHOTS is set, so check the new images before committing them.
+    fn assert_snapshot(name: &str, screen: &MemoryScreen<W, H>) {
+        let rgb: Vec<u8> = screen
+            .rows()
+            .iter()
+            .flatten()
+            .flat_map(|c| {
+                let c = embedded_graphics::pixelcolor::Rgb888::from(*c);
+                [c.r(), c.g(), c.b()]
+            })
+            .collect();
+        let image = image::RgbImage::from_raw(W as u32, H as u32, rgb).unwrap();
+
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("snapshots")
+            .join(format!("{}.png", name));
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() || !path.exists() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            image.save(&path).unwrap();
+            return;
+        }
+
+        let expected = image::open(&path).unwrap().to_rgb8();
+        assert_eq!(
+            expected.dimensions(),
+            image.dimensions(),
+            "{} changed size",
+            name
+        );
+        let changed = expected
+            .pixels()
+            .zip(image.pixels())
+            .filter(|(a, b)| a != b)
+            .count();
+        assert_eq!(changed, 0, "{} pixels of {} changed", changed, name);
+    }
+
+    fn screen() -> Box<MemoryScreen<W, H>> {
+        Box::new(MemoryScreen::new())
+    }
+
+    #[test]
+    fn color_bars() {
+        let mut s = screen();
+        draw_color_bars(s.as_mut()).unwrap();
+        assert_snapshot("color_bars", &s);
+    }
+
+    #[test]
+    fn gradients() {
+        let mut s = screen();
+        draw_gradients(s.as_mut()).unwrap();
+        assert_snapshot("gradients", &s);
+    }
+
+    #[test]
+    fn checkerboard() {
+        let mut s = screen();
+        draw_checkerboard(s.as_mut()).unwrap();
+        assert_snapshot("checkerboard", &s);
+    }
+
+    #[test]
+    fn text() {
+        let mut s = screen();
+        let (font, _) = TextSize::Medium.font();
+        let area = Rectangle::new(Point::new(10, 10), Size::new(220, 300));
+        let mut style = TextStyle::new(Rgb565::WHITE);
+        draw_text(
+            s.as_mut(),
+            &font,
+            "Wrapped to fit, left aligned",
+            &area,
+            &style,
+        )
+        .unwrap();
+
+        style.align = TextAlign::Center;
+        style.background = Some(Rgb565::BLUE);
+        style.scale = 2;
+        let area = Rectangle::new(Point::new(10, 120), Size::new(220, 100));
+        draw_text(
+            s.as_mut(),
+            &font,
+            "Centered\nand cut off at the bottom",
+            &area,
+            &style,
+        )
+        .unwrap();
+        assert_snapshot("text", &s);
+    }
+
+    #[test]
+    fn key_labels() {
+        let mut s = screen();
+        let mut labels = KeyLabels::new(3, 4, "Profile");
+        labels.set_label(0, "Mute");
+        labels.set_label(1, "Play / pause");
+        labels.set_label(11, "F24");
+        draw_key_labels(s.as_mut(), &labels).unwrap();
+        assert_snapshot("key_labels", &s);
+    }
+}
@@ -0,0 +1,385 @@
+//! HID usages the device sends by itself, and what each input sends in standalone mode, when no
+//! host app is connected. Usages are the plain ids from the HID usage tables, the firmware turns
+//! them into reports.
+//!
+//! The host can also have the device send reports for it, as a sequence of steps. Each step sets
+//! what one of the keyboard, consumer, system control or mouse interfaces reports, then holds it for a while
+//! before the next step. Keys and codes stay down across steps and sequences until a later step
+//! lets them go, or the host goes away.
+
+use crate::peripheral::DeviceType;
+
+// keyboard page
+pub const KEY_F1: u8 = 0x3A;
+pub const KEY_F13: u8 = 0x68;
+
+// consumer page
+pub const CONSUMER_SCAN_NEXT_TRACK: u16 = 0xB5;
+pub const CONSUMER_SCAN_PREVIOUS_TRACK: u16 = 0xB6;
+pub const CONSUMER_PLAY_PAUSE: u16 = 0xCD;
+pub const CONSUMER_MUTE: u16 = 0xE2;
+pub const CONSUMER_VOLUME_INCREMENT: u16 = 0xE9;
+pub const CONSUMER_VOLUME_DECREMENT: u16 = 0xEA;
+
+// generic desktop page, system controls
+pub const SYSTEM_POWER_DOWN: u8 = 0x81;
+pub const SYSTEM_SLEEP: u8 = 0x82;
+pub const SYSTEM_WAKE_UP: u8 = 0x83;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum HidUsage {
+    None,
+    Key(u8),       // keyboard page
+    Consumer(u16), // consumer page
+    System(u8),    // generic desktop system controls
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct KnobUsages {
+    pub ccw: HidUsage, // tapped once per detent
+    pub cw: HidUsage,
+    pub press: HidUsage, // held while the knob is pushed in
+}
+
+/// F1 to F24, `n` counting from 1. F13 and up aren't next to F12 in the usage table.
+pub fn function_key(n: u8) -> HidUsage {
+    match n {
+        1..=12 => HidUsage::Key(KEY_F1 + n - 1),
+        13..=24 => HidUsage::Key(KEY_F13 + n - 13),
+        _ => HidUsage::None,
+    }
+}
+
+/// What a key sends in standalone mode. Keypad keys count down from F24, the same as the desktop
+/// app labels them, and pedals are previous, play/pause and next.
+pub fn standalone_key(device_type: DeviceType, key: usize, key_count: usize) -> HidUsage {
+    match device_type {
+        DeviceType::KeyPad if key < key_count && key_count <= 24 => {
+            function_key((24 - key_count + key + 1) as u8)
+        }
+        DeviceType::PedalPad => match key {
+            0 => HidUsage::Consumer(CONSUMER_SCAN_PREVIOUS_TRACK),
+            1 => HidUsage::Consumer(CONSUMER_PLAY_PAUSE),
+            2 => HidUsage::Consumer(CONSUMER_SCAN_NEXT_TRACK),
+            _ => HidUsage::None,
+        },
+        _ => HidUsage::None,
+    }
+}
+
+/// What a knob sends in standalone mode. The first knob is volume and mute, the second is the
+/// track and play/pause.
+pub fn standalone_knob(knob: usize) -> KnobUsages {
+    match knob {
+        0 => KnobUsages {
+            ccw: HidUsage::Consumer(CONSUMER_VOLUME_DECREMENT),
+            cw: HidUsage::Consumer(CONSUMER_VOLUME_INCREMENT),
+            press: HidUsage::Consumer(CONSUMER_MUTE),
+        },
+        1 => KnobUsages {
+            ccw: HidUsage::Consumer(CONSUMER_SCAN_PREVIOUS_TRACK),
+            cw: HidUsage::Consumer(CONSUMER_SCAN_NEXT_TRACK),
+            press: HidUsage::Consumer(CONSUMER_PLAY_PAUSE),
+        },
+        _ => KnobUsages {
+            ccw: HidUsage::None,
+            cw: HidUsage::None,
+            press: HidUsage::None,
+        },
+    }
+}
+
+pub const HID_KEYBOARD_MAX_KEYS: usize = 8;
+pub const HID_CONSUMER_MAX_CODES: usize = 4;
+pub const HID_SEQUENCE_MAX_STEPS: usize = 32;
+
+// Steps are encoded as: ident, hold (u16, big endian), report data. Sequences are a step count
+// followed by the steps.
+pub const IDENT_HID_KEYBOARD: u8 = b'k';
+pub const IDENT_HID_CONSUMER: u8 = b'c';
+pub const IDENT_HID_MOUSE: u8 = b'm';
+pub const IDENT_HID_SYSTEM: u8 = b's';
+
+const HID_STEP_HEADER_SIZE: usize = 3;
+pub const HID_SEQUENCE_MAX_SIZE: usize =
+    1 + HID_SEQUENCE_MAX_STEPS * (HID_STEP_HEADER_SIZE + 2 * HID_CONSUMER_MAX_CODES);
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct MouseReport {
+    pub buttons: u8, // left is bit 0, then right and middle
+    pub x: i8,       // movement since the last report
+    pub y: i8,
+    pub wheel: i8, // positive scrolls up
+    pub pan: i8,   // positive scrolls right
+}
+impl MouseReport {
+    pub const fn default() -> Self {
+        MouseReport {
+            buttons: 0,
+            x: 0,
+            y: 0,
+            wheel: 0,
+            pan: 0,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum HidReport {
+    Keyboard([u8; HID_KEYBOARD_MAX_KEYS]), // usages held down, modifiers too, 0 if unused
+    Consumer([u16; HID_CONSUMER_MAX_CODES]), // usages held down, 0 if unused
+    Mouse(MouseReport),                    // sent once, movement isn't repeated
+    System(u8),                            // usage held down, 0 if none
+}
+impl HidReport {
+    /// Holds down `keys`, letting go of any others. Keys past HID_KEYBOARD_MAX_KEYS are dropped.
+    pub fn keyboard(keys: &[u8]) -> Self {
+        let mut k = [0u8; HID_KEYBOARD_MAX_KEYS];
+        for (k, key) in k.iter_mut().zip(keys) {
+            *k = *key;
+        }
+        Self::Keyboard(k)
+    }
+
+    /// Holds down `codes`, letting go of any others. Codes past HID_CONSUMER_MAX_CODES are dropped.
+    pub fn consumer(codes: &[u16]) -> Self {
+        let mut c = [0u16; HID_CONSUMER_MAX_CODES];
+        for (c, code) in c.iter_mut().zip(codes) {
+            *c = *code;
+        }
+        Self::Consumer(c)
+    }
+
+    fn ident(&self) -> u8 {
+        match self {
+            Self::Keyboard(_) => IDENT_HID_KEYBOARD,
+            Self::Consumer(_) => IDENT_HID_CONSUMER,
+            Self::Mouse(_) => IDENT_HID_MOUSE,
+            Self::System(_) => IDENT_HID_SYSTEM,
+        }
+    }
+
+    fn data_len(ident: u8) -> Result<usize, ()> {
+        match ident {
+            IDENT_HID_KEYBOARD => Ok(HID_KEYBOARD_MAX_KEYS),
+            IDENT_HID_CONSUMER => Ok(2 * HID_CONSUMER_MAX_CODES),
+            IDENT_HID_MOUSE => Ok(5),
+            IDENT_HID_SYSTEM => Ok(1),
+            _ => Err(()),
+        }
+    }
+
+    fn encode_data(&self, b: &mut [u8]) {
+        match self {
+            Self::Keyboard(k) => b.copy_from_slice(k),
+            Self::Consumer(c) => {
+                for (b, c) in b.chunks_exact_mut(2).zip(c) {
+                    b.copy_from_slice(&c.to_be_bytes());
+                }
+            }
+            Self::Mouse(m) => {
+                b.copy_from_slice(&[m.buttons, m.x as u8, m.y as u8, m.wheel as u8, m.pan as u8])
+            }
+            Self::System(s) => b[0] = *s,
+        }
+    }
+
+    fn decode_data(ident: u8, b: &[u8]) -> Result<Self, ()> {
+        match ident {
+            IDENT_HID_KEYBOARD => Ok(Self::keyboard(b)),
+            IDENT_HID_CONSUMER => {
+                let mut c = [0u16; HID_CONSUMER_MAX_CODES];
+                for (c, b) in c.iter_mut().zip(b.chunks_exact(2)) {
+                    *c = u16::from_be_bytes([b[0], b[1]]);
+                }
+                Ok(Self::Consumer(c))
+            }
+            IDENT_HID_MOUSE => Ok(Self::Mouse(MouseReport {
+                buttons: b[0],
+                x: b[1] as i8,
+                y: b[2] as i8,
+                wheel: b[3] as i8,
+                pan: b[4] as i8,
+            })),
+            IDENT_HID_SYSTEM => Ok(Self::System(b[0])),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct HidStep {
+    pub report: HidReport,
+    pub hold: u16, // ms before the next step
+}
+impl HidStep {
+    pub const fn default() -> Self {
+        HidStep {
+            report: HidReport::Keyboard([0u8; HID_KEYBOARD_MAX_KEYS]),
+            hold: 0,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct HidSequence {
+    steps: [HidStep; HID_SEQUENCE_MAX_STEPS],
+    len: usize,
+}
+impl Default for HidSequence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl HidSequence {
+    pub const fn new() -> Self {
+        HidSequence {
+            steps: [HidStep::default(); HID_SEQUENCE_MAX_STEPS],
+            len: 0,
+        }
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn push(&mut self, report: HidReport, hold: u16) -> Result<(), ()> {
+        if self.len == HID_SEQUENCE_MAX_STEPS {
+            return Err(());
+        }
+        self.steps[self.len] = HidStep { report, hold };
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn steps(&self) -> &[HidStep] {
+        &self.steps[..self.len]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Writes the sequence into b, returning how many bytes were used.
+    #[allow(clippy::result_unit_err)]
+    pub fn encode(&self, b: &mut [u8]) -> Result<usize, ()> {
+        if b.is_empty() {
+            return Err(());
+        }
+
+        b[0] = self.len as u8;
+        let mut i = 1;
+        for step in self.steps() {
+            let ident = step.report.ident();
+            let len = HidReport::data_len(ident)?;
+            if b.len() < i + HID_STEP_HEADER_SIZE + len {
+                return Err(());
+            }
+
+            b[i] = ident;
+            b[i + 1..i + 3].copy_from_slice(&step.hold.to_be_bytes());
+            i += HID_STEP_HEADER_SIZE;
+            step.report.encode_data(&mut b[i..i + len]);
+            i += len;
+        }
+
+        Ok(i)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn decode(b: &[u8]) -> Result<Self, ()> {
+        if b.is_empty() || b[0] as usize > HID_SEQUENCE_MAX_STEPS {
+            return Err(());
+        }
+
+        let mut s = HidSequence::new();
+        let mut i = 1;
+        for _ in 0..b[0] {
+            let header = b.get(i..i + HID_STEP_HEADER_SIZE).ok_or(())?;
+            let len = HidReport::data_len(header[0])?;
+            let data = b
+                .get(i + HID_STEP_HEADER_SIZE..i + HID_STEP_HEADER_SIZE + len)
+                .ok_or(())?;
+
+            let report = HidReport::decode_data(header[0], data)?;
+            s.push(report, u16::from_be_bytes([header[1], header[2]]))?;
+            i += HID_STEP_HEADER_SIZE + len;
+        }
+
+        if i != b.len() {
+            return Err(());
+        }
+        Ok(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn function_keys() {
+        assert_eq!(function_key(1), HidUsage::Key(KEY_F1));
+        assert_eq!(function_key(12), HidUsage::Key(KEY_F1 + 11));
+        assert_eq!(function_key(13), HidUsage::Key(KEY_F13));
+        assert_eq!(function_key(24), HidUsage::Key(KEY_F13 + 11));
+        assert_eq!(function_key(0), HidUsage::None);
+        assert_eq!(function_key(25), HidUsage::None);
+    }
+
+    #[test]
+    fn standalone_keypad_ends_at_f24() {
+        assert_eq!(standalone_key(DeviceType::KeyPad, 0, 12), function_key(13));
+        assert_eq!(standalone_key(DeviceType::KeyPad, 11, 12), function_key(24));
+        assert_eq!(standalone_key(DeviceType::KeyPad, 12, 12), HidUsage::None);
+        assert_eq!(standalone_key(DeviceType::KnobPad, 0, 12), HidUsage::None);
+    }
+
+    fn sequence() -> HidSequence {
+        let mut s = HidSequence::new();
+        s.push(HidReport::keyboard(&[0xE0, 0x06]), 10).unwrap();
+        s.push(HidReport::consumer(&[CONSUMER_MUTE]), 0).unwrap();
+        let m = MouseReport {
+            buttons: 1,
+            x: -5,
+            y: 127,
+            wheel: -128,
+            pan: 0,
+        };
+        s.push(HidReport::Mouse(m), u16::MAX).unwrap();
+        s.push(HidReport::System(SYSTEM_SLEEP), 100).unwrap();
+        s.push(HidReport::System(0), 0).unwrap();
+        s.push(HidReport::keyboard(&[]), 10).unwrap();
+        s
+    }
+
+    #[test]
+    fn sequence_round_trip() {
+        let mut b = [0u8; HID_SEQUENCE_MAX_SIZE];
+        for s in [sequence(), HidSequence::new()] {
+            let len = s.encode(&mut b).unwrap();
+            assert_eq!(HidSequence::decode(&b[..len]), Ok(s));
+        }
+
+        // the biggest sequence fits
+        let mut s = HidSequence::new();
+        while s.push(HidReport::consumer(&[1, 2, 3, 4]), 1).is_ok() {}
+        assert_eq!(s.steps().len(), HID_SEQUENCE_MAX_STEPS);
+        assert_eq!(s.encode(&mut b), Ok(HID_SEQUENCE_MAX_SIZE));
+        assert_eq!(HidSequence::decode(&b), Ok(s));
+    }
+
+    #[test]
+    fn sequence_rejects_bad_input() {
+        let mut b = [0u8; HID_SEQUENCE_MAX_SIZE];
+        let len = sequence().encode(&mut b).unwrap();
+
+        for cut in 0..len {
+            assert!(HidSequence::decode(&b[..cut]).is_err(), "cut at {cut}");
+        }
+        assert!(HidSequence::decode(&b[..len + 1]).is_err());
+        assert!(sequence().encode(&mut b[..len - 1]).is_err());
+
+        let mut bad = b;
+        bad[1] = b'x';
+        assert!(HidSequence::decode(&bad[..len]).is_err());
+        assert!(HidSequence::decode(&[HID_SEQUENCE_MAX_STEPS as u8 + 1]).is_err());
+    }
+}
@@ -0,0 +1,314 @@
+//! Turning pictures into frames for the screen, on the host. Images are decoded, turned and
+//! scaled to the screen, then dithered down to rgb565 so gradients don't band.
+//!
+//! Frames are kept as rgb565 values row by row. `Frame::to_bytes` packs them the way the St7789
+//! takes them over the wire, high byte first.
+
+use std::path::Path;
+use std::vec::Vec;
+
+use ::image::imageops::{self, FilterType};
+use ::image::{DynamicImage, ImageError, ImageReader, Rgba, RgbaImage};
+
+use crate::color::rgb565;
+use crate::peripheral::ScreenRotation;
+
+// 4x4 bayer matrix, thresholds 0-15 spread so neighbours differ as much as possible
+const BAYER_4X4: [[u32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ScaleMode {
+    Fit,     // the whole image shows, with bars of the background around it
+    Fill,    // covers the screen, cropping whatever doesn't fit
+    Stretch, // covers the screen, ignoring the aspect ratio
+}
+impl ScaleMode {
+    pub const ALL: [ScaleMode; 3] = [ScaleMode::Fit, ScaleMode::Fill, ScaleMode::Stretch];
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Dither {
+    None,           // each channel truncated, the same as color::rgb565
+    Ordered,        // a fixed bayer pattern, stable from frame to frame
+    FloydSteinberg, // error diffusion, smoothest for still images
+}
+impl Dither {
+    pub const ALL: [Dither; 3] = [Dither::None, Dither::Ordered, Dither::FloydSteinberg];
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct ConvertOptions {
+    pub width: u32, // the frame size, after rotation
+    pub height: u32,
+    pub scale: ScaleMode,
+    pub rotation: ScreenRotation, // clockwise, applied before scaling
+    pub dither: Dither,
+    pub background: (u8, u8, u8), // behind transparent pixels and fit bars
+}
+impl ConvertOptions {
+    pub fn new(width: u32, height: u32) -> Self {
+        ConvertOptions {
+            width,
+            height,
+            scale: ScaleMode::Fill,
+            rotation: ScreenRotation::Deg0,
+            dither: Dither::FloydSteinberg,
+            background: (0, 0, 0),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u16>, // rgb565, row by row
+}
+impl Frame {
+    /// The pixels in the St7789's byte order.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|p| p.to_be_bytes()).collect()
+    }
+}
+
+/// Decodes a PNG or JPEG, going by its contents rather than the file name.
+pub fn decode(bytes: &[u8]) -> Result<RgbaImage, ImageError> {
+    Ok(::image::load_from_memory(bytes)?.to_rgba8())
+}
+
+pub fn open(path: &Path) -> Result<RgbaImage, ImageError> {
+    Ok(ImageReader::open(path)?
+        .with_guessed_format()?
+        .decode()?
+        .to_rgba8())
+}
+
+pub fn rotate(img: &RgbaImage, rotation: ScreenRotation) -> RgbaImage {
+    match rotation {
+        ScreenRotation::Deg0 => img.clone(),
+        ScreenRotation::Deg90 => imageops::rotate90(img),
+        ScreenRotation::Deg180 => imageops::rotate180(img),
+        ScreenRotation::Deg270 => imageops::rotate270(img),
+    }
+}
+
+/// Scales to exactly `width` by `height`. Fit leaves the bars transparent.
+pub fn scale(img: &RgbaImage, width: u32, height: u32, mode: ScaleMode) -> RgbaImage {
+    let img = DynamicImage::ImageRgba8(img.clone());
+    match mode {
+        ScaleMode::Stretch => img.resize_exact(width, height, FilterType::Triangle),
+        ScaleMode::Fill => img.resize_to_fill(width, height, FilterType::Triangle),
+        ScaleMode::Fit => {
+            let fitted = img.resize(width, height, FilterType::Triangle);
+            let mut canvas = RgbaImage::new(width, height);
+            let x = (width - fitted.width()) / 2;
+            let y = (height - fitted.height()) / 2;
+            imageops::replace(&mut canvas, &fitted.to_rgba8(), x as i64, y as i64);
+            return canvas;
+        }
+    }
+    .to_rgba8()
+}
+
+/// Blends every pixel over the background, leaving the image opaque.
+pub fn flatten(img: &mut RgbaImage, background: (u8, u8, u8)) {
+    let bg = [background.0, background.1, background.2];
+    for p in img.pixels_mut() {
+        let a = p[3] as u16;
+        for c in 0..3 {
+            p[c] = ((p[c] as u16 * a + bg[c] as u16 * (255 - a) + 127) / 255) as u8;
+        }
+        p[3] = u8::MAX;
+    }
+}
+
+// a channel truncated to `bits`, and what the screen shows for it back in 8 bits
+fn quantize_channel(v: i32, bits: u32) -> (u16, i32) {
+    let q = (v.clamp(0, 255) >> (8 - bits)) as u16;
+    let shown = (q << (8 - bits)) | (q >> (2 * bits - 8));
+    (q, shown as i32)
+}
+
+fn pack(q: [u16; 3]) -> u16 {
+    q[0] << 11 | q[1] << 5 | q[2]
+}
+
+/// Dithers an opaque image down to rgb565. Alpha is ignored, `flatten` it first.
+pub fn quantize(img: &RgbaImage, dither: Dither) -> Vec<u16> {
+    let (w, h) = (img.width() as usize, img.height() as usize);
+    let bits = [5, 6, 5];
+
+    match dither {
+        Dither::None => img
+            .pixels()
+            .map(|&Rgba([r, g, b, _])| rgb565(r, g, b))
+            .collect(),
+        Dither::Ordered => img
+            .enumerate_pixels()
+            .map(|(x, y, p)| {
+                let t = BAYER_4X4[y as usize % 4][x as usize % 4];
+                let q = [0, 1, 2].map(|c| {
+                    // scale to the channel's levels, then round at the pixel's threshold
+                    let max = (1 << bits[c]) - 1;
+                    let v = p[c] as u32 * max * 32 + (2 * t + 1) * 255;
+                    (v / (255 * 32)).min(max) as u16
+                });
+                pack(q)
+            })
+            .collect(),
+        Dither::FloydSteinberg => {
+            let mut pixels = Vec::with_capacity(w * h);
+            // error carried onto this row and the next, with a pixel of padding either side
+            let mut err = std::vec![[0i32; 3]; w + 2];
+            let mut next = std::vec![[0i32; 3]; w + 2];
+
+            for y in 0..h {
+                for x in 0..w {
+                    let p = img.get_pixel(x as u32, y as u32).0;
+                    let mut q = [0u16; 3];
+                    for c in 0..3 {
+                        let v = (p[c] as i32 + err[x + 1][c] / 16).clamp(0, 255);
+                        let (qc, shown) = quantize_channel(v, bits[c]);
+                        let e = v - shown;
+
+                        err[x + 2][c] += e * 7;
+                        next[x][c] += e * 3;
+                        next[x + 1][c] += e * 5;
+                        next[x + 2][c] += e;
+                        q[c] = qc;
+                    }
+                    pixels.push(pack(q));
+                }
+
+                std::mem::swap(&mut err, &mut next);
+                next.iter_mut().for_each(|e| *e = [0; 3]);
+            }
+
+            pixels
+        }
+    }
+}
+
+/// Runs the whole pipeline: rotate, scale, flatten onto the background and dither.
+pub fn convert(img: &RgbaImage, options: &ConvertOptions) -> Frame {
+    let img = rotate(img, options.rotation);
+    let mut img = scale(&img, options.width, options.height, options.scale);
+    flatten(&mut img, options.background);
+
+    Frame {
+        width: options.width,
+        height: options.height,
+        pixels: quantize(&img, options.dither),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const GREEN: Rgba<u8> = Rgba([0, 255, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+    const CLEAR: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+    // three bands side by side, each `band` pixels wide
+    fn bands(band: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(band * 3, height, |x, _| {
+            [RED, GREEN, BLUE][(x / band) as usize]
+        })
+    }
+
+    fn rows(img: &RgbaImage) -> Vec<Vec<Rgba<u8>>> {
+        img.rows().map(|r| r.copied().collect()).collect()
+    }
+
+    #[test]
+    fn scale_fill_crops_the_middle() {
+        // already the right height, so nothing is resampled and only the middle band is left
+        let img = scale(&bands(4, 4), 4, 4, ScaleMode::Fill);
+        assert_eq!(img.dimensions(), (4, 4));
+        assert!(img.pixels().all(|p| *p == GREEN));
+    }
+
+    #[test]
+    fn scale_fit_adds_clear_bars() {
+        let img = RgbaImage::from_pixel(4, 2, RED);
+        let img = scale(&img, 4, 4, ScaleMode::Fit);
+        assert_eq!(
+            rows(&img),
+            std::vec![
+                std::vec![CLEAR; 4],
+                std::vec![RED; 4],
+                std::vec![RED; 4],
+                std::vec![CLEAR; 4],
+            ]
+        );
+
+        // the bars go on the sides for a tall image
+        let img = RgbaImage::from_pixel(2, 4, BLUE);
+        let img = scale(&img, 4, 4, ScaleMode::Fit);
+        let row = std::vec![CLEAR, BLUE, BLUE, CLEAR];
+        assert_eq!(rows(&img), std::vec![row; 4]);
+    }
+
+    #[test]
+    fn flatten_blends_over_the_background() {
+        let mut img = RgbaImage::from_vec(
+            3,
+            1,
+            std::vec![200, 100, 0, 128, 10, 20, 30, 255, 10, 20, 30, 0],
+        )
+        .unwrap();
+        flatten(&mut img, (0, 0, 255));
+        assert_eq!(
+            img.into_raw(),
+            std::vec![100, 50, 127, 255, 10, 20, 30, 255, 0, 0, 255, 255]
+        );
+    }
+
+    #[test]
+    fn quantize_without_dither_truncates() {
+        let img = RgbaImage::from_vec(
+            3,
+            1,
+            std::vec![255, 255, 255, 255, 8, 4, 8, 255, 70, 70, 70, 255],
+        )
+        .unwrap();
+        assert_eq!(
+            quantize(&img, Dither::None),
+            std::vec![0xFFFF, 0x0821, 0x4228]
+        );
+    }
+
+    #[test]
+    fn quantize_floyd_steinberg() {
+        // 70 sits between two levels in every channel, the error pushes some pixels up a level
+        let img = RgbaImage::from_pixel(4, 2, Rgba([70, 70, 70, 255]));
+        assert_eq!(
+            quantize(&img, Dither::FloydSteinberg),
+            std::vec![0x4228, 0x4228, 0x4A29, 0x4228, 0x4A29, 0x4228, 0x4A29, 0x4228]
+        );
+
+        // colors the screen can show exactly have no error to spread
+        let img = RgbaImage::from_fn(4, 2, |x, _| [RED, GREEN, BLUE, CLEAR][x as usize]);
+        assert_eq!(
+            quantize(&img, Dither::FloydSteinberg),
+            quantize(&img, Dither::None)
+        );
+    }
+
+    #[test]
+    fn floyd_steinberg_keeps_the_average() {
+        let img = RgbaImage::from_pixel(16, 16, Rgba([70, 70, 70, 255]));
+        let red = |p: &u16| {
+            let r = (p >> 11) as u32;
+            r << 3 | r >> 2
+        };
+        let average =
+            |pixels: &[u16]| pixels.iter().map(red).sum::<u32>() as f32 / pixels.len() as f32;
+
+        // truncating loses 4 in every pixel, the dithered pixels come out right on average
+        assert_eq!(average(&quantize(&img, Dither::None)), 66.0);
+        assert!((average(&quantize(&img, Dither::FloydSteinberg)) - 70.0).abs() < 1.0);
+    }
+}
@@ -1,5 +1,18 @@
 #![no_std]
 
+#[cfg(feature = "std")]
+extern crate std;
+
 pub mod peripheral;
+pub mod animation;
 pub mod color;
-pub mod protocol;
\ No newline at end of file
+pub mod diagnostics;
+pub mod font;
+pub mod hid;
+pub mod protocol;
+pub mod quadrature;
+pub mod status;
+#[cfg(feature = "graphics")]
+pub mod graphics;
+#[cfg(feature = "std")]
+pub mod image;
@@ -2,18 +2,66 @@
 
 use bitmatch::bitmatch;
 
+use crate::font::{TextAlign, TextSize};
+
 pub const IDENT_UNKNOWN_INPUT: u8 = b'?';
 pub const IDENT_KEY_INPUT: u8 = b'K';
 pub const IDENT_KNOB_INPUT: u8 = b'O';
 pub const IDENT_PEDAL_INPUT: u8 = b'P';
 
+// Analog pedal travel is reported on an axis, in tenths of a percent
+pub const PEDAL_ANALOG_MAX: u16 = 1000;
+pub const PEDAL_COUNT: usize = 3;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum DeviceType {
+    Unknown,
+    KeyPad,
+    KnobPad,
+    PedalPad,
+}
+impl DeviceType {
+    pub fn ident(self) -> u8 {
+        match self {
+            Self::Unknown => IDENT_UNKNOWN_INPUT,
+            Self::KeyPad => IDENT_KEY_INPUT,
+            Self::KnobPad => IDENT_KNOB_INPUT,
+            Self::PedalPad => IDENT_PEDAL_INPUT,
+        }
+    }
+
+    pub fn decode(w: u8) -> Self {
+        match w {
+            IDENT_KEY_INPUT => Self::KeyPad,
+            IDENT_KNOB_INPUT => Self::KnobPad,
+            IDENT_PEDAL_INPUT => Self::PedalPad,
+            _ => Self::Unknown,
+        }
+    }
+
+    pub fn usb_pid(self) -> u16 {
+        match self {
+            Self::Unknown => 0xF209,
+            Self::KeyPad => 0xF20A,
+            Self::KnobPad => 0xF20B,
+            Self::PedalPad => 0xF20C,
+        }
+    }
+
+    pub fn is_usb_pid(pid: u16) -> bool {
+        [Self::Unknown, Self::KeyPad, Self::KnobPad, Self::PedalPad]
+            .iter()
+            .any(|d| d.usb_pid() == pid)
+    }
+}
+
 #[derive(PartialEq, Clone, Copy)]
 pub enum Connection {
     NotConnected(bool), // false - lost connection, true - clean disconnect
     Connected,
 }
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum SwitchPosition {
     Up,
     Down,
@@ -30,324 +78,1310 @@ impl SwitchPosition {
         }
     }
 }
-impl Into<SwitchPosition> for bool {
-    fn into(self) -> SwitchPosition {
-        match self {
-            true => SwitchPosition::Down,
-            false => SwitchPosition::Up,
+impl From<bool> for SwitchPosition {
+    fn from(value: bool) -> Self {
+        match value {
+            true => Self::Down,
+            false => Self::Up,
         }
     }
 }
-impl Into<SwitchPosition> for u8 {
-    fn into(self) -> SwitchPosition {
-        match self {
-            1 => SwitchPosition::Down,
-            _ => SwitchPosition::Up,
+impl From<u8> for SwitchPosition {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Self::Down,
+            _ => Self::Up,
         }
     }
 }
-impl Into<u8> for SwitchPosition {
-    fn into(self) -> u8 {
-        match self {
-            Self::Down => 1,
-            Self::Up => 0,
+impl From<SwitchPosition> for u8 {
+    fn from(value: SwitchPosition) -> Self {
+        match value {
+            SwitchPosition::Down => 1,
+            SwitchPosition::Up => 0,
         }
     }
 }
-impl Into<SwitchPosition> for u16 {
-    fn into(self) -> SwitchPosition {
-        match self {
-            1 => SwitchPosition::Down,
-            _ => SwitchPosition::Up,
+impl From<u16> for SwitchPosition {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => Self::Down,
+            _ => Self::Up,
         }
     }
 }
-impl Into<bool> for SwitchPosition {
-    fn into(self) -> bool {
-        match self {
-            Self::Down => true,
-            Self::Up => false,
-        }
-    }
-}
-
-#[derive(Clone, Copy)]
-pub struct KeyInputs {
-    pub key1: SwitchPosition,
-    pub key2: SwitchPosition,
-    pub key3: SwitchPosition,
-    pub key4: SwitchPosition,
-    pub key5: SwitchPosition,
-    pub key6: SwitchPosition,
-    pub key7: SwitchPosition,
-    pub key8: SwitchPosition,
-    pub key9: SwitchPosition,
-    pub key10: SwitchPosition,
-    pub key11: SwitchPosition,
-    pub key12: SwitchPosition,
-    pub key13: SwitchPosition,
-    pub key14: SwitchPosition,
-    pub key15: SwitchPosition,
-    pub key16: SwitchPosition,
-}
-impl KeyInputs {
+impl From<SwitchPosition> for bool {
+    fn from(value: SwitchPosition) -> Self {
+        match value {
+            SwitchPosition::Down => true,
+            SwitchPosition::Up => false,
+        }
+    }
+}
+
+// What a board has on it, so the host doesn't have to guess from the device type
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct PeripheralDescriptor {
+    pub key_rows: u8,
+    pub key_cols: u8,
+    pub led_count: u8,
+    pub screen_width: u16, // 0 if there is no screen
+    pub screen_height: u16,
+    pub knob_count: u8,
+    pub pedal_count: u8,
+    pub flash_settings: bool, // settings can be saved to the device
+}
+impl PeripheralDescriptor {
+    pub const fn default() -> Self {
+        PeripheralDescriptor {
+            key_rows: 0,
+            key_cols: 0,
+            led_count: 0,
+            screen_width: 0,
+            screen_height: 0,
+            knob_count: 0,
+            pedal_count: 0,
+            flash_settings: false,
+        }
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.key_rows as usize * self.key_cols as usize
+    }
+
+    pub fn has_screen(&self) -> bool {
+        self.screen_width != 0 && self.screen_height != 0
+    }
+
+    pub fn encode(self) -> [u8; 10] {
+        let w = self.screen_width.to_be_bytes();
+        let h = self.screen_height.to_be_bytes();
+
+        [
+            self.key_rows,
+            self.key_cols,
+            self.led_count,
+            w[0],
+            w[1],
+            h[0],
+            h[1],
+            self.knob_count,
+            self.pedal_count,
+            self.flash_settings as u8,
+        ]
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn decode(b: &[u8]) -> Result<Self, ()> {
+        if b.len() != 10 || b[9] > 1 {
+            return Err(());
+        }
+
+        Ok(PeripheralDescriptor {
+            key_rows: b[0],
+            key_cols: b[1],
+            led_count: b[2],
+            screen_width: u16::from_be_bytes([b[3], b[4]]),
+            screen_height: u16::from_be_bytes([b[5], b[6]]),
+            knob_count: b[7],
+            pedal_count: b[8],
+            flash_settings: b[9] == 1,
+        })
+    }
+}
+
+// Keypads come with three or four rows of keys
+pub const KEYPAD_MIN_ROWS: u8 = 3;
+pub const KEYPAD_MAX_ROWS: u8 = 4;
+
+// Which board a device is, saved to its flash so it doesn't have to rely on strapping pins. Only
+// keypads have a choice of key rows, the other boards keep it at 0.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct DeviceSettings {
+    pub device_type: DeviceType,
+    pub key_rows: u8,
+}
+impl DeviceSettings {
+    pub fn encode(self) -> [u8; 2] {
+        [self.device_type.ident(), self.key_rows]
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn decode(b: &[u8]) -> Result<Self, ()> {
+        if b.len() != 2 {
+            return Err(());
+        }
+
+        let settings = DeviceSettings {
+            device_type: DeviceType::decode(b[0]),
+            key_rows: b[1],
+        };
+        let valid = match settings.device_type {
+            DeviceType::KeyPad => (KEYPAD_MIN_ROWS..=KEYPAD_MAX_ROWS).contains(&settings.key_rows),
+            DeviceType::KnobPad | DeviceType::PedalPad => settings.key_rows == 0,
+            DeviceType::Unknown => false,
+        };
+        if !valid {
+            return Err(());
+        }
+
+        Ok(settings)
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct PedalConfig {
+    pub analog: [bool; PEDAL_COUNT], // pedal is read through the adc instead of as a switch
+    pub actuation: [u16; PEDAL_COUNT], // travel (out of PEDAL_ANALOG_MAX) where an analog pedal counts as down
+}
+impl PedalConfig {
     pub const fn default() -> Self {
-        KeyInputs {
-            key1: SwitchPosition::default(),
-            key2: SwitchPosition::default(),
-            key3: SwitchPosition::default(),
-            key4: SwitchPosition::default(),
-            key5: SwitchPosition::default(),
-            key6: SwitchPosition::default(),
-            key7: SwitchPosition::default(),
-            key8: SwitchPosition::default(),
-            key9: SwitchPosition::default(),
-            key10: SwitchPosition::default(),
-            key11: SwitchPosition::default(),
-            key12: SwitchPosition::default(),
-            key13: SwitchPosition::default(),
-            key14: SwitchPosition::default(),
-            key15: SwitchPosition::default(),
-            key16: SwitchPosition::default(),
+        PedalConfig {
+            analog: [false; PEDAL_COUNT],
+            actuation: [PEDAL_ANALOG_MAX / 2; PEDAL_COUNT],
         }
     }
 
     #[bitmatch]
-    pub fn encode(self) -> [u8; 3] {
-        let p: u8 = self.key16.into();
-        let o: u8 = self.key15.into();
-        let n: u8 = self.key14.into();
-        let m: u8 = self.key13.into();
-        let l: u8 = self.key12.into();
-        let k: u8 = self.key11.into();
-        let j: u8 = self.key10.into();
-        let i: u8 = self.key9.into();
-        let h: u8 = self.key8.into();
-        let g: u8 = self.key7.into();
-        let f: u8 = self.key6.into();
-        let e: u8 = self.key5.into();
-        let d: u8 = self.key4.into();
-        let c: u8 = self.key3.into();
-        let b: u8 = self.key2.into();
-        let a: u8 = self.key1.into();
-
-        [IDENT_KEY_INPUT, bitpack!("ponmlkji"), bitpack!("hgfedcba")]
+    pub fn encode(self) -> [u8; 7] {
+        let l = self.analog[0] as u8;
+        let m = self.analog[1] as u8;
+        let r = self.analog[2] as u8;
+        let a = self.actuation.map(|a| a.to_be_bytes());
+
+        [
+            bitpack!("00000lmr"),
+            a[0][0],
+            a[0][1],
+            a[1][0],
+            a[1][1],
+            a[2][0],
+            a[2][1],
+        ]
     }
 
     #[bitmatch]
+    #[allow(clippy::result_unit_err)]
     pub fn decode(b: &[u8]) -> Result<Self, ()> {
-        if b.len() != 3
-            || *b.get(0).unwrap_or(&b'\0') != IDENT_KEY_INPUT
-            || b.get(1).is_none()
-            || b.get(2).is_none()
-        {
+        if b.len() != 7 {
             return Err(());
         }
 
-        let w1 = *b.get(1).unwrap();
-        let w2 = *b.get(2).unwrap();
-        let w: u16 = (w1 as u16) << 8 | w2 as u16;
+        let w = |i: usize| u16::from_be_bytes([b[i], b[i + 1]]);
+        let actuation = [w(1), w(3), w(5)];
+        if actuation.iter().any(|a| *a > PEDAL_ANALOG_MAX) {
+            return Err(());
+        }
 
         #[bitmatch]
-        match w {
-            "ponmlkjihgfedcba" => Ok(KeyInputs {
-                key1: a.into(),
-                key2: b.into(),
-                key3: c.into(),
-                key4: d.into(),
-                key5: e.into(),
-                key6: f.into(),
-                key7: g.into(),
-                key8: h.into(),
-                key9: i.into(),
-                key10: j.into(),
-                key11: k.into(),
-                key12: l.into(),
-                key13: m.into(),
-                key14: n.into(),
-                key15: o.into(),
-                key16: p.into(),
+        match b[0] {
+            "00000lmr" => Ok(PedalConfig {
+                analog: [l == 1, m == 1, r == 1],
+                actuation,
             }),
             _ => Err(()),
         }
     }
 }
-impl Into<KeyInputs> for [bool; 16] {
-    fn into(self) -> KeyInputs {
-        KeyInputs {
-            key1: self[0].into(),
-            key2: self[1].into(),
-            key3: self[2].into(),
-            key4: self[3].into(),
-            key5: self[4].into(),
-            key6: self[5].into(),
-            key7: self[6].into(),
-            key8: self[7].into(),
-            key9: self[8].into(),
-            key10: self[9].into(),
-            key11: self[10].into(),
-            key12: self[11].into(),
-            key13: self[12].into(),
-            key14: self[13].into(),
-            key15: self[14].into(),
-            key16: self[15].into(),
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ScreenRotation {
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+impl ScreenRotation {
+    pub const ALL: [ScreenRotation; 4] = [Self::Deg0, Self::Deg90, Self::Deg180, Self::Deg270];
+
+    pub fn degrees(self) -> u16 {
+        match self {
+            Self::Deg0 => 0,
+            Self::Deg90 => 90,
+            Self::Deg180 => 180,
+            Self::Deg270 => 270,
+        }
+    }
+
+    pub fn from_degrees(d: u16) -> Self {
+        match d % 360 {
+            90 => Self::Deg90,
+            180 => Self::Deg180,
+            270 => Self::Deg270,
+            _ => Self::Deg0,
         }
     }
 }
 
-#[derive(PartialEq, Clone, Copy)]
-pub enum KnobDirection {
-    None,
-    Clockwise,
-    CounterClockwise,
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct ScreenOrientation {
+    pub rotation: ScreenRotation, // clockwise, from the panel's native portrait
+    pub mirrored: bool,           // flipped left to right, after rotating
 }
-impl KnobDirection {
+impl ScreenOrientation {
     pub const fn default() -> Self {
-        KnobDirection::None
+        ScreenOrientation {
+            rotation: ScreenRotation::Deg0,
+            mirrored: false,
+        }
     }
 
-    pub fn is_clockwise(self) -> bool {
-        match self {
-            Self::Clockwise => true,
-            _ => false,
-        }
+    // width and height trade places when the screen is on its side
+    pub fn swaps_axes(self) -> bool {
+        matches!(
+            self.rotation,
+            ScreenRotation::Deg90 | ScreenRotation::Deg270
+        )
     }
 
-    pub fn is_counter_clockwise(self) -> bool {
-        match self {
-            Self::CounterClockwise => true,
-            _ => false,
+    #[bitmatch]
+    pub fn encode(self) -> [u8; 1] {
+        let m = self.mirrored as u8;
+        let r = match self.rotation {
+            ScreenRotation::Deg0 => 0,
+            ScreenRotation::Deg90 => 1,
+            ScreenRotation::Deg180 => 2,
+            ScreenRotation::Deg270 => 3,
+        };
+
+        [bitpack!("00000mrr")]
+    }
+
+    #[bitmatch]
+    #[allow(clippy::result_unit_err)]
+    pub fn decode(b: &[u8]) -> Result<Self, ()> {
+        if b.len() != 1 {
+            return Err(());
+        }
+
+        #[bitmatch]
+        match b[0] {
+            "00000mrr" => Ok(ScreenOrientation {
+                rotation: ScreenRotation::ALL[r as usize],
+                mirrored: m == 1,
+            }),
+            _ => Err(()),
         }
     }
 }
-impl Into<KnobDirection> for u8 {
-    fn into(self) -> KnobDirection {
-        match self {
-            0b01 => KnobDirection::Clockwise,
-            0b10 => KnobDirection::CounterClockwise,
-            _ => KnobDirection::None,
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct ScreenPowerConfig {
+    pub brightness: u8,     // backlight while in use
+    pub dim_brightness: u8, // backlight once dimmed
+    pub dim_timeout: u16,   // seconds without a key press before dimming, 0 never dims
+    pub sleep_timeout: u16, // seconds without a key press before the panel sleeps, 0 never sleeps
+}
+impl ScreenPowerConfig {
+    pub const fn default() -> Self {
+        ScreenPowerConfig {
+            brightness: 255,
+            dim_brightness: 40,
+            dim_timeout: 60,
+            sleep_timeout: 600,
         }
     }
-}
-impl Into<u8> for KnobDirection {
-    fn into(self) -> u8 {
-        match self {
-            Self::None => 0b00,
-            Self::Clockwise => 0b01,
-            Self::CounterClockwise => 0b10,
+
+    pub fn encode(self) -> [u8; 6] {
+        let d = self.dim_timeout.to_be_bytes();
+        let s = self.sleep_timeout.to_be_bytes();
+        [self.brightness, self.dim_brightness, d[0], d[1], s[0], s[1]]
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn decode(b: &[u8]) -> Result<Self, ()> {
+        if b.len() != 6 {
+            return Err(());
         }
+
+        Ok(ScreenPowerConfig {
+            brightness: b[0],
+            dim_brightness: b[1],
+            dim_timeout: u16::from_be_bytes([b[2], b[3]]),
+            sleep_timeout: u16::from_be_bytes([b[4], b[5]]),
+        })
     }
 }
 
-#[derive(Clone, Copy)]
-pub struct KnobInputs {
-    pub left_switch: SwitchPosition,
-    pub left_direction: KnobDirection,
-    pub right_switch: SwitchPosition,
-    pub right_direction: KnobDirection,
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct RgbConfig {
+    pub brightness: u8,     // as it looks, before gamma correction
+    pub current_limit: u16, // mA for all the leds together, 0 for no limit
 }
-impl KnobInputs {
+impl RgbConfig {
     pub const fn default() -> Self {
-        KnobInputs {
-            left_switch: SwitchPosition::default(),
-            left_direction: KnobDirection::default(),
-            right_switch: SwitchPosition::default(),
-            right_direction: KnobDirection::default(),
+        RgbConfig {
+            brightness: 128,
+            // leaves room under a usb 2.0 port's 500mA for the rest of the board
+            current_limit: 300,
+        }
+    }
+
+    pub fn encode(self) -> [u8; 3] {
+        let l = self.current_limit.to_be_bytes();
+        [self.brightness, l[0], l[1]]
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn decode(b: &[u8]) -> Result<Self, ()> {
+        if b.len() != 3 {
+            return Err(());
         }
+
+        Ok(RgbConfig {
+            brightness: b[0],
+            current_limit: u16::from_be_bytes([b[1], b[2]]),
+        })
+    }
+}
+
+// the longest prefix of `s` that fits in `max` bytes without splitting a char
+fn truncated_len(s: &str, max: usize) -> usize {
+    let mut len = s.len().min(max);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    len
+}
+
+pub const SCREEN_TEXT_HEADER_SIZE: usize = 13;
+pub const SCREEN_TEXT_MAX_LEN: usize = 128;
+
+// Text shown in a region of the screen. The region is in screen pixels, after rotating, and
+// colors are rgb565. Texts draw over whatever is already there, so several can make up a
+// layout, and a text with an empty region puts the screen back to its usual animation.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct ScreenText {
+    pub x: u16,
+    pub y: u16,
+    pub w: u16,
+    pub h: u16,
+    pub color: u16,
+    pub background: Option<u16>,
+    pub size: TextSize,
+    pub align: TextAlign,
+    pub wrap: bool,
+    text: [u8; SCREEN_TEXT_MAX_LEN],
+    len: usize,
+}
+impl ScreenText {
+    /// White text, wrapped and left aligned. Text past SCREEN_TEXT_MAX_LEN bytes is cut off.
+    pub fn new(x: u16, y: u16, w: u16, h: u16, text: &str) -> Self {
+        let len = truncated_len(text, SCREEN_TEXT_MAX_LEN);
+
+        let mut t = ScreenText {
+            x,
+            y,
+            w,
+            h,
+            color: 0xFFFF,
+            background: None,
+            size: TextSize::Small,
+            align: TextAlign::Left,
+            wrap: true,
+            text: [0u8; SCREEN_TEXT_MAX_LEN],
+            len,
+        };
+        t.text[..len].copy_from_slice(&text.as_bytes()[..len]);
+        t
+    }
+
+    pub const fn clear() -> Self {
+        ScreenText {
+            x: 0,
+            y: 0,
+            w: 0,
+            h: 0,
+            color: 0xFFFF,
+            background: None,
+            size: TextSize::Small,
+            align: TextAlign::Left,
+            wrap: true,
+            text: [0u8; SCREEN_TEXT_MAX_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn is_clear(&self) -> bool {
+        self.w == 0 || self.h == 0
+    }
+
+    pub fn text(&self) -> &str {
+        // only ever filled from a str, or checked when decoded
+        core::str::from_utf8(&self.text[..self.len]).unwrap_or("")
     }
 
     #[bitmatch]
-    pub fn encode(self) -> [u8; 2] {
-        let l: u8 = self.left_switch.into();
-        let d: u8 = self.left_direction.into();
-        let r: u8 = self.right_switch.into();
-        let b: u8 = self.right_direction.into();
+    #[allow(clippy::result_unit_err)]
+    pub fn encode(&self, b: &mut [u8]) -> Result<usize, ()> {
+        let size = SCREEN_TEXT_HEADER_SIZE + self.len;
+        if b.len() < size {
+            return Err(());
+        }
+
+        let w = self.wrap as u8;
+        let g = self.background.is_some() as u8;
+        let a = match self.align {
+            TextAlign::Left => 0,
+            TextAlign::Center => 1,
+            TextAlign::Right => 2,
+        };
+        let s = match self.size {
+            TextSize::Small => 0,
+            TextSize::Medium => 1,
+            TextSize::Large => 2,
+        };
+
+        let fields = [
+            self.x,
+            self.y,
+            self.w,
+            self.h,
+            self.color,
+            self.background.unwrap_or(0),
+        ];
+        for (i, f) in fields.iter().enumerate() {
+            b[i * 2..i * 2 + 2].copy_from_slice(&f.to_be_bytes());
+        }
+        b[12] = bitpack!("wgaa00ss");
+        b[SCREEN_TEXT_HEADER_SIZE..size].copy_from_slice(&self.text[..self.len]);
 
-        [IDENT_KNOB_INPUT, bitpack!("00lddrbb")]
+        Ok(size)
     }
 
     #[bitmatch]
+    #[allow(clippy::result_unit_err)]
     pub fn decode(b: &[u8]) -> Result<Self, ()> {
-        if b.len() != 2 || *b.get(0).unwrap_or(&b'\0') != IDENT_KNOB_INPUT || b.get(1).is_none() {
+        if b.len() < SCREEN_TEXT_HEADER_SIZE
+            || b.len() > SCREEN_TEXT_HEADER_SIZE + SCREEN_TEXT_MAX_LEN
+        {
             return Err(());
         }
 
-        let w = b.get(1).unwrap();
+        let field = |i: usize| u16::from_be_bytes([b[i * 2], b[i * 2 + 1]]);
+        let text = core::str::from_utf8(&b[SCREEN_TEXT_HEADER_SIZE..]).map_err(|_| ())?;
+        let mut t = ScreenText::new(field(0), field(1), field(2), field(3), text);
+        t.color = field(4);
 
         #[bitmatch]
-        match w {
-            "00lddrbb" => Ok(KnobInputs {
-                left_switch: l.into(),
-                left_direction: match d {
-                    0b00 => KnobDirection::None,
-                    0b01 => KnobDirection::Clockwise,
-                    0b10 => KnobDirection::CounterClockwise,
-                    _ => return Err(()),
-                },
-                right_switch: r.into(),
-                right_direction: match b {
-                    0b00 => KnobDirection::None,
-                    0b01 => KnobDirection::Clockwise,
-                    0b10 => KnobDirection::CounterClockwise,
-                    _ => return Err(()),
-                },
-            }),
-            _ => Err(()),
+        match b[12] {
+            "wgaa00ss" => {
+                if a as usize >= TextAlign::ALL.len() || s as usize >= TextSize::ALL.len() {
+                    return Err(());
+                }
+                t.background = (g == 1).then(|| field(5));
+                t.align = TextAlign::ALL[a as usize];
+                t.size = TextSize::ALL[s as usize];
+                t.wrap = w == 1;
+            }
+            _ => return Err(()),
         }
+
+        Ok(t)
     }
 }
 
-#[derive(Clone, Copy)]
-pub struct PedalInputs {
-    pub left: SwitchPosition,
-    pub middle: SwitchPosition,
-    pub right: SwitchPosition,
+pub const KEY_LABELS_TITLE_MAX_LEN: usize = 24;
+pub const KEY_LABEL_MAX_LEN: usize = 16;
+pub const MAX_KEY_LABELS: usize = 16;
+pub const KEY_LABELS_MAX_SIZE: usize =
+    3 + KEY_LABELS_TITLE_MAX_LEN + MAX_KEY_LABELS * (1 + KEY_LABEL_MAX_LEN);
+
+// What each key does in the current profile, drawn on the screen as a grid like the keys. Keys
+// are numbered like the key switches, left to right then top to bottom. Encoded as: rows, cols,
+// title len, title, then a len and label for each key. Labels with no rows put the screen back
+// to its usual animation.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct KeyLabels {
+    pub rows: u8,
+    pub cols: u8,
+    title: [u8; KEY_LABELS_TITLE_MAX_LEN],
+    title_len: usize,
+    labels: [[u8; KEY_LABEL_MAX_LEN]; MAX_KEY_LABELS],
+    label_lens: [usize; MAX_KEY_LABELS],
 }
-impl PedalInputs {
-    pub const fn default() -> Self {
-        PedalInputs {
-            left: SwitchPosition::default(),
-            middle: SwitchPosition::default(),
-            right: SwitchPosition::default(),
+impl KeyLabels {
+    /// Blank labels for a grid of keys. Titles and labels too long to fit are cut off.
+    pub fn new(rows: u8, cols: u8, title: &str) -> Self {
+        let mut l = KeyLabels::clear();
+        if rows as usize * cols as usize > MAX_KEY_LABELS {
+            return l;
         }
+
+        l.rows = rows;
+        l.cols = cols;
+        l.title_len = truncated_len(title, KEY_LABELS_TITLE_MAX_LEN);
+        l.title[..l.title_len].copy_from_slice(&title.as_bytes()[..l.title_len]);
+        l
     }
 
-    #[bitmatch]
-    pub fn encode(self) -> [u8; 2] {
-        let l: u8 = self.left.into();
-        let m: u8 = self.middle.into();
-        let r: u8 = self.right.into();
+    pub const fn clear() -> Self {
+        KeyLabels {
+            rows: 0,
+            cols: 0,
+            title: [0u8; KEY_LABELS_TITLE_MAX_LEN],
+            title_len: 0,
+            labels: [[0u8; KEY_LABEL_MAX_LEN]; MAX_KEY_LABELS],
+            label_lens: [0; MAX_KEY_LABELS],
+        }
+    }
 
-        [IDENT_PEDAL_INPUT, bitpack!("00000lmr")]
+    pub fn is_clear(&self) -> bool {
+        self.key_count() == 0
     }
 
-    #[bitmatch]
+    pub fn key_count(&self) -> usize {
+        self.rows as usize * self.cols as usize
+    }
+
+    pub fn title(&self) -> &str {
+        core::str::from_utf8(&self.title[..self.title_len]).unwrap_or("")
+    }
+
+    pub fn label(&self, key: usize) -> &str {
+        if key >= self.key_count() {
+            return "";
+        }
+        core::str::from_utf8(&self.labels[key][..self.label_lens[key]]).unwrap_or("")
+    }
+
+    pub fn set_label(&mut self, key: usize, label: &str) {
+        if key >= self.key_count() {
+            return;
+        }
+        let len = truncated_len(label, KEY_LABEL_MAX_LEN);
+        self.labels[key][..len].copy_from_slice(&label.as_bytes()[..len]);
+        self.label_lens[key] = len;
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn encode(&self, b: &mut [u8]) -> Result<usize, ()> {
+        if b.len() < KEY_LABELS_MAX_SIZE {
+            return Err(());
+        }
+
+        b[0] = self.rows;
+        b[1] = self.cols;
+        b[2] = self.title_len as u8;
+        let mut i = 3;
+        b[i..i + self.title_len].copy_from_slice(&self.title[..self.title_len]);
+        i += self.title_len;
+
+        for key in 0..self.key_count() {
+            let len = self.label_lens[key];
+            b[i] = len as u8;
+            b[i + 1..i + 1 + len].copy_from_slice(&self.labels[key][..len]);
+            i += 1 + len;
+        }
+
+        Ok(i)
+    }
+
+    #[allow(clippy::result_unit_err)]
     pub fn decode(b: &[u8]) -> Result<Self, ()> {
-        if b.len() != 2 || *b.get(0).unwrap_or(&b'\0') != IDENT_PEDAL_INPUT {
+        // takes `len` bytes of text from the front of `b`, as long as they're all there
+        fn take_str(b: &[u8], len: usize) -> Result<&str, ()> {
+            let s = b.get(..len).ok_or(())?;
+            core::str::from_utf8(s).map_err(|_| ())
+        }
+
+        if b.len() < 3 || b[0] as usize * b[1] as usize > MAX_KEY_LABELS {
+            return Err(());
+        }
+        if b[2] as usize > KEY_LABELS_TITLE_MAX_LEN {
             return Err(());
         }
 
-        let w = b.get(1);
-        if w.is_none() {
+        let mut l = KeyLabels::new(b[0], b[1], take_str(&b[3..], b[2] as usize)?);
+        let mut i = 3 + b[2] as usize;
+        for key in 0..l.key_count() {
+            let len = *b.get(i).ok_or(())? as usize;
+            if len > KEY_LABEL_MAX_LEN {
+                return Err(());
+            }
+            l.set_label(key, take_str(&b[i + 1..], len)?);
+            i += 1 + len;
+        }
+
+        if i != b.len() {
             return Err(());
         }
-        let w = *w.unwrap();
+        Ok(l)
+    }
+}
 
-        #[bitmatch]
-        match w {
-            "00000lmr" => Ok(PedalInputs {
-                left: l.into(),
-                middle: m.into(),
-                right: r.into(),
-            }),
-            _ => Err(()),
+// Inputs are reported as a list of blocks, each starting with its identifier and the length of
+// the data that follows: ident, len, data. A board describes itself by the blocks it sends, so
+// boards with more keys, or a mix of keys and knobs, don't need a report of their own.
+pub const IDENT_KEY_BLOCK: u8 = b'k';
+pub const IDENT_ENCODER_BLOCK: u8 = b'e';
+pub const IDENT_AXIS_BLOCK: u8 = b'a';
+
+pub const BLOCK_HEADER_SIZE: usize = 2;
+pub const MAX_KEYS: usize = 32;
+pub const MAX_ENCODERS: usize = 4;
+pub const MAX_AXES: usize = 8;
+pub const MAX_INPUT_BLOCKS: usize = 4;
+// encoder blocks are the largest, so this fits any mix of blocks
+pub const INPUT_REPORT_MAX_SIZE: usize =
+    MAX_INPUT_BLOCKS * (BLOCK_HEADER_SIZE + 1 + MAX_ENCODERS * 5);
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct KeyBlock {
+    pub count: u8,
+    pub pressed: u32, // key 1 is bit 0
+}
+impl KeyBlock {
+    pub const fn new(count: u8) -> Self {
+        KeyBlock { count, pressed: 0 }
+    }
+
+    pub fn is_down(&self, key: usize) -> bool {
+        key < self.count as usize && self.pressed & (1 << key) != 0
+    }
+
+    pub fn set(&mut self, key: usize, down: bool) {
+        if key >= self.count as usize {
+            return;
+        }
+
+        if down {
+            self.pressed |= 1 << key;
+        } else {
+            self.pressed &= !(1 << key);
+        }
+    }
+
+    pub fn set_all(&mut self, keys: &[bool]) {
+        for (n, k) in keys.iter().enumerate() {
+            self.set(n, *k);
+        }
+    }
+
+    fn data_len(&self) -> usize {
+        1 + (self.count as usize).div_ceil(8)
+    }
+
+    fn encode_data(&self, b: &mut [u8]) {
+        b[0] = self.count;
+        for (n, w) in b[1..self.data_len()].iter_mut().enumerate() {
+            *w = (self.pressed >> (n * 8)) as u8;
+        }
+    }
+
+    fn decode_data(b: &[u8]) -> Result<Self, ()> {
+        let count = *b.first().ok_or(())?;
+        let block = KeyBlock::new(count);
+        if count as usize > MAX_KEYS || b.len() != block.data_len() {
+            return Err(());
+        }
+
+        let pressed = b[1..]
+            .iter()
+            .enumerate()
+            .fold(0u32, |p, (n, w)| p | (*w as u32) << (n * 8));
+
+        Ok(KeyBlock { count, pressed })
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Encoder {
+    pub switch: SwitchPosition,
+    pub steps: i16,    // detents turned since the last read, positive is clockwise
+    pub velocity: u16, // detents per second, 0 if the knob is idle or it hasn't been measured
+}
+impl Encoder {
+    pub const fn default() -> Self {
+        Encoder {
+            switch: SwitchPosition::default(),
+            steps: 0,
+            velocity: 0,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct EncoderBlock {
+    pub count: u8,
+    pub encoders: [Encoder; MAX_ENCODERS],
+}
+impl EncoderBlock {
+    pub const fn new(count: u8) -> Self {
+        EncoderBlock {
+            count,
+            encoders: [Encoder::default(); MAX_ENCODERS],
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Encoder> {
+        self.encoders.iter().take(self.count as usize)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Encoder> {
+        self.encoders.iter_mut().take(self.count as usize)
+    }
+
+    fn data_len(&self) -> usize {
+        1 + self.count as usize * 5
+    }
+
+    fn encode_data(&self, b: &mut [u8]) {
+        b[0] = self.count;
+        for (e, w) in self.iter().zip(b[1..].chunks_exact_mut(5)) {
+            let s = e.steps.to_be_bytes();
+            let v = e.velocity.to_be_bytes();
+            w.copy_from_slice(&[e.switch.into(), s[0], s[1], v[0], v[1]]);
+        }
+    }
+
+    fn decode_data(b: &[u8]) -> Result<Self, ()> {
+        let count = *b.first().ok_or(())?;
+        let mut block = EncoderBlock::new(count);
+        if count as usize > MAX_ENCODERS || b.len() != block.data_len() {
+            return Err(());
+        }
+
+        for (e, w) in block.encoders.iter_mut().zip(b[1..].chunks_exact(5)) {
+            *e = Encoder {
+                switch: w[0].into(),
+                steps: i16::from_be_bytes([w[1], w[2]]),
+                velocity: u16::from_be_bytes([w[3], w[4]]),
+            };
+        }
+
+        Ok(block)
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct AxisBlock {
+    pub count: u8,
+    pub axes: [u16; MAX_AXES],
+}
+impl AxisBlock {
+    pub const fn new(count: u8) -> Self {
+        AxisBlock {
+            count,
+            axes: [0; MAX_AXES],
+        }
+    }
+
+    fn data_len(&self) -> usize {
+        1 + self.count as usize * 2
+    }
+
+    fn encode_data(&self, b: &mut [u8]) {
+        b[0] = self.count;
+        for (a, w) in self
+            .axes
+            .iter()
+            .zip(b[1..self.data_len()].chunks_exact_mut(2))
+        {
+            w.copy_from_slice(&a.to_be_bytes());
+        }
+    }
+
+    fn decode_data(b: &[u8]) -> Result<Self, ()> {
+        let count = *b.first().ok_or(())?;
+        let mut block = AxisBlock::new(count);
+        if count as usize > MAX_AXES || b.len() != block.data_len() {
+            return Err(());
+        }
+
+        for (a, w) in block.axes.iter_mut().zip(b[1..].chunks_exact(2)) {
+            *a = u16::from_be_bytes([w[0], w[1]]);
+        }
+
+        Ok(block)
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum InputBlock {
+    Keys(KeyBlock),
+    Encoders(EncoderBlock),
+    Axes(AxisBlock),
+}
+impl InputBlock {
+    pub fn ident(&self) -> u8 {
+        match self {
+            Self::Keys(_) => IDENT_KEY_BLOCK,
+            Self::Encoders(_) => IDENT_ENCODER_BLOCK,
+            Self::Axes(_) => IDENT_AXIS_BLOCK,
+        }
+    }
+
+    fn data_len(&self) -> usize {
+        match self {
+            Self::Keys(b) => b.data_len(),
+            Self::Encoders(b) => b.data_len(),
+            Self::Axes(b) => b.data_len(),
+        }
+    }
+
+    // Writes the block into b, returning how many bytes were used.
+    #[allow(clippy::result_unit_err)]
+    pub fn encode(&self, b: &mut [u8]) -> Result<usize, ()> {
+        let len = self.data_len();
+        if b.len() < BLOCK_HEADER_SIZE + len {
+            return Err(());
+        }
+
+        b[0] = self.ident();
+        b[1] = len as u8;
+        let data = &mut b[BLOCK_HEADER_SIZE..BLOCK_HEADER_SIZE + len];
+        match self {
+            Self::Keys(k) => k.encode_data(data),
+            Self::Encoders(e) => e.encode_data(data),
+            Self::Axes(a) => a.encode_data(data),
+        }
+
+        Ok(BLOCK_HEADER_SIZE + len)
+    }
+
+    // Reads the block at the start of b, returning it and how many bytes it used.
+    #[allow(clippy::result_unit_err)]
+    pub fn decode(b: &[u8]) -> Result<(Self, usize), ()> {
+        if b.len() < BLOCK_HEADER_SIZE {
+            return Err(());
+        }
+
+        let size = BLOCK_HEADER_SIZE + b[1] as usize;
+        let data = b.get(BLOCK_HEADER_SIZE..size).ok_or(())?;
+        let block = match b[0] {
+            IDENT_KEY_BLOCK => Self::Keys(KeyBlock::decode_data(data)?),
+            IDENT_ENCODER_BLOCK => Self::Encoders(EncoderBlock::decode_data(data)?),
+            IDENT_AXIS_BLOCK => Self::Axes(AxisBlock::decode_data(data)?),
+            _ => return Err(()),
+        };
+
+        Ok((block, size))
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct InputReport {
+    blocks: [Option<InputBlock>; MAX_INPUT_BLOCKS],
+}
+impl Default for InputReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl InputReport {
+    pub const fn new() -> Self {
+        InputReport {
+            blocks: [None; MAX_INPUT_BLOCKS],
+        }
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn push(&mut self, block: InputBlock) -> Result<(), ()> {
+        let slot = self.blocks.iter_mut().find(|b| b.is_none()).ok_or(())?;
+        *slot = Some(block);
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &InputBlock> {
+        self.blocks.iter().flatten()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut InputBlock> {
+        self.blocks.iter_mut().flatten()
+    }
+
+    // First block of each kind, which is all the current boards have
+    pub fn keys(&self) -> Option<&KeyBlock> {
+        self.iter().find_map(|b| match b {
+            InputBlock::Keys(k) => Some(k),
+            _ => None,
+        })
+    }
+
+    pub fn keys_mut(&mut self) -> Option<&mut KeyBlock> {
+        self.iter_mut().find_map(|b| match b {
+            InputBlock::Keys(k) => Some(k),
+            _ => None,
+        })
+    }
+
+    pub fn encoders(&self) -> Option<&EncoderBlock> {
+        self.iter().find_map(|b| match b {
+            InputBlock::Encoders(e) => Some(e),
+            _ => None,
+        })
+    }
+
+    pub fn encoders_mut(&mut self) -> Option<&mut EncoderBlock> {
+        self.iter_mut().find_map(|b| match b {
+            InputBlock::Encoders(e) => Some(e),
+            _ => None,
+        })
+    }
+
+    pub fn axes(&self) -> Option<&AxisBlock> {
+        self.iter().find_map(|b| match b {
+            InputBlock::Axes(a) => Some(a),
+            _ => None,
+        })
+    }
+
+    pub fn axes_mut(&mut self) -> Option<&mut AxisBlock> {
+        self.iter_mut().find_map(|b| match b {
+            InputBlock::Axes(a) => Some(a),
+            _ => None,
+        })
+    }
+
+    // Writes every block into b, returning how many bytes were used.
+    #[allow(clippy::result_unit_err)]
+    pub fn encode(&self, b: &mut [u8]) -> Result<usize, ()> {
+        let mut size = 0;
+        for block in self.iter() {
+            size += block.encode(&mut b[size..])?;
+        }
+        Ok(size)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn decode(b: &[u8]) -> Result<Self, ()> {
+        let mut report = InputReport::new();
+        let mut i = 0;
+        while i < b.len() {
+            let (block, size) = InputBlock::decode(&b[i..])?;
+            report.push(block)?;
+            i += size;
         }
+        Ok(report)
     }
 }
 
-#[derive(Clone, Copy)]
-pub enum JBInputs {
-    KeyPad(KeyInputs),
-    KnobPad(KnobInputs),
-    PedalPad(PedalInputs),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn knob_report() -> InputReport {
+        let mut keys = KeyBlock::new(12);
+        keys.set_all(&[
+            true, false, false, true, false, false, false, false, false, false, true,
+        ]);
+        let mut encoders = EncoderBlock::new(2);
+        encoders.encoders[0] = Encoder {
+            switch: SwitchPosition::Down,
+            steps: -3,
+            velocity: 12,
+        };
+        encoders.encoders[1].steps = i16::MAX;
+        let mut axes = AxisBlock::new(3);
+        axes.axes[..3].copy_from_slice(&[0, 500, PEDAL_ANALOG_MAX]);
+
+        let mut report = InputReport::new();
+        report.push(InputBlock::Keys(keys)).unwrap();
+        report.push(InputBlock::Encoders(encoders)).unwrap();
+        report.push(InputBlock::Axes(axes)).unwrap();
+        report
+    }
+
+    #[test]
+    fn descriptor_round_trip() {
+        let d = PeripheralDescriptor {
+            key_rows: 4,
+            key_cols: 4,
+            led_count: 16,
+            screen_width: 240,
+            screen_height: 320,
+            knob_count: 2,
+            pedal_count: 0,
+            flash_settings: true,
+        };
+        assert_eq!(PeripheralDescriptor::decode(&d.encode()), Ok(d));
+
+        let b = d.encode();
+        assert!(PeripheralDescriptor::decode(&b[..9]).is_err());
+        assert!(PeripheralDescriptor::decode(&[&b[..], &[0]].concat()).is_err());
+        let mut bad = b;
+        bad[9] = 2;
+        assert!(PeripheralDescriptor::decode(&bad).is_err());
+    }
+
+    #[test]
+    fn device_settings_round_trip() {
+        let boards = [
+            (DeviceType::KeyPad, KEYPAD_MIN_ROWS),
+            (DeviceType::KeyPad, KEYPAD_MAX_ROWS),
+            (DeviceType::KnobPad, 0),
+            (DeviceType::PedalPad, 0),
+        ];
+        for (device_type, key_rows) in boards {
+            let s = DeviceSettings {
+                device_type,
+                key_rows,
+            };
+            assert_eq!(DeviceSettings::decode(&s.encode()), Ok(s));
+        }
+
+        assert!(DeviceSettings::decode(&[IDENT_KEY_INPUT]).is_err());
+        assert!(DeviceSettings::decode(&[IDENT_KEY_INPUT, 3, 0]).is_err());
+        assert!(DeviceSettings::decode(&[IDENT_UNKNOWN_INPUT, 0]).is_err());
+        // erased flash
+        assert!(DeviceSettings::decode(&[0xFF, 0xFF]).is_err());
+        assert!(DeviceSettings::decode(&[IDENT_KEY_INPUT, 2]).is_err());
+        assert!(DeviceSettings::decode(&[IDENT_KEY_INPUT, 5]).is_err());
+        assert!(DeviceSettings::decode(&[IDENT_KNOB_INPUT, 3]).is_err());
+    }
+
+    #[test]
+    fn pedal_config_round_trip() {
+        let c = PedalConfig {
+            analog: [true, false, true],
+            actuation: [0, 250, PEDAL_ANALOG_MAX],
+        };
+        assert_eq!(PedalConfig::decode(&c.encode()), Ok(c));
+        assert_eq!(
+            PedalConfig::decode(&PedalConfig::default().encode()),
+            Ok(PedalConfig::default())
+        );
+
+        let b = c.encode();
+        assert!(PedalConfig::decode(&b[..6]).is_err());
+        assert!(PedalConfig::decode(&[&b[..], &[0]].concat()).is_err());
+        let mut bad = b;
+        bad[0] = 0b1000;
+        assert!(PedalConfig::decode(&bad).is_err());
+        let mut bad = b;
+        bad[5..7].copy_from_slice(&(PEDAL_ANALOG_MAX + 1).to_be_bytes());
+        assert!(PedalConfig::decode(&bad).is_err());
+    }
+
+    #[test]
+    fn screen_orientation_round_trip() {
+        for rotation in ScreenRotation::ALL {
+            for mirrored in [false, true] {
+                let o = ScreenOrientation { rotation, mirrored };
+                assert_eq!(ScreenOrientation::decode(&o.encode()), Ok(o));
+            }
+        }
+
+        assert!(ScreenOrientation::decode(&[]).is_err());
+        assert!(ScreenOrientation::decode(&[0, 0]).is_err());
+        assert!(ScreenOrientation::decode(&[0b1000]).is_err());
+    }
+
+    #[test]
+    fn screen_power_round_trip() {
+        let c = ScreenPowerConfig {
+            brightness: 200,
+            dim_brightness: 0,
+            dim_timeout: 0,
+            sleep_timeout: u16::MAX,
+        };
+        assert_eq!(ScreenPowerConfig::decode(&c.encode()), Ok(c));
+
+        let b = c.encode();
+        assert!(ScreenPowerConfig::decode(&b[..5]).is_err());
+        assert!(ScreenPowerConfig::decode(&[&b[..], &[0]].concat()).is_err());
+    }
+
+    #[test]
+    fn rgb_config_round_trip() {
+        let c = RgbConfig {
+            brightness: 255,
+            current_limit: 0,
+        };
+        assert_eq!(RgbConfig::decode(&c.encode()), Ok(c));
+        assert_eq!(
+            RgbConfig::decode(&RgbConfig::default().encode()),
+            Ok(RgbConfig::default())
+        );
+
+        let b = c.encode();
+        assert!(RgbConfig::decode(&b[..2]).is_err());
+        assert!(RgbConfig::decode(&[&b[..], &[0]].concat()).is_err());
+    }
+
+    #[test]
+    fn screen_text_round_trip() {
+        let mut t = ScreenText::new(10, 20, 200, 40, "Now playing: ünïcode");
+        t.color = 0xF800;
+        t.background = Some(0x001F);
+        t.size = TextSize::Large;
+        t.align = TextAlign::Right;
+        t.wrap = false;
+
+        let mut b = [0u8; SCREEN_TEXT_HEADER_SIZE + SCREEN_TEXT_MAX_LEN];
+        let len = t.encode(&mut b).unwrap();
+        assert_eq!(ScreenText::decode(&b[..len]), Ok(t));
+
+        let clear = ScreenText::clear();
+        let len = clear.encode(&mut b).unwrap();
+        assert_eq!(ScreenText::decode(&b[..len]), Ok(clear));
+        assert!(clear.encode(&mut b[..SCREEN_TEXT_HEADER_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn screen_text_rejects_bad_input() {
+        let t = ScreenText::new(0, 0, 100, 100, "hi");
+        let mut b = [0u8; SCREEN_TEXT_HEADER_SIZE + SCREEN_TEXT_MAX_LEN + 1];
+        let len = t.encode(&mut b).unwrap();
+
+        assert!(ScreenText::decode(&b[..SCREEN_TEXT_HEADER_SIZE - 1]).is_err());
+        assert!(ScreenText::decode(&b).is_err());
+
+        let mut bad = b;
+        bad[12] = 0b0000_0011; // no fourth size
+        assert!(ScreenText::decode(&bad[..len]).is_err());
+        let mut bad = b;
+        bad[12] = 0b0011_0000; // no fourth alignment
+        assert!(ScreenText::decode(&bad[..len]).is_err());
+        let mut bad = b;
+        bad[SCREEN_TEXT_HEADER_SIZE] = 0xFF;
+        assert!(ScreenText::decode(&bad[..len]).is_err());
+    }
+
+    #[test]
+    fn key_labels_round_trip() {
+        let mut l = KeyLabels::new(2, 3, "Media");
+        l.set_label(0, "Play / pause");
+        l.set_label(2, "Next");
+        l.set_label(5, "Mute");
+
+        let mut b = [0u8; KEY_LABELS_MAX_SIZE];
+        let len = l.encode(&mut b).unwrap();
+        assert_eq!(KeyLabels::decode(&b[..len]), Ok(l));
+
+        let clear = KeyLabels::clear();
+        let len = clear.encode(&mut b).unwrap();
+        assert_eq!(KeyLabels::decode(&b[..len]), Ok(clear));
+    }
+
+    #[test]
+    fn key_labels_reject_bad_input() {
+        let mut l = KeyLabels::new(1, 2, "Title");
+        l.set_label(1, "Last");
+        let mut b = [0u8; KEY_LABELS_MAX_SIZE];
+        let len = l.encode(&mut b).unwrap();
+
+        for cut in 0..len {
+            assert!(KeyLabels::decode(&b[..cut]).is_err(), "cut at {cut}");
+        }
+        assert!(KeyLabels::decode(&b[..len + 1]).is_err());
+
+        // more keys than there are labels for
+        assert!(KeyLabels::decode(&[5, 4, 0]).is_err());
+        // a title or label longer than the most that's sent
+        let mut bad = [0u8; 3 + KEY_LABELS_TITLE_MAX_LEN + 1];
+        bad[2] = KEY_LABELS_TITLE_MAX_LEN as u8 + 1;
+        assert!(KeyLabels::decode(&bad).is_err());
+        let mut bad = [0u8; 4 + KEY_LABEL_MAX_LEN + 1];
+        bad[..4].copy_from_slice(&[1, 1, 0, KEY_LABEL_MAX_LEN as u8 + 1]);
+        assert!(KeyLabels::decode(&bad).is_err());
+    }
+
+    #[test]
+    fn input_block_round_trip() {
+        let mut b = [0u8; INPUT_REPORT_MAX_SIZE];
+        for block in knob_report().iter() {
+            let len = block.encode(&mut b).unwrap();
+            assert_eq!(InputBlock::decode(&b[..len]), Ok((*block, len)));
+            // a block only takes its own bytes, whatever follows it
+            assert_eq!(InputBlock::decode(&b[..len + 1]), Ok((*block, len)));
+            assert!(block.encode(&mut b[..len - 1]).is_err());
+        }
+
+        let full = [
+            InputBlock::Keys(KeyBlock::new(MAX_KEYS as u8)),
+            InputBlock::Encoders(EncoderBlock::new(MAX_ENCODERS as u8)),
+            InputBlock::Axes(AxisBlock::new(MAX_AXES as u8)),
+        ];
+        for block in full {
+            let len = block.encode(&mut b).unwrap();
+            assert_eq!(InputBlock::decode(&b[..len]), Ok((block, len)));
+        }
+    }
+
+    #[test]
+    fn input_block_rejects_bad_input() {
+        let mut b = [0u8; INPUT_REPORT_MAX_SIZE];
+        for block in knob_report().iter() {
+            let len = block.encode(&mut b).unwrap();
+            for cut in 0..len {
+                assert!(InputBlock::decode(&b[..cut]).is_err(), "cut at {cut}");
+            }
+
+            // a count that doesn't match the length, keys are sent a byte per eight
+            let mut bad = b;
+            bad[BLOCK_HEADER_SIZE] -= match block {
+                InputBlock::Keys(_) => 8,
+                _ => 1,
+            };
+            assert!(InputBlock::decode(&bad[..len]).is_err());
+        }
+
+        // more than a block can hold, even with the bytes for it
+        let mut keys = [0u8; BLOCK_HEADER_SIZE + 1 + 5];
+        keys[..3].copy_from_slice(&[IDENT_KEY_BLOCK, 6, MAX_KEYS as u8 + 1]);
+        assert!(InputBlock::decode(&keys).is_err());
+        let mut encoders = [0u8; BLOCK_HEADER_SIZE + 1 + (MAX_ENCODERS + 1) * 5];
+        let len = encoders.len() - BLOCK_HEADER_SIZE;
+        encoders[..3].copy_from_slice(&[IDENT_ENCODER_BLOCK, len as u8, MAX_ENCODERS as u8 + 1]);
+        assert!(InputBlock::decode(&encoders).is_err());
+        let mut axes = [0u8; BLOCK_HEADER_SIZE + 1 + (MAX_AXES + 1) * 2];
+        let len = axes.len() - BLOCK_HEADER_SIZE;
+        axes[..3].copy_from_slice(&[IDENT_AXIS_BLOCK, len as u8, MAX_AXES as u8 + 1]);
+        assert!(InputBlock::decode(&axes).is_err());
+
+        assert!(InputBlock::decode(&[b'x', 1, 0]).is_err());
+    }
+
+    #[test]
+    fn input_report_round_trip() {
+        let report = knob_report();
+        let mut b = [0u8; INPUT_REPORT_MAX_SIZE];
+        let len = report.encode(&mut b).unwrap();
+        assert_eq!(InputReport::decode(&b[..len]), Ok(report));
+        assert_eq!(InputReport::decode(&[]), Ok(InputReport::new()));
+
+        let keys = InputReport::decode(&b[..len]).unwrap();
+        assert!(keys.keys().unwrap().is_down(10));
+        assert_eq!(keys.encoders().unwrap().encoders[0].steps, -3);
+        assert_eq!(keys.axes().unwrap().axes[2], PEDAL_ANALOG_MAX);
+    }
+
+    #[test]
+    fn input_report_rejects_bad_input() {
+        let report = knob_report();
+        let mut b = [0u8; INPUT_REPORT_MAX_SIZE * 2];
+        let len = report.encode(&mut b).unwrap();
+
+        for cut in 1..len {
+            // cutting between blocks leaves a shorter report that's still good
+            let whole = report
+                .iter()
+                .scan(0, |end, block| {
+                    *end += BLOCK_HEADER_SIZE + block.data_len();
+                    Some(*end)
+                })
+                .any(|end| end == cut);
+            assert_eq!(
+                InputReport::decode(&b[..cut]).is_ok(),
+                whole,
+                "cut at {cut}"
+            );
+        }
+        assert!(InputReport::decode(&b[..len + 1]).is_err());
+
+        // more blocks than a report holds
+        let mut full = InputReport::new();
+        for _ in 0..MAX_INPUT_BLOCKS {
+            full.push(InputBlock::Keys(KeyBlock::new(1))).unwrap();
+        }
+        assert!(full.push(InputBlock::Keys(KeyBlock::new(1))).is_err());
+        let len = full.encode(&mut b).unwrap();
+        let extra = InputBlock::Keys(KeyBlock::new(1))
+            .encode(&mut b[len..])
+            .unwrap();
+        assert!(InputReport::decode(&b[..len]).is_ok());
+        assert!(InputReport::decode(&b[..len + extra]).is_err());
+    }
 }
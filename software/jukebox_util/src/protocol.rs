@@ -2,29 +2,79 @@
 
 pub const CMD_GREET: u8 = b'\x05';
 pub const CMD_GET_INPUT_KEYS: u8 = b'\x30';
+pub const CMD_DIAGNOSTICS: u8 = b'\x31';
+pub const CMD_PEDAL_CONFIG: u8 = b'\x32';
+pub const CMD_GET_PERIPHERALS: u8 = b'\x33';
+pub const CMD_SCREEN_ORIENTATION: u8 = b'\x34';
+pub const CMD_SCREEN_POWER: u8 = b'\x35';
+pub const CMD_SCREEN_TEXT: u8 = b'\x36';
+pub const CMD_KEY_LABELS: u8 = b'\x37';
 pub const CMD_UPDATE: u8 = b'\x38';
 pub const CMD_DISCONNECT: u8 = b'\x39';
+pub const CMD_ANIMATION_FRAME: u8 = b'\x3A';
+pub const CMD_RGB_CONFIG: u8 = b'\x3B';
+pub const CMD_HID_SEQUENCE: u8 = b'\x3C';
+pub const CMD_DEVICE_SETTINGS: u8 = b'\x3D';
 pub const CMD_NEGATIVE_ACK: u8 = b'\x15';
 pub const CMD_UNKNOWN: u8 = b'?';
 
 pub const CMD_DEVICE: u8 = b'U';
 pub const CMD_END: &[u8] = b"\r\n";
 
+// Commands that carry a payload are framed as: cmd, len (u16, big endian), payload, CMD_END
+pub const CMD_PAYLOAD_HEADER_SIZE: usize = 3;
+pub const CMD_PAYLOAD_MAX_SIZE: usize = 1024;
+
 pub const RSP_LINK_HEADER: u8 = b'L';
 pub const RSP_LINK_DELIMITER: u8 = b',';
 
+// Input responses are framed as: RSP_INPUT_HEADER, len (u16, big endian), input blocks, RSP_END.
+// Diagnostics, peripheral and config responses are framed the same way around their data.
 pub const RSP_INPUT_HEADER: u8 = b'I';
+pub const RSP_DIAGNOSTICS_HEADER: u8 = b'D';
+pub const RSP_PEDAL_CONFIG_HEADER: u8 = b'C';
+pub const RSP_PERIPHERALS_HEADER: u8 = b'P';
+pub const RSP_SCREEN_ORIENTATION_HEADER: u8 = b'O';
+pub const RSP_SCREEN_POWER_HEADER: u8 = b'B';
+pub const RSP_SCREEN_TEXT_HEADER: u8 = b'T';
+pub const RSP_KEY_LABELS_HEADER: u8 = b'K';
+// Animation frame responses carry one byte after the header, 0 if the device was busy and the
+// chunk needs sending again
+pub const RSP_ANIMATION_FRAME_HEADER: u8 = b'A';
+pub const RSP_RGB_CONFIG_HEADER: u8 = b'R';
+// HID sequence responses carry one byte after the header, 0 if the device's queue was full and the
+// sequence needs sending again
+pub const RSP_HID_SEQUENCE_HEADER: u8 = b'H';
+// Device settings are echoed like the configs, then the device saves them and restarts
+pub const RSP_DEVICE_SETTINGS_HEADER: u8 = b'S';
 
 pub const RSP_UNKNOWN: u8 = b'?';
 pub const RSP_DISCONNECTED: u8 = b'\x04';
 
 pub const RSP_END: &[u8] = b"\r\n\r\n";
 
+pub const RESET_REASON_POWER_ON: u8 = b'P';
+pub const RESET_REASON_SOFTWARE: u8 = b'S';
+pub const RESET_REASON_CORE0_STALL: u8 = b'0';
+pub const RESET_REASON_CORE1_STALL: u8 = b'1';
+pub const RESET_REASON_UNKNOWN: u8 = b'?';
+
 #[derive(PartialEq, Clone, Copy, Debug)]
 #[repr(u8)]
 pub enum Command {
     Greeting,
     GetInputKeys,
+    Diagnostics,
+    PedalConfig,
+    GetPeripherals,
+    ScreenOrientation,
+    ScreenPower,
+    ScreenText,
+    KeyLabels,
+    AnimationFrame,
+    RgbConfig,
+    HidSequence,
+    DeviceSettings,
     Update,
     Disconnect,
     NegativeAck,
@@ -36,14 +86,129 @@ impl Command {
             Self::Greeting
         } else if w == CMD_GET_INPUT_KEYS {
             Self::GetInputKeys
+        } else if w == CMD_DIAGNOSTICS {
+            Self::Diagnostics
+        } else if w == CMD_PEDAL_CONFIG {
+            Self::PedalConfig
+        } else if w == CMD_GET_PERIPHERALS {
+            Self::GetPeripherals
+        } else if w == CMD_SCREEN_ORIENTATION {
+            Self::ScreenOrientation
+        } else if w == CMD_SCREEN_POWER {
+            Self::ScreenPower
+        } else if w == CMD_SCREEN_TEXT {
+            Self::ScreenText
+        } else if w == CMD_KEY_LABELS {
+            Self::KeyLabels
+        } else if w == CMD_ANIMATION_FRAME {
+            Self::AnimationFrame
+        } else if w == CMD_RGB_CONFIG {
+            Self::RgbConfig
+        } else if w == CMD_HID_SEQUENCE {
+            Self::HidSequence
+        } else if w == CMD_DEVICE_SETTINGS {
+            Self::DeviceSettings
         } else if w == CMD_UPDATE {
             Self::Update
         } else if w == CMD_DISCONNECT {
-            Self::Update
+            Self::Disconnect
         } else if w == CMD_NEGATIVE_ACK {
             Self::NegativeAck
         } else {
             Self::Unknown
         }
     }
+
+    pub fn has_payload(self) -> bool {
+        matches!(
+            self,
+            Self::Diagnostics
+                | Self::PedalConfig
+                | Self::ScreenOrientation
+                | Self::ScreenPower
+                | Self::ScreenText
+                | Self::KeyLabels
+                | Self::AnimationFrame
+                | Self::RgbConfig
+                | Self::HidSequence
+                | Self::DeviceSettings
+        )
+    }
+}
+
+pub fn encode_payload_header(cmd: u8, len: usize) -> [u8; CMD_PAYLOAD_HEADER_SIZE] {
+    [cmd, (len >> 8) as u8, len as u8]
+}
+
+pub fn decode_payload_len(w1: u8, w2: u8) -> usize {
+    (w1 as usize) << 8 | w2 as usize
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ResetReason {
+    PowerOn,
+    Software,
+    Core0Stall, // watchdog fired, core 0 (usb) stopped feeding it
+    Core1Stall, // watchdog fired, core 1 (gpio) stopped sending heartbeats
+    Unknown,
+}
+impl ResetReason {
+    pub fn encode(self) -> u8 {
+        match self {
+            Self::PowerOn => RESET_REASON_POWER_ON,
+            Self::Software => RESET_REASON_SOFTWARE,
+            Self::Core0Stall => RESET_REASON_CORE0_STALL,
+            Self::Core1Stall => RESET_REASON_CORE1_STALL,
+            Self::Unknown => RESET_REASON_UNKNOWN,
+        }
+    }
+
+    pub fn decode(w: u8) -> Self {
+        if w == RESET_REASON_POWER_ON {
+            Self::PowerOn
+        } else if w == RESET_REASON_SOFTWARE {
+            Self::Software
+        } else if w == RESET_REASON_CORE0_STALL {
+            Self::Core0Stall
+        } else if w == RESET_REASON_CORE1_STALL {
+            Self::Core1Stall
+        } else {
+            Self::Unknown
+        }
+    }
+
+    pub fn is_watchdog(self) -> bool {
+        matches!(self, Self::Core0Stall | Self::Core1Stall)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_decode_to_their_own_variant() {
+        let commands = [
+            (CMD_GREET, Command::Greeting),
+            (CMD_GET_INPUT_KEYS, Command::GetInputKeys),
+            (CMD_DIAGNOSTICS, Command::Diagnostics),
+            (CMD_PEDAL_CONFIG, Command::PedalConfig),
+            (CMD_GET_PERIPHERALS, Command::GetPeripherals),
+            (CMD_SCREEN_ORIENTATION, Command::ScreenOrientation),
+            (CMD_SCREEN_POWER, Command::ScreenPower),
+            (CMD_SCREEN_TEXT, Command::ScreenText),
+            (CMD_KEY_LABELS, Command::KeyLabels),
+            (CMD_ANIMATION_FRAME, Command::AnimationFrame),
+            (CMD_RGB_CONFIG, Command::RgbConfig),
+            (CMD_HID_SEQUENCE, Command::HidSequence),
+            (CMD_DEVICE_SETTINGS, Command::DeviceSettings),
+            (CMD_UPDATE, Command::Update),
+            (CMD_DISCONNECT, Command::Disconnect),
+            (CMD_NEGATIVE_ACK, Command::NegativeAck),
+        ];
+        for (w, cmd) in commands {
+            assert_eq!(Command::decode(w), cmd);
+        }
+        assert_eq!(Command::decode(CMD_UNKNOWN), Command::Unknown);
+    }
 }
@@ -0,0 +1,137 @@
+// Quadrature decoding for rotary encoders
+
+// Indexed by (previous AB << 2) | current AB. Clockwise runs 00 -> 01 -> 11 -> 10 -> 00.
+// Transitions where both channels change at once are invalid (we missed an edge) and are ignored.
+const TRANSITIONS: [i8; 16] = [0, 1, -1, 0, -1, 0, 0, 1, 1, 0, 0, -1, 0, -1, 1, 0];
+
+// Most detented encoders (EC11 and friends) go through a full quadrature cycle per detent.
+pub const STEPS_PER_DETENT: i32 = 4;
+
+#[derive(Clone, Copy)]
+pub struct QuadratureDecoder {
+    state: u8,
+    steps: i32,
+}
+impl QuadratureDecoder {
+    pub const fn new(a: bool, b: bool) -> Self {
+        QuadratureDecoder {
+            state: Self::encode(a, b),
+            steps: 0,
+        }
+    }
+
+    const fn encode(a: bool, b: bool) -> u8 {
+        (a as u8) << 1 | b as u8
+    }
+
+    // Feed in the current level of both channels, returns the step that was taken (-1, 0, or 1).
+    pub fn update(&mut self, a: bool, b: bool) -> i8 {
+        let next = Self::encode(a, b);
+        let step = TRANSITIONS[((self.state << 2) | next) as usize];
+        self.state = next;
+        self.steps += step as i32;
+        step
+    }
+
+    // Takes all the whole detents turned since the last call, positive is clockwise.
+    // Partial detents are kept until they are completed.
+    pub fn take_detents(&mut self) -> i32 {
+        let detents = self.steps / STEPS_PER_DETENT;
+        self.steps -= detents * STEPS_PER_DETENT;
+        detents
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // channel levels (a, b) for one clockwise detent, starting from rest at 00
+    const CW: [(bool, bool); 4] = [(false, true), (true, true), (true, false), (false, false)];
+    const CCW: [(bool, bool); 4] = [(true, false), (true, true), (false, true), (false, false)];
+
+    fn feed(d: &mut QuadratureDecoder, levels: &[(bool, bool)]) -> i32 {
+        levels.iter().map(|&(a, b)| d.update(a, b) as i32).sum()
+    }
+
+    #[test]
+    fn clockwise() {
+        let mut d = QuadratureDecoder::new(false, false);
+        for &(a, b) in CW.iter() {
+            assert_eq!(d.update(a, b), 1);
+        }
+        assert_eq!(d.take_detents(), 1);
+    }
+
+    #[test]
+    fn counter_clockwise() {
+        let mut d = QuadratureDecoder::new(false, false);
+        for &(a, b) in CCW.iter() {
+            assert_eq!(d.update(a, b), -1);
+        }
+        assert_eq!(d.take_detents(), -1);
+    }
+
+    #[test]
+    fn bounce_cancels_out() {
+        // a chattering on one edge goes back and forth without turning
+        let mut d = QuadratureDecoder::new(false, false);
+        let chatter = [(false, true), (false, false), (false, true), (false, false)];
+        assert_eq!(feed(&mut d, &chatter), 0);
+        assert_eq!(d.take_detents(), 0);
+
+        // b chattering partway through a detent doesn't stop the detent from counting
+        let mut d = QuadratureDecoder::new(false, false);
+        let turn = [
+            (false, true),
+            (true, true),
+            (true, false),
+            (true, true),
+            (true, false),
+            (false, false),
+        ];
+        assert_eq!(feed(&mut d, &turn), 4);
+        assert_eq!(d.take_detents(), 1);
+    }
+
+    #[test]
+    fn double_steps_are_ignored() {
+        // both channels changing at once is a missed edge, the direction can't be known
+        let mut d = QuadratureDecoder::new(false, false);
+        assert_eq!(d.update(true, true), 0);
+        assert_eq!(d.update(false, false), 0);
+        assert_eq!(d.update(true, true), 0);
+        assert_eq!(d.update(false, false), 0);
+        assert_eq!(d.take_detents(), 0);
+
+        // unchanged levels aren't a step either
+        assert_eq!(d.update(false, false), 0);
+    }
+
+    #[test]
+    fn detents_accumulate() {
+        let mut d = QuadratureDecoder::new(false, false);
+        for _ in 0..3 {
+            feed(&mut d, &CW);
+        }
+        feed(&mut d, &CCW);
+        assert_eq!(d.take_detents(), 2);
+        assert_eq!(d.take_detents(), 0);
+    }
+
+    #[test]
+    fn partial_detents_are_kept() {
+        let mut d = QuadratureDecoder::new(false, false);
+        feed(&mut d, &CW[..3]);
+        assert_eq!(d.take_detents(), 0);
+        feed(&mut d, &CW[3..]);
+        assert_eq!(d.take_detents(), 1);
+
+        // and backing out of a partial detent undoes it
+        feed(&mut d, &CW[..2]);
+        feed(&mut d, &[(false, true), (false, false)]);
+        assert_eq!(d.take_detents(), 0);
+        feed(&mut d, &CW);
+        assert_eq!(d.take_detents(), 1);
+    }
+}
@@ -0,0 +1,187 @@
+//! What the status LED shows. Each state blinks a looping pattern, and whether the LED is lit is
+//! worked out from the time since the state began, so a pattern can be checked without a board.
+
+use crate::peripheral::Connection;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct BlinkStep {
+    pub on: bool,
+    pub ms: u16,
+}
+
+const fn on(ms: u16) -> BlinkStep {
+    BlinkStep { on: true, ms }
+}
+
+const fn off(ms: u16) -> BlinkStep {
+    BlinkStep { on: false, ms }
+}
+
+const NOT_CONNECTED: &[BlinkStep] = &[on(100), off(1900)];
+const LINKED: &[BlinkStep] = &[on(1000)];
+const KEEPALIVE_LOST: &[BlinkStep] = &[on(150), off(150), on(150), off(1050)];
+const UPDATE_PENDING: &[BlinkStep] = &[on(50), off(50)];
+const RECOVERED: &[BlinkStep] = &[on(100), off(150), on(100), off(150), on(100), off(1400)];
+const UNCONFIGURED: &[BlinkStep] = &[on(600), off(200), on(100), off(1100)];
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum DeviceStatus {
+    NotConnected,  // waiting for the host, a short blip every two seconds
+    Linked,        // talking to the host, steady on
+    KeepaliveLost, // the link dropped without a clean disconnect, double blink
+    UpdatePending, // about to reboot into the bootloader, fast flicker
+    Recovered,     // restarted by the watchdog, triple blink until the host links up
+    Unconfigured,  // doesn't know which board it is, long then short blink until it's set
+}
+impl DeviceStatus {
+    pub const ALL: [DeviceStatus; 6] = [
+        DeviceStatus::NotConnected,
+        DeviceStatus::Linked,
+        DeviceStatus::KeepaliveLost,
+        DeviceStatus::UpdatePending,
+        DeviceStatus::Recovered,
+        DeviceStatus::Unconfigured,
+    ];
+
+    /// Picks the state to show, the most urgent first.
+    pub fn current(
+        connection: Connection,
+        update_pending: bool,
+        unconfigured: bool,
+        recovered: bool,
+    ) -> Self {
+        if update_pending {
+            Self::UpdatePending
+        } else if unconfigured {
+            Self::Unconfigured
+        } else if recovered && connection != Connection::Connected {
+            Self::Recovered
+        } else {
+            match connection {
+                Connection::Connected => Self::Linked,
+                Connection::NotConnected(false) => Self::KeepaliveLost,
+                Connection::NotConnected(true) => Self::NotConnected,
+            }
+        }
+    }
+
+    pub fn pattern(self) -> &'static [BlinkStep] {
+        match self {
+            Self::NotConnected => NOT_CONNECTED,
+            Self::Linked => LINKED,
+            Self::KeepaliveLost => KEEPALIVE_LOST,
+            Self::UpdatePending => UPDATE_PENDING,
+            Self::Recovered => RECOVERED,
+            Self::Unconfigured => UNCONFIGURED,
+        }
+    }
+
+    /// How long the pattern takes before it repeats.
+    pub fn period(self) -> u32 {
+        self.pattern().iter().map(|s| s.ms as u32).sum()
+    }
+
+    /// Whether the LED is lit `ms` after the state began.
+    pub fn is_lit(self, ms: u64) -> bool {
+        let mut t = ms % self.period().max(1) as u64;
+        for s in self.pattern() {
+            if t < s.ms as u64 {
+                return s.on;
+            }
+            t -= s.ms as u64;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONNECTIONS: [Connection; 3] = [
+        Connection::Connected,
+        Connection::NotConnected(false),
+        Connection::NotConnected(true),
+    ];
+
+    // the times the led turns on and off over one period, starting lit at 0
+    fn edges(status: DeviceStatus) -> impl Iterator<Item = u64> {
+        (1..status.period() as u64).filter(move |&ms| status.is_lit(ms) != status.is_lit(ms - 1))
+    }
+
+    #[test]
+    fn update_pending_comes_first() {
+        for connection in CONNECTIONS {
+            for (unconfigured, recovered) in [(false, false), (true, false), (true, true)] {
+                assert_eq!(
+                    DeviceStatus::current(connection, true, unconfigured, recovered),
+                    DeviceStatus::UpdatePending
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unconfigured_shows_while_linked() {
+        for connection in CONNECTIONS {
+            for recovered in [false, true] {
+                assert_eq!(
+                    DeviceStatus::current(connection, false, true, recovered),
+                    DeviceStatus::Unconfigured
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn recovered_shows_until_linked() {
+        assert_eq!(
+            DeviceStatus::current(Connection::Connected, false, false, true),
+            DeviceStatus::Linked
+        );
+        for connection in [
+            Connection::NotConnected(false),
+            Connection::NotConnected(true),
+        ] {
+            assert_eq!(
+                DeviceStatus::current(connection, false, false, true),
+                DeviceStatus::Recovered
+            );
+        }
+    }
+
+    #[test]
+    fn connection_states() {
+        let current = |c| DeviceStatus::current(c, false, false, false);
+        assert_eq!(current(Connection::Connected), DeviceStatus::Linked);
+        assert_eq!(
+            current(Connection::NotConnected(false)),
+            DeviceStatus::KeepaliveLost
+        );
+        assert_eq!(
+            current(Connection::NotConnected(true)),
+            DeviceStatus::NotConnected
+        );
+    }
+
+    #[test]
+    fn blink_timing() {
+        let expected: [(DeviceStatus, u32, &[u64]); 6] = [
+            (DeviceStatus::NotConnected, 2000, &[100]),
+            (DeviceStatus::Linked, 1000, &[]),
+            (DeviceStatus::KeepaliveLost, 1500, &[150, 300, 450]),
+            (DeviceStatus::UpdatePending, 100, &[50]),
+            (DeviceStatus::Recovered, 2000, &[100, 250, 350, 500, 600]),
+            (DeviceStatus::Unconfigured, 2000, &[600, 800, 900]),
+        ];
+        for (status, period, changes) in expected {
+            assert_eq!(status.period(), period, "{status:?}");
+            assert!(status.is_lit(0), "{status:?}");
+            assert!(edges(status).eq(changes.iter().copied()), "{status:?}");
+            // the pattern loops
+            for ms in 0..period as u64 {
+                assert_eq!(status.is_lit(ms), status.is_lit(ms + 3 * period as u64));
+            }
+        }
+    }
+}
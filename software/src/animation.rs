@@ -0,0 +1,95 @@
+// Animated images for the device screen, imported from GIF or APNG files
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use image::codecs::{gif::GifDecoder, png::PngDecoder};
+use image::{AnimationDecoder, Frame, ImageFormat, ImageReader};
+use jukebox_util::image::{flatten, quantize, scale, Dither, ScaleMode};
+
+// GIFs often leave the delay at 0 and expect a sensible default
+const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+const MIN_FRAME_DELAY: Duration = Duration::from_millis(20);
+
+pub struct AnimationFrame {
+    pub pixels: Vec<u16>, // rgb565, row by row
+    pub delay: Duration,
+}
+
+pub struct Animation {
+    pub width: usize,
+    pub height: usize,
+    pub frames: Vec<AnimationFrame>,
+}
+impl Animation {
+    pub fn duration(&self) -> Duration {
+        self.frames.iter().map(|f| f.delay).sum()
+    }
+
+    /// The frame showing `t` into the animation, looping forever.
+    pub fn frame_at(&self, t: Duration) -> usize {
+        let total = self.duration().as_micros().max(1);
+        let mut t = t.as_micros() % total;
+        for (i, f) in self.frames.iter().enumerate() {
+            if t < f.delay.as_micros() {
+                return i;
+            }
+            t -= f.delay.as_micros();
+        }
+        0
+    }
+}
+
+fn decode_frames(path: &Path) -> Result<Vec<Frame>> {
+    let format = ImageReader::open(path)?.with_guessed_format()?.format();
+    let reader = BufReader::new(File::open(path)?);
+
+    let frames = match format {
+        Some(ImageFormat::Gif) => GifDecoder::new(reader)?.into_frames().collect_frames()?,
+        Some(ImageFormat::Png) => {
+            let png = PngDecoder::new(reader)?;
+            if !png.is_apng()? {
+                bail!("png has no animation");
+            }
+            png.apng()?.into_frames().collect_frames()?
+        }
+        _ => bail!("not a gif or apng"),
+    };
+
+    if frames.is_empty() {
+        bail!("animation has no frames");
+    }
+    Ok(frames)
+}
+
+/// Loads a GIF or APNG, cropped and scaled to fill the screen, and dithered to rgb565.
+pub fn load_animation(path: &Path, width: usize, height: usize) -> Result<Animation> {
+    let frames = decode_frames(path).with_context(|| format!("loading {}", path.display()))?;
+
+    let frames = frames
+        .into_iter()
+        .map(|f| {
+            let (num, den) = f.delay().numer_denom_ms();
+            let delay = match Duration::from_micros(num as u64 * 1000 / den.max(1) as u64) {
+                d if d.is_zero() => DEFAULT_FRAME_DELAY,
+                d => d.max(MIN_FRAME_DELAY),
+            };
+
+            let mut img = scale(f.buffer(), width as u32, height as u32, ScaleMode::Fill);
+            flatten(&mut img, (0, 0, 0));
+            AnimationFrame {
+                pixels: quantize(&img, Dither::FloydSteinberg),
+                delay: delay,
+            }
+        })
+        .collect();
+
+    Ok(Animation {
+        width: width,
+        height: height,
+        frames: frames,
+    })
+}
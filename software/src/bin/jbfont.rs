@@ -0,0 +1,204 @@
+// Converts a BDF bitmap font into the JBF format drawn by the JukeBox screen.
+//
+// usage: jbfont <input.bdf> <output.jbf> [first char] [last char]
+//
+// Only printable ASCII is converted unless a range is given. Outline fonts (TTF/OTF) need to be
+// rasterized to BDF at the wanted pixel size first, for example with otf2bdf.
+
+use std::{collections::HashMap, env, fs};
+
+use anyhow::{anyhow, bail, Context, Result};
+use jukebox_util::font::{Font, FONT_HEADER_SIZE, FONT_MAGIC, FONT_VERSION, GLYPH_ENTRY_SIZE};
+
+const DEFAULT_FIRST: u32 = 0x20;
+const DEFAULT_LAST: u32 = 0x7E;
+
+struct BdfGlyph {
+    advance: u32,
+    bbx: (u32, u32, i32, i32), // width, height, x offset, y offset from the baseline
+    rows: Vec<u32>,            // msb aligned to bit 31
+}
+
+struct BdfFont {
+    ascent: i32,
+    descent: i32,
+    glyphs: HashMap<u32, BdfGlyph>,
+}
+
+fn parse_nums(args: &[&str]) -> Result<Vec<i32>> {
+    args.iter()
+        .map(|a| {
+            a.parse::<i32>()
+                .with_context(|| format!("bad number {:?}", a))
+        })
+        .collect()
+}
+
+fn parse_bdf(src: &str) -> Result<BdfFont> {
+    let mut ascent = None;
+    let mut descent = None;
+    let mut bounds = None;
+    let mut glyphs = HashMap::new();
+
+    let mut lines = src.lines();
+    while let Some(line) = lines.next() {
+        let mut words = line.split_whitespace();
+        let Some(key) = words.next() else { continue };
+        let args: Vec<&str> = words.collect();
+
+        match key {
+            "FONTBOUNDINGBOX" => bounds = Some(parse_nums(&args)?),
+            "FONT_ASCENT" => ascent = Some(parse_nums(&args)?[0]),
+            "FONT_DESCENT" => descent = Some(parse_nums(&args)?[0]),
+            "STARTCHAR" => {
+                let mut encoding = None;
+                let mut advance = 0;
+                let mut bbx = (0, 0, 0, 0);
+                let mut rows = Vec::new();
+
+                // glyph properties, then the bitmap rows, one hex string each
+                let mut in_bitmap = false;
+                for line in lines.by_ref() {
+                    let mut words = line.split_whitespace();
+                    let key = words.next().unwrap_or("");
+                    let args: Vec<&str> = words.collect();
+
+                    if key == "ENDCHAR" {
+                        break;
+                    } else if in_bitmap {
+                        let bits = u32::from_str_radix(key, 16)
+                            .with_context(|| format!("bad bitmap row {:?}", key))?;
+                        rows.push(bits << (32 - 4 * key.len() as u32));
+                    } else if key == "ENCODING" {
+                        encoding = Some(parse_nums(&args)?[0]);
+                    } else if key == "DWIDTH" {
+                        advance = parse_nums(&args)?[0].max(0) as u32;
+                    } else if key == "BBX" {
+                        let n = parse_nums(&args)?;
+                        bbx = (n[0].max(0) as u32, n[1].max(0) as u32, n[2], n[3]);
+                    } else if key == "BITMAP" {
+                        in_bitmap = true;
+                    }
+                }
+
+                // negative encodings are glyphs without a code point
+                if let Some(c) = encoding.filter(|c| *c >= 0) {
+                    glyphs.insert(
+                        c as u32,
+                        BdfGlyph {
+                            advance: advance,
+                            bbx: bbx,
+                            rows: rows,
+                        },
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // older fonts leave out the ascent and descent, fall back on the bounding box
+    let bounds = bounds.ok_or(anyhow!("missing FONTBOUNDINGBOX"))?;
+    let ascent = ascent.unwrap_or(bounds[1] + bounds[3]);
+    let descent = descent.unwrap_or(-bounds[3]);
+
+    Ok(BdfFont {
+        ascent: ascent,
+        descent: descent,
+        glyphs: glyphs,
+    })
+}
+
+fn encode_jbf(bdf: &BdfFont, first: u32, last: u32) -> Result<Vec<u8>> {
+    let height = bdf.ascent + bdf.descent;
+    if height <= 0 || height > u8::MAX as i32 || bdf.ascent < 0 {
+        bail!("unsupported font height {}", height);
+    }
+    if first > last || last > 0xFF {
+        bail!("char range must be within 0-255");
+    }
+
+    let count = last - first + 1;
+    let mut table = Vec::with_capacity(count as usize * GLYPH_ENTRY_SIZE);
+    let mut bitmaps = Vec::new();
+
+    for c in first..=last {
+        let Some(g) = bdf.glyphs.get(&c) else {
+            // missing glyphs have no width, and draw as '?'
+            table.extend_from_slice(&[0, 0, 0]);
+            continue;
+        };
+        if g.advance > u8::MAX as u32 {
+            bail!("glyph {:#x} is too wide", c);
+        }
+
+        let stride = (g.advance as usize + 7) / 8;
+        let mut cell = vec![0u8; stride * height as usize];
+        let (w, h, xoff, yoff) = g.bbx;
+        // bbx y offset is from the baseline up to the bottom of the glyph
+        let top = bdf.ascent - (yoff + h as i32);
+        for (r, bits) in g.rows.iter().take(h as usize).enumerate() {
+            let y = top + r as i32;
+            if y < 0 || y >= height {
+                continue;
+            }
+            for col in 0..w {
+                let x = xoff + col as i32;
+                if x < 0 || x >= g.advance as i32 || bits & (0x8000_0000 >> col) == 0 {
+                    continue;
+                }
+                cell[y as usize * stride + x as usize / 8] |= 0x80 >> (x % 8);
+            }
+        }
+
+        let offset = u16::try_from(bitmaps.len()).map_err(|_| anyhow!("font is too large"))?;
+        table.push(g.advance as u8);
+        table.extend_from_slice(&offset.to_be_bytes());
+        bitmaps.extend_from_slice(&cell);
+    }
+
+    let mut out = Vec::with_capacity(FONT_HEADER_SIZE + table.len() + bitmaps.len());
+    out.extend_from_slice(FONT_MAGIC);
+    out.push(FONT_VERSION);
+    out.extend_from_slice(&[height as u8, bdf.ascent as u8, first as u8, count as u8]);
+    out.extend_from_slice(&table);
+    out.extend_from_slice(&bitmaps);
+
+    // make sure the firmware will take it
+    Font::parse(&out).map_err(|_| anyhow!("converted font failed to parse"))?;
+    Ok(out)
+}
+
+fn parse_char(arg: Option<&String>, default: u32) -> Result<u32> {
+    match arg {
+        None => Ok(default),
+        Some(a) => match a.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => a.parse::<u32>(),
+        }
+        .with_context(|| format!("bad char {:?}", a)),
+    }
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        bail!("usage: jbfont <input.bdf> <output.jbf> [first char] [last char]");
+    }
+
+    let src = fs::read_to_string(&args[1]).with_context(|| format!("reading {}", args[1]))?;
+    let bdf = parse_bdf(&src)?;
+    let first = parse_char(args.get(3), DEFAULT_FIRST)?;
+    let last = parse_char(args.get(4), DEFAULT_LAST)?;
+
+    let jbf = encode_jbf(&bdf, first, last)?;
+    fs::write(&args[2], &jbf).with_context(|| format!("writing {}", args[2]))?;
+    println!(
+        "{} glyphs, {}px high, {} bytes",
+        last - first + 1,
+        bdf.ascent + bdf.descent,
+        jbf.len()
+    );
+
+    Ok(())
+}
@@ -9,26 +9,88 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
+use anyhow::Result;
 use eframe::egui::{
-    vec2, Align, Button, CentralPanel, Color32, ComboBox, Grid, Layout, RichText, Rounding, Sense,
-    TextBuffer, TextEdit, Ui, ViewportBuilder,
+    vec2, Align, Button, CentralPanel, Checkbox, Color32, ComboBox, Grid, Layout, ProgressBar,
+    RichText, Rounding, Sense, Slider, TextBuffer, TextEdit, Ui, ViewportBuilder,
 };
 use egui_phosphor::regular as phos;
+use jukebox_util::diagnostics::{DiagnosticsReport, DiagnosticsTest};
+use jukebox_util::font::{TextAlign, TextSize};
+use jukebox_util::peripheral::{
+    AxisBlock, DeviceSettings, DeviceType, KeyLabels, PedalConfig, PeripheralDescriptor, RgbConfig,
+    ScreenOrientation, ScreenPowerConfig, ScreenRotation, ScreenText, IDENT_PEDAL_INPUT,
+    KEYPAD_MAX_ROWS, KEYPAD_MIN_ROWS, PEDAL_ANALOG_MAX, PEDAL_COUNT, SCREEN_TEXT_MAX_LEN,
+};
+use jukebox_util::protocol::ResetReason;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::reaction::{reaction_task, InputKey, ReactionConfig};
+use crate::animation::{load_animation, Animation};
+use crate::reaction::{
+    format_keys, parse_keys, reaction_task, InputKey, ReactionConfig, ReactionInputPressKey,
+};
 use crate::serial::{serial_task, SerialCommand, SerialConnectionDetails, SerialEvent};
 use crate::splash::SPLASH_MESSAGES;
 
 const APP_VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
+// shown until a device tells us what it has
+const DEFAULT_PERIPHERALS: PeripheralDescriptor = PeripheralDescriptor {
+    key_rows: 3,
+    key_cols: 4,
+    ..PeripheralDescriptor::default()
+};
+
+// the screen's size once turned, which is what gets drawn on
+fn screen_size(p: &PeripheralDescriptor, orientation: ScreenOrientation) -> (u16, u16) {
+    match orientation.swaps_axes() {
+        true => (p.screen_height, p.screen_width),
+        false => (p.screen_width, p.screen_height),
+    }
+}
+
+// keys count down from F24, so a 3x4 pad is F13-F24 and a 4x4 pad is F9-F24
+fn key_label(n: usize, key_count: usize) -> String {
+    format!("F{}", 24 - key_count + n + 1)
+}
+
 #[derive(PartialEq)]
 enum GuiTab {
     Device,
     Settings,
 }
 
+#[derive(PartialEq, Clone, Copy)]
+enum DiagnosticsStep {
+    StatusLed,
+    Rgb,
+    Screen,
+    Keys,
+    Report,
+}
+impl DiagnosticsStep {
+    fn test(self) -> DiagnosticsTest {
+        match self {
+            Self::StatusLed => DiagnosticsTest::StatusLed,
+            Self::Rgb => DiagnosticsTest::Rgb,
+            Self::Screen => DiagnosticsTest::Screen,
+            Self::Keys => DiagnosticsTest::Keys,
+            Self::Report => DiagnosticsTest::Stop,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::StatusLed => "Status LED",
+            Self::Rgb => "RGB LEDs",
+            Self::Screen => "Screen",
+            Self::Keys => "Keys",
+            Self::Report => "Report",
+        }
+    }
+}
+
 #[derive(PartialEq)]
 enum ConnectionStatus {
     Connected,
@@ -36,16 +98,132 @@ enum ConnectionStatus {
     Disconnected,
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct PedalSettings {
+    pub analog: [bool; PEDAL_COUNT],
+    pub actuation: [u16; PEDAL_COUNT],
+}
+impl Default for PedalSettings {
+    fn default() -> Self {
+        let c = PedalConfig::default();
+        PedalSettings {
+            analog: c.analog,
+            actuation: c.actuation,
+        }
+    }
+}
+impl From<PedalSettings> for PedalConfig {
+    fn from(s: PedalSettings) -> Self {
+        PedalConfig {
+            analog: s.analog,
+            actuation: s.actuation.map(|a| a.min(PEDAL_ANALOG_MAX)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct ScreenSettings {
+    pub rotation: u16, // degrees clockwise
+    pub mirrored: bool,
+    pub brightness: u8,
+    pub dim_brightness: u8,
+    pub dim_timeout: u16,   // seconds, 0 never dims
+    pub sleep_timeout: u16, // seconds, 0 never sleeps
+}
+impl Default for ScreenSettings {
+    fn default() -> Self {
+        let c = ScreenPowerConfig::default();
+        ScreenSettings {
+            rotation: 0,
+            mirrored: false,
+            brightness: c.brightness,
+            dim_brightness: c.dim_brightness,
+            dim_timeout: c.dim_timeout,
+            sleep_timeout: c.sleep_timeout,
+        }
+    }
+}
+impl From<ScreenSettings> for ScreenPowerConfig {
+    fn from(s: ScreenSettings) -> Self {
+        ScreenPowerConfig {
+            brightness: s.brightness,
+            dim_brightness: s.dim_brightness,
+            dim_timeout: s.dim_timeout,
+            sleep_timeout: s.sleep_timeout,
+        }
+    }
+}
+impl From<ScreenSettings> for ScreenOrientation {
+    fn from(s: ScreenSettings) -> Self {
+        ScreenOrientation {
+            rotation: ScreenRotation::from_degrees(s.rotation),
+            mirrored: s.mirrored,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct RgbSettings {
+    pub brightness: u8,
+    pub current_limit: u16, // mA, 0 for no limit
+}
+impl Default for RgbSettings {
+    fn default() -> Self {
+        let c = RgbConfig::default();
+        RgbSettings {
+            brightness: c.brightness,
+            current_limit: c.current_limit,
+        }
+    }
+}
+impl From<RgbSettings> for RgbConfig {
+    fn from(s: RgbSettings) -> Self {
+        RgbConfig {
+            brightness: s.brightness,
+            current_limit: s.current_limit,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct AnimationSettings {
+    pub path: Option<PathBuf>, // gif or apng, None shows the key labels instead
+    pub fps: u8,
+}
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        AnimationSettings {
+            path: None,
+            fps: 15,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct JukeBoxConfig {
     pub current_profile: String,
     pub profiles: HashMap<String, HashMap<InputKey, ReactionConfig>>,
+    #[serde(default)]
+    pub pedals: PedalSettings,
+    #[serde(default)]
+    pub screen: ScreenSettings,
+    #[serde(default)]
+    pub animation: AnimationSettings,
+    #[serde(default)]
+    pub rgb: RgbSettings,
 }
 impl Default for JukeBoxConfig {
     fn default() -> Self {
         JukeBoxConfig {
             current_profile: "Default".to_string(),
             profiles: HashMap::from([("Default".to_string(), HashMap::new())]),
+            pedals: PedalSettings::default(),
+            screen: ScreenSettings::default(),
+            animation: AnimationSettings::default(),
+            rgb: RgbSettings::default(),
         }
     }
 }
@@ -95,8 +273,28 @@ struct JukeBoxGui {
     gui_tab: GuiTab,
 
     device_info: Option<SerialConnectionDetails>,
-    // device_peripherals: HashSet<Peripheral>,
+    device_peripherals: Option<PeripheralDescriptor>,
     device_inputs: HashSet<InputKey>,
+    device_pedal_positions: Option<AxisBlock>,
+    device_key_labels: Option<KeyLabels>, // last sent, so they're only sent when they change
+
+    pedal_setup: bool,
+    screen_setup: bool,
+    rgb_setup: bool,
+    board_setup: Option<DeviceSettings>, // the board being picked, while the page is open
+    screen_message_entry: String,
+
+    editing_key: Option<(InputKey, String)>, // the input whose key press is being set, and its label
+    key_press_entry: String,
+    key_press_error: Option<String>,
+
+    animation: Option<Arc<Animation>>,
+    animation_loading: Option<Receiver<Result<Animation>>>,
+    animation_error: Option<String>,
+
+    diagnostics_step: Option<DiagnosticsStep>,
+    diagnostics_checks: Vec<(DiagnosticsStep, bool)>,
+    diagnostics_report: Option<DiagnosticsReport>,
 
     config: Arc<Mutex<JukeBoxConfig>>,
     config_renaming_profile: bool,
@@ -114,9 +312,25 @@ impl JukeBoxGui {
             splash_index: 0usize,
             conn_status: ConnectionStatus::Disconnected,
             gui_tab: GuiTab::Device,
-            // device_peripherals: HashSet::new(),
+            device_peripherals: None,
             device_inputs: HashSet::new(),
             device_info: None,
+            device_pedal_positions: None,
+            device_key_labels: None,
+            pedal_setup: false,
+            screen_setup: false,
+            rgb_setup: false,
+            board_setup: None,
+            screen_message_entry: String::new(),
+            editing_key: None,
+            key_press_entry: String::new(),
+            key_press_error: None,
+            animation: None,
+            animation_loading: None,
+            animation_error: None,
+            diagnostics_step: None,
+            diagnostics_checks: Vec::new(),
+            diagnostics_report: None,
             config: config,
             config_renaming_profile: false,
             config_profile_name_entry: String::new(),
@@ -135,6 +349,7 @@ impl JukeBoxGui {
 
         let s_evnt_tx_serial = s_evnt_tx.clone();
         let s_cmd_tx2 = s_cmd_tx.clone();
+        let s_cmd_tx_reaction = s_cmd_tx.clone(); // reactions send hid reports through the device
 
         let config_reaction = self.config.clone();
 
@@ -144,7 +359,13 @@ impl JukeBoxGui {
 
         // reaction comms thread
         let reactioncomms = thread::spawn(move || {
-            reaction_task(brkr_reaction, s_evnt_rx, r_evnt_tx, config_reaction)
+            reaction_task(
+                brkr_reaction,
+                s_evnt_rx,
+                r_evnt_tx,
+                s_cmd_tx_reaction,
+                config_reaction,
+            )
         });
 
         let options = eframe::NativeOptions {
@@ -169,7 +390,9 @@ impl JukeBoxGui {
             egui_phosphor::add_to_fonts(&mut fonts, egui_phosphor::Variant::Regular);
             ctx.set_fonts(fonts);
 
-            self.handle_serial_events(&r_evnt_rx);
+            self.handle_serial_events(&r_evnt_rx, &s_cmd_tx);
+            self.sync_key_labels(&s_cmd_tx);
+            self.poll_animation(&s_cmd_tx);
 
             CentralPanel::default().show(ctx, |ui| {
                 ui.horizontal(|ui| {
@@ -211,31 +434,73 @@ impl JukeBoxGui {
             .expect("could not rejoin reactioncomms thread");
     }
 
-    fn handle_serial_events(&mut self, s_evnt_rx: &Receiver<SerialEvent>) {
+    fn handle_serial_events(
+        &mut self,
+        s_evnt_rx: &Receiver<SerialEvent>,
+        s_cmd_tx: &Sender<SerialCommand>,
+    ) {
         while let Ok(event) = s_evnt_rx.try_recv() {
             match event {
                 SerialEvent::Connected(d) => {
                     self.conn_status = ConnectionStatus::Connected;
+                    s_cmd_tx
+                        .send(SerialCommand::GetPeripherals)
+                        .expect("failed to send get peripherals command");
+                    if d.input_identifier == IDENT_PEDAL_INPUT {
+                        // pedal setup lives on the host, the device forgets it when unplugged
+                        self.send_pedal_config(s_cmd_tx);
+                    }
                     self.device_info = Some(d);
                 }
                 SerialEvent::LostConnection => {
                     self.conn_status = ConnectionStatus::LostConnection;
-                    // self.device_peripherals.clear();
+                    self.device_peripherals = None;
                     self.device_info = None;
+                    self.device_pedal_positions = None;
+                    self.device_key_labels = None;
+                    self.diagnostics_step = None;
+                    self.pedal_setup = false;
+                    self.screen_setup = false;
+                    self.rgb_setup = false;
+                    self.board_setup = None;
                 }
                 SerialEvent::Disconnected => {
                     self.conn_status = ConnectionStatus::Disconnected;
-                    // self.device_peripherals.clear();
+                    self.device_peripherals = None;
                     self.device_info = None;
+                    self.device_pedal_positions = None;
+                    self.device_key_labels = None;
+                    self.diagnostics_step = None;
+                    self.pedal_setup = false;
+                    self.screen_setup = false;
+                    self.rgb_setup = false;
+                    self.board_setup = None;
+                }
+                SerialEvent::GetPedalPositions(p) => {
+                    self.device_pedal_positions = Some(p);
+                }
+                SerialEvent::GetKnobTurns(t) => {
+                    // turns come after the keys they were read with, so they light up until the
+                    // next read like the keys do
+                    self.device_inputs.extend(t.into_keys());
+                }
+                SerialEvent::Diagnostics(r) => {
+                    if r.test == DiagnosticsTest::Stop {
+                        self.diagnostics_report = Some(r);
+                    }
+                }
+                SerialEvent::GetPeripherals(p) => {
+                    if p.led_count > 0 {
+                        self.send_rgb_config(s_cmd_tx);
+                    }
+                    if p.has_screen() {
+                        // like the pedals, the device doesn't remember how its screen is mounted
+                        self.send_screen_config(s_cmd_tx);
+                    }
+                    self.device_peripherals = Some(p);
+                    // converted for this screen's size, so it waits until we know it
+                    self.load_animation(s_cmd_tx);
                 }
-                // SerialEvent::GetPeripherals(p) => {
-                //     self.device_peripherals = p;
-                //     if self.device_peripherals.contains(&Peripheral::Keyboard) {
-                //         self.device_tab = GuiDeviceTab::Keyboard;
-                //     } else {
-                //         self.device_tab = GuiDeviceTab::None;
-                //     }
-                // }
                 SerialEvent::GetInputKeys(k) => {
                     self.device_inputs = k
                     // TODO: run all config.profiles[config.current_profile] actions
@@ -245,25 +510,63 @@ impl JukeBoxGui {
     }
 
     fn draw_device_page(&mut self, ui: &mut Ui) {
-        self.draw_keyboard(ui);
+        if let Some((key, label)) = self.editing_key.clone() {
+            self.draw_key_press_editor(ui, key, &label);
+            return;
+        }
+
+        let p = self.device_peripherals.unwrap_or(DEFAULT_PERIPHERALS);
+        if p.key_count() > 0 {
+            self.draw_keyboard(ui, p.key_rows as usize, p.key_cols as usize);
+        }
+        if p.knob_count > 0 {
+            self.draw_knobs(ui, p.knob_count as usize);
+        }
+        if p.pedal_count > 0 {
+            self.draw_pedals(ui, p.pedal_count as usize);
+        }
         // ui.allocate_exact_size(vec2(324.0, 231.0), Sense::hover());
     }
 
     fn draw_settings_page(&mut self, ui: &mut Ui, s_cmd_tx: &Sender<SerialCommand>) {
         self.draw_jukebox_logo(ui);
         ui.label("");
+        if self.diagnostics_step.is_some() {
+            self.draw_diagnostics_wizard(ui, &s_cmd_tx);
+            return;
+        }
+        if self.pedal_setup {
+            self.draw_pedal_setup(ui, &s_cmd_tx);
+            return;
+        }
+        if self.screen_setup {
+            self.draw_screen_setup(ui, &s_cmd_tx);
+            return;
+        }
+        if self.rgb_setup {
+            self.draw_rgb_setup(ui, &s_cmd_tx);
+            return;
+        }
+        if self.board_setup.is_some() {
+            self.draw_board_setup(ui, &s_cmd_tx);
+            return;
+        }
         ui.label("");
         self.draw_update_button(ui, &s_cmd_tx);
+        self.draw_diagnostics_button(ui, &s_cmd_tx);
+        self.draw_pedal_setup_button(ui);
+        self.draw_screen_setup_button(ui);
+        self.draw_rgb_setup_button(ui);
+        self.draw_board_setup_button(ui);
         ui.label("");
         self.draw_settings_bottom(ui);
     }
 
     fn draw_profile_management(&mut self, ui: &mut Ui) {
         ui.scope(|ui| {
-            // TODO
-            // if editing_key_reaction {
-            //     ui.disable();
-            // }
+            if self.editing_key.is_some() {
+                ui.disable();
+            }
             if self.gui_tab != GuiTab::Device {
                 ui.disable();
             }
@@ -388,60 +691,95 @@ impl JukeBoxGui {
         });
     }
 
-    fn draw_keyboard(&mut self, ui: &mut Ui) {
+    fn draw_input_button(&mut self, ui: &mut Ui, key: InputKey, label: String, size: f32) {
+        let rt = RichText::new(&label).heading();
+        let mut b = Button::new(rt);
+        if self.device_inputs.contains(&key) {
+            let r = 20.0;
+            b = b.rounding(Rounding {
+                nw: r,
+                ne: r,
+                sw: r,
+                se: r,
+            });
+        }
+        let btn = ui.add_sized([size, size], b);
+
+        if btn.clicked() {
+            log::info!("{} clicked", label);
+            let conf = self.config.lock().unwrap();
+            self.key_press_entry = match conf
+                .profiles
+                .get(&conf.current_profile)
+                .and_then(|p| p.get(&key))
+            {
+                Some(ReactionConfig::InputPressKey(r)) => format_keys(&r.keys),
+                _ => String::new(),
+            };
+            drop(conf);
+            self.key_press_error = None;
+            self.editing_key = Some((key, label));
+            // TODO: display some better text in the buttons
+            // TODO: add hover text for button info
+        }
+    }
+
+    fn draw_key_press_editor(&mut self, ui: &mut Ui, key: InputKey, label: &str) {
+        ui.label(RichText::new(format!("Key Press - {}", label)).heading());
+        ui.label("Keys the device presses for this input, joined with +, like Ctrl+Shift+F5.");
+        ui.label("Knob turns tap the keys once per detent.");
+        ui.add(TextEdit::singleline(&mut self.key_press_entry).desired_width(240.0));
+        if let Some(e) = &self.key_press_error {
+            ui.label(RichText::new(e).color(Color32::from_rgb(200, 50, 50)));
+        }
+        ui.label("");
+
+        // None while the editor stays open, Some(reaction) to save it, Some(None) to remove it
+        let mut done: Option<Option<ReactionConfig>> = None;
+        ui.horizontal(|ui| {
+            if ui.button("Save").clicked() {
+                match parse_keys(&self.key_press_entry) {
+                    Ok(keys) => {
+                        let r = ReactionInputPressKey { keys };
+                        done = Some(Some(ReactionConfig::InputPressKey(r)));
+                    }
+                    Err(e) => self.key_press_error = Some(format!("{}", e)),
+                }
+            }
+            if ui.button("Remove").clicked() {
+                done = Some(None);
+            }
+            if ui.button("Cancel").clicked() {
+                self.editing_key = None;
+            }
+        });
+
+        if let Some(reaction) = done {
+            let mut conf = self.config.lock().unwrap();
+            let current = conf.current_profile.clone();
+            if let Some(profile) = conf.profiles.get_mut(&current) {
+                match reaction {
+                    Some(r) => profile.insert(key, r),
+                    None => profile.remove(&key),
+                };
+            }
+            conf.save();
+            self.editing_key = None;
+        }
+    }
+
+    fn draw_keyboard(&mut self, ui: &mut Ui, rows: usize, cols: usize) {
         let s = Sense::hover();
+        // shrink the keys on taller pads so the page stays the same height
+        let size = (75.0 * 3.0 / rows as f32).min(75.0);
         ui.horizontal(|ui| {
             ui.allocate_exact_size([62.0, 0.0].into(), s);
             Grid::new("KBGrid").show(ui, |ui| {
-                let keys = [
-                    [
-                        InputKey::KeySwitch1,
-                        InputKey::KeySwitch2,
-                        InputKey::KeySwitch3,
-                        InputKey::KeySwitch4,
-                    ],
-                    [
-                        InputKey::KeySwitch5,
-                        InputKey::KeySwitch6,
-                        InputKey::KeySwitch7,
-                        InputKey::KeySwitch8,
-                    ],
-                    [
-                        InputKey::KeySwitch9,
-                        InputKey::KeySwitch10,
-                        InputKey::KeySwitch11,
-                        InputKey::KeySwitch12,
-                    ],
-                    // [
-                    //     InputKey::KeySwitch13,
-                    //     InputKey::KeySwitch14,
-                    //     InputKey::KeySwitch15,
-                    //     InputKey::KeySwitch16,
-                    // ],
-                ];
-                for (y, k) in keys.iter().enumerate() {
-                    for (x, k) in k.iter().enumerate() {
-                        let s = format!("F{}", 12 + x + y * 4 + 1);
-                        let rt = RichText::new(s).heading();
-                        let mut b = Button::new(rt);
-                        if self.device_inputs.contains(k) {
-                            let r = 20.0;
-                            b = b.rounding(Rounding {
-                                nw: r,
-                                ne: r,
-                                sw: r,
-                                se: r,
-                            });
-                        }
-                        let btn = ui.add_sized([75.0, 75.0], b);
-
-                        if btn.clicked() {
-                            log::info!("F{} clicked", 12 + x + y * 4 + 1);
-                            // TODO: add config menu when button is clicked
-                            // TODO: highlight button when press signal is recieved
-                            // TODO: display some better text in the buttons
-                            // TODO: add hover text for button info
-                        }
+                for y in 0..rows {
+                    for x in 0..cols {
+                        let n = x + y * cols;
+                        let label = key_label(n, rows * cols);
+                        self.draw_input_button(ui, InputKey::key_switch(n), label, size);
                     }
                     ui.end_row();
                 }
@@ -449,6 +787,35 @@ impl JukeBoxGui {
         });
     }
 
+    fn draw_knobs(&mut self, ui: &mut Ui, count: usize) {
+        ui.horizontal(|ui| {
+            ui.allocate_exact_size([62.0, 0.0].into(), Sense::hover());
+            Grid::new("KnobGrid").show(ui, |ui| {
+                for n in 0..count {
+                    let (switch, cw, ccw) = InputKey::knob(n);
+                    let label = phos::ARROW_COUNTER_CLOCKWISE.to_string();
+                    self.draw_input_button(ui, ccw, label, 75.0);
+                    self.draw_input_button(ui, switch, format!("Knob {}", n + 1), 75.0);
+                    self.draw_input_button(ui, cw, phos::ARROW_CLOCKWISE.to_string(), 75.0);
+                    ui.end_row();
+                }
+            });
+        });
+    }
+
+    fn draw_pedals(&mut self, ui: &mut Ui, count: usize) {
+        ui.horizontal(|ui| {
+            ui.allocate_exact_size([62.0, 0.0].into(), Sense::hover());
+            Grid::new("PedalGrid").show(ui, |ui| {
+                for n in 0..count {
+                    let label = format!("Pedal {}", n + 1);
+                    self.draw_input_button(ui, InputKey::pedal(n), label, 100.0);
+                }
+                ui.end_row();
+            });
+        });
+    }
+
     fn draw_jukebox_logo(&mut self, ui: &mut Ui) {
         ui.horizontal(|ui| {
             ui.label(
@@ -488,8 +855,708 @@ impl JukeBoxGui {
         });
     }
 
+    fn draw_diagnostics_button(&mut self, ui: &mut Ui, s_cmd_tx: &Sender<SerialCommand>) {
+        ui.horizontal(|ui| {
+            if self.conn_status != ConnectionStatus::Connected {
+                ui.disable();
+            }
+            if ui.button("Run Diagnostics").clicked() {
+                self.diagnostics_checks.clear();
+                self.diagnostics_report = None;
+                self.set_diagnostics_step(Some(DiagnosticsStep::StatusLed), s_cmd_tx);
+            }
+            ui.label(" - ");
+            ui.label("Walks through a self-test of the connected JukeBox.")
+        });
+    }
+
+    fn diagnostics_steps(&self) -> Vec<DiagnosticsStep> {
+        let mut steps = vec![DiagnosticsStep::StatusLed, DiagnosticsStep::Rgb];
+        if let Some(p) = &self.device_peripherals {
+            if p.has_screen() {
+                steps.push(DiagnosticsStep::Screen);
+            }
+        }
+        steps.push(DiagnosticsStep::Keys);
+        steps.push(DiagnosticsStep::Report);
+        steps
+    }
+
+    fn set_diagnostics_step(
+        &mut self,
+        step: Option<DiagnosticsStep>,
+        s_cmd_tx: &Sender<SerialCommand>,
+    ) {
+        let test = step.map_or(DiagnosticsTest::Stop, |s| s.test());
+        s_cmd_tx
+            .send(SerialCommand::Diagnostics(test))
+            .expect("failed to send diagnostics command");
+        self.diagnostics_step = step;
+    }
+
+    fn next_diagnostics_step(&mut self, passed: bool, s_cmd_tx: &Sender<SerialCommand>) {
+        let step = self.diagnostics_step.expect("no diagnostics step running");
+        self.diagnostics_checks.push((step, passed));
+
+        let steps = self.diagnostics_steps();
+        let next = steps
+            .iter()
+            .skip_while(|s| **s != step)
+            .nth(1)
+            .copied()
+            .unwrap_or(DiagnosticsStep::Report);
+        self.set_diagnostics_step(Some(next), s_cmd_tx);
+    }
+
+    fn draw_diagnostics_wizard(&mut self, ui: &mut Ui, s_cmd_tx: &Sender<SerialCommand>) {
+        let step = self.diagnostics_step.expect("no diagnostics step running");
+        let steps = self.diagnostics_steps();
+        let index = steps.iter().position(|s| *s == step).unwrap_or(0);
+
+        ui.label(
+            RichText::new(format!(
+                "Diagnostics ({}/{}): {}",
+                index + 1,
+                steps.len(),
+                step.name()
+            ))
+            .heading(),
+        );
+
+        let question = match step {
+            DiagnosticsStep::StatusLed => "Is the status LED on the board blinking quickly?",
+            DiagnosticsStep::Rgb => {
+                "Are the RGB LEDs lighting up one at a time, in red, then green, then blue?"
+            }
+            DiagnosticsStep::Screen => {
+                "Is the screen cycling through color bars, a gradient, and a checkerboard?"
+            }
+            DiagnosticsStep::Keys => {
+                "Let go of every key and leave the JukeBox untouched, then click Finish. Any key still held down will be reported as stuck."
+            }
+            DiagnosticsStep::Report => "",
+        };
+
+        match step {
+            DiagnosticsStep::Report => self.draw_diagnostics_report(ui),
+            DiagnosticsStep::Keys => {
+                ui.label(question);
+                ui.label("");
+                ui.horizontal(|ui| {
+                    if ui.button("Finish").clicked() {
+                        self.next_diagnostics_step(true, s_cmd_tx);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.set_diagnostics_step(None, s_cmd_tx);
+                    }
+                });
+            }
+            _ => {
+                ui.label(question);
+                ui.label("");
+                ui.horizontal(|ui| {
+                    if ui.button(format!("{} Yes", phos::CHECK)).clicked() {
+                        self.next_diagnostics_step(true, s_cmd_tx);
+                    }
+                    if ui.button(format!("{} No", phos::X)).clicked() {
+                        self.next_diagnostics_step(false, s_cmd_tx);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.set_diagnostics_step(None, s_cmd_tx);
+                    }
+                });
+            }
+        }
+    }
+
+    fn draw_diagnostics_report(&mut self, ui: &mut Ui) {
+        let report = match self.diagnostics_report {
+            Some(r) => r,
+            None => {
+                ui.label("Waiting for the device to report...");
+                return;
+            }
+        };
+
+        Grid::new("DiagnosticsReport").show(ui, |ui| {
+            for (step, passed) in &self.diagnostics_checks {
+                ui.label(step.name());
+                if *step == DiagnosticsStep::Keys {
+                    // boards without keys label their switches like the default pad did
+                    let key_count = self
+                        .device_peripherals
+                        .unwrap_or(DEFAULT_PERIPHERALS)
+                        .key_count()
+                        .max(DEFAULT_PERIPHERALS.key_count());
+                    let stuck: Vec<_> = (0..16)
+                        .filter(|k| report.is_stuck(*k))
+                        .map(|k| key_label(k, key_count))
+                        .collect();
+                    if stuck.is_empty() {
+                        ui.label(
+                            RichText::new("No stuck keys").color(Color32::from_rgb(50, 200, 50)),
+                        );
+                    } else {
+                        ui.label(
+                            RichText::new(format!("Stuck: {}", stuck.join(", ")))
+                                .color(Color32::from_rgb(200, 50, 50)),
+                        );
+                    }
+                } else if *passed {
+                    ui.label(RichText::new("Passed").color(Color32::from_rgb(50, 200, 50)));
+                } else {
+                    ui.label(RichText::new("Failed").color(Color32::from_rgb(200, 50, 50)));
+                }
+                ui.end_row();
+            }
+        });
+
+        ui.label("");
+        if ui.button("Close").clicked() {
+            self.diagnostics_step = None;
+        }
+    }
+
+    fn send_pedal_config(&self, s_cmd_tx: &Sender<SerialCommand>) {
+        let pedals = self.config.lock().unwrap().pedals;
+        s_cmd_tx
+            .send(SerialCommand::PedalConfig(pedals.into()))
+            .expect("failed to send pedal config command");
+    }
+
+    fn send_rgb_config(&self, s_cmd_tx: &Sender<SerialCommand>) {
+        let rgb = self.config.lock().unwrap().rgb;
+        s_cmd_tx
+            .send(SerialCommand::RgbConfig(rgb.into()))
+            .expect("failed to send rgb config command");
+    }
+
+    fn send_screen_config(&mut self, s_cmd_tx: &Sender<SerialCommand>) {
+        let screen = self.config.lock().unwrap().screen;
+        s_cmd_tx
+            .send(SerialCommand::ScreenOrientation(screen.into()))
+            .expect("failed to send screen orientation command");
+        s_cmd_tx
+            .send(SerialCommand::ScreenPower(screen.into()))
+            .expect("failed to send screen power command");
+        // the labels are laid out for the old orientation, have them drawn again
+        self.device_key_labels = None;
+    }
+
+    // the device screen shows what each key does, keep it in step with the current profile
+    fn sync_key_labels(&mut self, s_cmd_tx: &Sender<SerialCommand>) {
+        let Some(p) = self.device_peripherals else {
+            return;
+        };
+        if !p.has_screen() || p.key_count() == 0 {
+            return;
+        }
+
+        let conf = self.config.lock().unwrap();
+        let mut labels = KeyLabels::new(p.key_rows, p.key_cols, &conf.current_profile);
+        if let Some(profile) = conf.profiles.get(&conf.current_profile) {
+            for n in 0..labels.key_count() {
+                if let Some(r) = profile.get(&InputKey::key_switch(n)) {
+                    labels.set_label(n, r.label());
+                }
+            }
+        }
+        drop(conf);
+
+        if self.device_key_labels != Some(labels) {
+            s_cmd_tx
+                .send(SerialCommand::KeyLabels(labels))
+                .expect("failed to send key labels command");
+            self.device_key_labels = Some(labels);
+        }
+    }
+
+    // the message fills the screen, centered in the larger font
+    fn send_screen_message(
+        &self,
+        s_cmd_tx: &Sender<SerialCommand>,
+        orientation: ScreenOrientation,
+    ) {
+        let Some(p) = &self.device_peripherals else {
+            return;
+        };
+        let (w, h) = screen_size(p, orientation);
+
+        let mut text = ScreenText::new(0, 0, w, h, &self.screen_message_entry);
+        text.size = TextSize::Medium;
+        text.align = TextAlign::Center;
+        s_cmd_tx
+            .send(SerialCommand::ScreenText(text))
+            .expect("failed to send screen text command");
+    }
+
+    // converting takes a moment, so it's done off the gui thread and picked up when it's ready
+    fn load_animation(&mut self, s_cmd_tx: &Sender<SerialCommand>) {
+        s_cmd_tx
+            .send(SerialCommand::StopAnimation)
+            .expect("failed to send stop animation command");
+        self.animation = None;
+        self.animation_loading = None;
+        self.animation_error = None;
+
+        let Some(p) = self.device_peripherals else {
+            return;
+        };
+        let conf = self.config.lock().unwrap();
+        let (Some(path), true) = (conf.animation.path.clone(), p.has_screen()) else {
+            return;
+        };
+        let (w, h) = screen_size(&p, conf.screen.into());
+        drop(conf);
+
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            let _ = tx.send(load_animation(&path, w as usize, h as usize));
+        });
+        self.animation_loading = Some(rx);
+    }
+
+    fn poll_animation(&mut self, s_cmd_tx: &Sender<SerialCommand>) {
+        let Some(rx) = &self.animation_loading else {
+            return;
+        };
+        let Ok(res) = rx.try_recv() else {
+            return;
+        };
+        self.animation_loading = None;
+
+        match res {
+            Ok(a) => {
+                self.animation = Some(Arc::new(a));
+                self.send_animation(s_cmd_tx);
+            }
+            Err(e) => {
+                log::warn!("Failed to load animation: {:#}", e);
+                self.animation_error = Some(format!("{:#}", e));
+            }
+        }
+    }
+
+    fn send_animation(&self, s_cmd_tx: &Sender<SerialCommand>) {
+        let Some(a) = &self.animation else {
+            return;
+        };
+        let fps = self.config.lock().unwrap().animation.fps;
+        s_cmd_tx
+            .send(SerialCommand::PlayAnimation(a.clone(), fps))
+            .expect("failed to send play animation command");
+    }
+
+    fn draw_screen_setup_button(&mut self, ui: &mut Ui) {
+        match &self.device_peripherals {
+            Some(p) if p.has_screen() => {}
+            _ => return,
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Screen Setup").clicked() {
+                self.screen_setup = true;
+            }
+            ui.label(" - ");
+            ui.label("Turn the screen to match the case, and set when it dims and sleeps.")
+        });
+    }
+
+    fn draw_screen_setup(&mut self, ui: &mut Ui, s_cmd_tx: &Sender<SerialCommand>) {
+        ui.label(RichText::new("Screen Setup").heading());
+
+        let mut screen = self.config.lock().unwrap().screen;
+        // shown in minutes, so the sliders don't need to cover every second
+        let mut dim_minutes = screen.dim_timeout / 60;
+        let mut sleep_minutes = screen.sleep_timeout / 60;
+        let never = |v: f64, _| {
+            if v == 0.0 {
+                "Never".to_string()
+            } else {
+                format!("{:.0} min", v)
+            }
+        };
+
+        Grid::new("ScreenSetup").show(ui, |ui| {
+            ui.label("Rotation");
+            ui.horizontal(|ui| {
+                ComboBox::from_id_salt("ScreenRotation")
+                    .selected_text(format!("{}°", screen.rotation))
+                    .width(60.0)
+                    .show_ui(ui, |ui| {
+                        for r in ScreenRotation::ALL {
+                            let d = r.degrees();
+                            ui.selectable_value(&mut screen.rotation, d, format!("{}°", d));
+                        }
+                    });
+                ui.add(Checkbox::new(&mut screen.mirrored, "Mirrored"));
+            });
+            ui.end_row();
+
+            ui.label("Brightness");
+            ui.add(Slider::new(&mut screen.brightness, 1..=u8::MAX));
+            ui.end_row();
+
+            ui.label("Dimmed brightness");
+            ui.add(Slider::new(&mut screen.dim_brightness, 0..=u8::MAX));
+            ui.end_row();
+
+            ui.label("Dim after");
+            ui.add(Slider::new(&mut dim_minutes, 0..=60).custom_formatter(never));
+            ui.end_row();
+
+            ui.label("Sleep after");
+            ui.add(Slider::new(&mut sleep_minutes, 0..=240).custom_formatter(never));
+            ui.end_row();
+        });
+        ui.label("Any key press wakes the screen. It also sleeps while the computer does.");
+
+        ui.label("");
+        let mut animation = self.config.lock().unwrap().animation.clone();
+        let (mut import, mut remove) = (false, false);
+        Grid::new("ScreenAnimation").show(ui, |ui| {
+            ui.label("Animation");
+            ui.horizontal(|ui| {
+                let name = animation
+                    .path
+                    .as_ref()
+                    .and_then(|p| p.file_name())
+                    .map_or("None".to_string(), |n| n.to_string_lossy().to_string());
+                ui.label(name);
+                import = ui.button("Import...").clicked();
+                remove = ui
+                    .add_enabled(animation.path.is_some(), Button::new("Remove"))
+                    .clicked();
+            });
+            ui.end_row();
+
+            ui.label("Frame rate");
+            ui.add(Slider::new(&mut animation.fps, 1..=30).suffix(" fps"));
+            ui.end_row();
+        });
+        if self.animation_loading.is_some() {
+            ui.label("Converting...");
+        } else if let Some(e) = &self.animation_error {
+            ui.label(RichText::new(e).color(Color32::RED));
+        } else if let Some(a) = &self.animation {
+            ui.label(format!(
+                "Playing {} frames at {}x{}.",
+                a.frames.len(),
+                a.width,
+                a.height
+            ));
+        } else {
+            ui.label("A GIF or APNG plays in place of the key labels, filling the screen.");
+        }
+
+        if import {
+            if let Some(p) = rfd::FileDialog::new()
+                .add_filter("Animations", &["gif", "png", "apng"])
+                .pick_file()
+            {
+                animation.path = Some(p);
+            }
+        }
+        if remove {
+            animation.path = None;
+        }
+
+        ui.label("");
+        let (mut show, mut clear) = (false, false);
+        ui.horizontal(|ui| {
+            ui.label("Test message");
+            ui.add(
+                TextEdit::singleline(&mut self.screen_message_entry)
+                    .char_limit(SCREEN_TEXT_MAX_LEN)
+                    .desired_width(200.0),
+            );
+            show = ui.button("Show").clicked();
+            clear = ui.button("Clear").clicked();
+        });
+        if show {
+            self.send_screen_message(s_cmd_tx, screen.into());
+        }
+        if clear {
+            s_cmd_tx
+                .send(SerialCommand::ScreenText(ScreenText::clear()))
+                .expect("failed to send screen text command");
+            // clearing drops the key labels too, put them back
+            self.device_key_labels = None;
+        }
+
+        // only touch the timeouts when the sliders moved, so odd values from the config survive
+        if dim_minutes != screen.dim_timeout / 60 {
+            screen.dim_timeout = dim_minutes * 60;
+        }
+        if sleep_minutes != screen.sleep_timeout / 60 {
+            screen.sleep_timeout = sleep_minutes * 60;
+        }
+
+        let mut conf = self.config.lock().unwrap();
+        let screen_changed = conf.screen != screen;
+        let animation_changed = conf.animation != animation;
+        // a new file, or turned the other way, means converting the animation again
+        let reload =
+            conf.animation.path != animation.path || conf.screen.rotation != screen.rotation;
+        if screen_changed || animation_changed {
+            conf.screen = screen;
+            conf.animation = animation;
+            conf.save();
+        }
+        drop(conf);
+
+        if screen_changed {
+            self.send_screen_config(s_cmd_tx);
+        }
+        if reload {
+            self.load_animation(s_cmd_tx);
+            // until the new one plays, the key labels show again
+            self.device_key_labels = None;
+        } else if animation_changed {
+            self.send_animation(s_cmd_tx);
+        }
+
+        ui.label("");
+        if ui.button("Close").clicked() {
+            self.screen_setup = false;
+        }
+    }
+
+    fn draw_pedal_setup_button(&mut self, ui: &mut Ui) {
+        match &self.device_peripherals {
+            Some(p) if p.pedal_count > 0 => {}
+            _ => return,
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Pedal Setup").clicked() {
+                self.pedal_setup = true;
+            }
+            ui.label(" - ");
+            ui.label("Choose switch or analog pedals, and where they actuate.")
+        });
+    }
+
+    fn draw_pedal_setup(&mut self, ui: &mut Ui, s_cmd_tx: &Sender<SerialCommand>) {
+        ui.label(RichText::new("Pedal Setup").heading());
+
+        let mut pedals = self.config.lock().unwrap().pedals;
+        let positions = self
+            .device_pedal_positions
+            .map_or([0; PEDAL_COUNT], |p| [p.axes[0], p.axes[1], p.axes[2]]);
+
+        Grid::new("PedalSetup").show(ui, |ui| {
+            for (n, name) in ["Left", "Middle", "Right"].iter().enumerate() {
+                ui.label(*name);
+                ui.add(Checkbox::new(&mut pedals.analog[n], "Analog"));
+                ui.scope(|ui| {
+                    if !pedals.analog[n] {
+                        ui.disable();
+                    }
+                    ui.add(
+                        Slider::new(&mut pedals.actuation[n], 0..=PEDAL_ANALOG_MAX)
+                            .custom_formatter(|v, _| format!("{:.0}%", v / 10.0))
+                            .text("Actuation"),
+                    );
+                });
+                ui.add(
+                    ProgressBar::new(positions[n] as f32 / PEDAL_ANALOG_MAX as f32)
+                        .desired_width(60.0),
+                );
+                ui.end_row();
+            }
+        });
+        ui.label("Analog pedals calibrate themselves, press each one all the way down once.");
+
+        let mut conf = self.config.lock().unwrap();
+        if conf.pedals != pedals {
+            conf.pedals = pedals;
+            conf.save();
+            drop(conf);
+            self.send_pedal_config(s_cmd_tx);
+        }
+
+        ui.label("");
+        if ui.button("Close").clicked() {
+            self.pedal_setup = false;
+        }
+    }
+
+    fn draw_rgb_setup_button(&mut self, ui: &mut Ui) {
+        match &self.device_peripherals {
+            Some(p) if p.led_count > 0 => {}
+            _ => return,
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("LED Setup").clicked() {
+                self.rgb_setup = true;
+            }
+            ui.label(" - ");
+            ui.label("Set how bright the key lights are, and how much power they can draw.")
+        });
+    }
+
+    fn draw_rgb_setup(&mut self, ui: &mut Ui, s_cmd_tx: &Sender<SerialCommand>) {
+        ui.label(RichText::new("LED Setup").heading());
+
+        let mut rgb = self.config.lock().unwrap().rgb;
+
+        Grid::new("RgbSetup").show(ui, |ui| {
+            ui.label("Brightness");
+            ui.add(Slider::new(&mut rgb.brightness, 0..=u8::MAX));
+            ui.end_row();
+
+            ui.label("Current limit");
+            ui.add(
+                Slider::new(&mut rgb.current_limit, 0..=500)
+                    .step_by(10.0)
+                    .custom_formatter(|v, _| {
+                        if v == 0.0 {
+                            "None".to_string()
+                        } else {
+                            format!("{:.0} mA", v)
+                        }
+                    }),
+            );
+            ui.end_row();
+        });
+        ui.label("Bright frames are dimmed as a whole to stay under the limit.");
+        ui.label("A USB 2.0 port gives 500 mA for the whole device.");
+
+        let mut conf = self.config.lock().unwrap();
+        if conf.rgb != rgb {
+            conf.rgb = rgb;
+            conf.save();
+            drop(conf);
+            self.send_rgb_config(s_cmd_tx);
+        }
+
+        ui.label("");
+        if ui.button("Close").clicked() {
+            self.rgb_setup = false;
+        }
+    }
+
+    fn draw_board_setup_button(&mut self, ui: &mut Ui) {
+        let (Some(p), Some(i)) = (&self.device_peripherals, &self.device_info) else {
+            return;
+        };
+        if !p.flash_settings {
+            return;
+        }
+
+        let device_type = DeviceType::decode(i.input_identifier);
+        // boards without a type start off as a keypad, the same as boards without strapping pins
+        let current = match device_type {
+            DeviceType::KeyPad | DeviceType::Unknown => DeviceSettings {
+                device_type: DeviceType::KeyPad,
+                key_rows: p.key_rows.clamp(KEYPAD_MIN_ROWS, KEYPAD_MAX_ROWS),
+            },
+            d => DeviceSettings {
+                device_type: d,
+                key_rows: 0,
+            },
+        };
+
+        ui.horizontal(|ui| {
+            if ui.button("Board Setup").clicked() {
+                self.board_setup = Some(current);
+            }
+            ui.label(" - ");
+            if device_type == DeviceType::Unknown {
+                ui.label(
+                    RichText::new("This JukeBox doesn't know which board it is, set it here.")
+                        .color(Color32::from_rgb(200, 50, 50)),
+                )
+            } else {
+                ui.label("Tell the connected JukeBox which board it is.")
+            }
+        });
+    }
+
+    fn draw_board_setup(&mut self, ui: &mut Ui, s_cmd_tx: &Sender<SerialCommand>) {
+        let Some(mut board) = self.board_setup else {
+            return;
+        };
+        ui.label(RichText::new("Board Setup").heading());
+
+        let boards = [
+            (DeviceType::KeyPad, "Keypad"),
+            (DeviceType::KnobPad, "Knobpad"),
+            (DeviceType::PedalPad, "Pedalpad"),
+        ];
+        let name = boards
+            .iter()
+            .find(|(d, _)| *d == board.device_type)
+            .map_or("", |(_, n)| n);
+
+        Grid::new("BoardSetup").show(ui, |ui| {
+            ui.label("Board");
+            ComboBox::from_id_salt("BoardType")
+                .selected_text(name)
+                .width(100.0)
+                .show_ui(ui, |ui| {
+                    for (d, n) in boards {
+                        ui.selectable_value(&mut board.device_type, d, n);
+                    }
+                });
+            ui.end_row();
+
+            if board.device_type == DeviceType::KeyPad {
+                ui.label("Key rows");
+                ui.horizontal(|ui| {
+                    for rows in KEYPAD_MIN_ROWS..=KEYPAD_MAX_ROWS {
+                        ui.radio_value(&mut board.key_rows, rows, format!("{}x4", rows));
+                    }
+                });
+                ui.end_row();
+            }
+        });
+        // only keypads have rows to pick from
+        board.key_rows = match board.device_type {
+            DeviceType::KeyPad => board.key_rows.clamp(KEYPAD_MIN_ROWS, KEYPAD_MAX_ROWS),
+            _ => 0,
+        };
+        self.board_setup = Some(board);
+        ui.label("The JukeBox saves this and restarts, then it's found again as the new board.");
+
+        ui.label("");
+        ui.horizontal(|ui| {
+            if ui.button("Save").clicked() {
+                s_cmd_tx
+                    .send(SerialCommand::DeviceSettings(board))
+                    .expect("failed to send device settings command");
+                self.board_setup = None;
+            }
+            if ui.button("Cancel").clicked() {
+                self.board_setup = None;
+            }
+        });
+    }
+
     fn draw_settings_bottom(&mut self, ui: &mut Ui) {
         ui.with_layout(Layout::bottom_up(Align::LEFT), |ui| {
+            if let Some(i) = &self.device_info {
+                let res = match i.reset_reason {
+                    ResetReason::PowerOn => ("Power on", Color32::GRAY),
+                    ResetReason::Software => ("Software reset", Color32::GRAY),
+                    ResetReason::Core0Stall => (
+                        "Watchdog (USB core stalled)",
+                        Color32::from_rgb(200, 50, 50),
+                    ),
+                    ResetReason::Core1Stall => (
+                        "Watchdog (GPIO core stalled)",
+                        Color32::from_rgb(200, 50, 50),
+                    ),
+                    ResetReason::Unknown => ("Unknown", Color32::GRAY),
+                };
+                ui.horizontal(|ui| {
+                    ui.label("Last Reset:");
+                    ui.label(RichText::new(res.0).color(res.1));
+                });
+            }
+
             ui.horizontal(|ui| {
                 if let Some(i) = &self.device_info {
                     ui.label(format!("Firmware Version: {}", i.firmware_version));
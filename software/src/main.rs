@@ -2,6 +2,7 @@
 
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // disables console spawning for release build
 
+mod animation;
 mod gui;
 mod reaction;
 mod serial;
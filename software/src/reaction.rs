@@ -1,7 +1,7 @@
 // Defining reactions to perform when actions happen (key pressed, knob turned, etc.)
 
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     sync::{
         atomic::AtomicBool,
         mpsc::{Receiver, Sender},
@@ -11,11 +11,15 @@ use std::{
     time::{Duration, Instant},
 };
 
-use anyhow::{Context, Result};
-use jukebox_util::peripheral::{KeyInputs, KnobInputs, PedalInputs};
+use anyhow::{bail, Context, Result};
+use jukebox_util::hid::{function_key, HidReport, HidSequence, HidUsage, HID_KEYBOARD_MAX_KEYS};
+use jukebox_util::peripheral::{DeviceType, EncoderBlock, KeyBlock};
 use serde::{Deserialize, Serialize};
 
-use crate::{gui::JukeBoxConfig, serial::SerialEvent};
+use crate::{
+    gui::JukeBoxConfig,
+    serial::{SerialCommand, SerialEvent},
+};
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Hash, Clone, Copy)]
 pub enum InputKey {
@@ -49,83 +53,123 @@ pub enum InputKey {
     PedalMiddle,
     PedalRight,
 }
-impl InputKey {
-    pub fn trans_keys(i: KeyInputs) -> HashSet<Self> {
-        let mut res = HashSet::new();
+// Input blocks only carry indices, these give them names. The variants above are kept as they are
+// since profiles are saved with them.
+const KEY_SWITCHES: [InputKey; 16] = [
+    InputKey::KeySwitch1,
+    InputKey::KeySwitch2,
+    InputKey::KeySwitch3,
+    InputKey::KeySwitch4,
+    InputKey::KeySwitch5,
+    InputKey::KeySwitch6,
+    InputKey::KeySwitch7,
+    InputKey::KeySwitch8,
+    InputKey::KeySwitch9,
+    InputKey::KeySwitch10,
+    InputKey::KeySwitch11,
+    InputKey::KeySwitch12,
+    InputKey::KeySwitch13,
+    InputKey::KeySwitch14,
+    InputKey::KeySwitch15,
+    InputKey::KeySwitch16,
+];
+// switch, clockwise, counter clockwise
+const KNOBS: [(InputKey, InputKey, InputKey); 2] = [
+    (
+        InputKey::KnobLeftSwitch,
+        InputKey::KnobLeftClockwise,
+        InputKey::KnobLeftCounterClockwise,
+    ),
+    (
+        InputKey::KnobRightSwitch,
+        InputKey::KnobRightClockwise,
+        InputKey::KnobRightCounterClockwise,
+    ),
+];
+const PEDALS: [InputKey; 3] = [
+    InputKey::PedalLeft,
+    InputKey::PedalMiddle,
+    InputKey::PedalRight,
+];
 
-        let mut doif = |c, f| {
-            if c {
-                res.insert(f);
-            }
-        };
-
-        doif(i.key1.is_down(), Self::KeySwitch1);
-        doif(i.key2.is_down(), Self::KeySwitch2);
-        doif(i.key3.is_down(), Self::KeySwitch3);
-        doif(i.key4.is_down(), Self::KeySwitch4);
-        doif(i.key5.is_down(), Self::KeySwitch5);
-        doif(i.key6.is_down(), Self::KeySwitch6);
-        doif(i.key7.is_down(), Self::KeySwitch7);
-        doif(i.key8.is_down(), Self::KeySwitch8);
-        doif(i.key9.is_down(), Self::KeySwitch9);
-        doif(i.key10.is_down(), Self::KeySwitch10);
-        doif(i.key11.is_down(), Self::KeySwitch11);
-        doif(i.key12.is_down(), Self::KeySwitch12);
-        doif(i.key13.is_down(), Self::KeySwitch13);
-        doif(i.key14.is_down(), Self::KeySwitch14);
-        doif(i.key15.is_down(), Self::KeySwitch15);
-        doif(i.key16.is_down(), Self::KeySwitch16);
+impl InputKey {
+    pub fn key_switch(n: usize) -> Self {
+        *KEY_SWITCHES.get(n).unwrap_or(&Self::UnknownKey)
+    }
 
-        res
+    // switch, clockwise, counter clockwise
+    pub fn knob(n: usize) -> (Self, Self, Self) {
+        *KNOBS
+            .get(n)
+            .unwrap_or(&(Self::UnknownKey, Self::UnknownKey, Self::UnknownKey))
     }
 
-    pub fn trans_knob(i: KnobInputs) -> HashSet<Self> {
-        let mut res = HashSet::new();
+    pub fn pedal(n: usize) -> Self {
+        *PEDALS.get(n).unwrap_or(&Self::UnknownKey)
+    }
 
-        let mut doif = |c, f| {
-            if c {
-                res.insert(f);
-            }
+    pub fn trans_keys(device_type: DeviceType, i: &KeyBlock) -> HashSet<Self> {
+        // pedals are reported as a key block, so the board decides what the keys are called
+        let names: &[Self] = match device_type {
+            DeviceType::PedalPad => &PEDALS,
+            _ => &KEY_SWITCHES,
         };
 
-        doif(i.left_switch.is_down(), Self::KnobLeftSwitch);
-        doif(i.left_direction.is_clockwise(), Self::KnobLeftClockwise);
-        doif(
-            i.left_direction.is_counter_clockwise(),
-            Self::KnobLeftCounterClockwise,
-        );
-
-        doif(i.right_switch.is_down(), Self::KnobRightSwitch);
-        doif(i.right_direction.is_clockwise(), Self::KnobRightClockwise);
-        doif(
-            i.right_direction.is_counter_clockwise(),
-            Self::KnobRightCounterClockwise,
-        );
-
-        res
+        names
+            .iter()
+            .enumerate()
+            .filter(|(n, _)| i.is_down(*n))
+            .map(|(_, k)| *k)
+            .collect()
     }
 
-    pub fn trans_pedals(i: PedalInputs) -> HashSet<Self> {
-        let mut res = HashSet::new();
+    // only the switches, turns go through trans_knob_turns so a detent isn't reacted to twice
+    pub fn trans_knobs(i: &EncoderBlock) -> HashSet<Self> {
+        i.iter()
+            .zip(KNOBS)
+            .filter(|(e, _)| e.switch.is_down())
+            .map(|(_, (switch, _, _))| switch)
+            .collect()
+    }
 
-        let mut doif = |c, f| {
-            if c {
-                res.insert(f);
+    pub fn trans_knob_turns(i: &EncoderBlock) -> HashMap<Self, KnobTurn> {
+        let mut res = HashMap::new();
+
+        // turns are handed to the direction key they went in, so steps are always positive
+        for (e, (_, cw, ccw)) in i.iter().zip(KNOBS) {
+            let turn = KnobTurn {
+                steps: e.steps.unsigned_abs(),
+                velocity: e.velocity,
+            };
+            if e.steps > 0 {
+                res.insert(cw, turn);
+            } else if e.steps < 0 {
+                res.insert(ccw, turn);
             }
-        };
-
-        doif(i.left.is_down(), Self::PedalLeft);
-        doif(i.middle.is_down(), Self::PedalMiddle);
-        doif(i.right.is_down(), Self::PedalRight);
+        }
 
         res
     }
 }
 
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct KnobTurn {
+    pub steps: u16,    // detents turned since the last report
+    pub velocity: u16, // detents per second, 0 if unknown
+}
+
+// how long each press of a knob's key taps is held, and the gap before the next
+const TAP_HOLD_MS: u16 = 10;
+// taps from one report, a fast spin is cut short rather than typed out for seconds
+const MAX_TAPS: u16 = 16;
+
+// Reactions can have the device send hid reports for them, over `device`. They go out on its own
+// keyboard and mouse, so they work without the app needing permission to inject input.
 pub trait Reaction {
     // TODO: add result output for error reporting
-    fn on_press(&self, key: InputKey);
-    fn on_release(&self, key: InputKey);
+    fn on_press(&self, key: InputKey, device: &Sender<SerialCommand>);
+    fn on_release(&self, key: InputKey, device: &Sender<SerialCommand>);
+    fn on_turn(&self, key: InputKey, turn: KnobTurn, device: &Sender<SerialCommand>);
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -136,7 +180,7 @@ pub enum ReactionConfig {
     MetaCopyFromProfile(),
 
     // Input
-    InputPressKey(),
+    InputPressKey(ReactionInputPressKey),
     InputClickMouse(),
     InputMoveMouse(),
     InputScrollMouse(),
@@ -160,28 +204,233 @@ pub enum ReactionConfig {
     // OBS
 }
 
+impl ReactionConfig {
+    // short enough to fit on a key on the device screen
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::MetaTest(_) => "Test",
+            Self::MetaSwitchProfile() => "Profile",
+            Self::MetaCopyFromProfile() => "Copy Profile",
+            Self::InputPressKey(_) => "Key Press",
+            Self::InputClickMouse() => "Click",
+            Self::InputMoveMouse() => "Move Mouse",
+            Self::InputScrollMouse() => "Scroll",
+            Self::SystemLaunch() => "Launch",
+            Self::SystemWebsite() => "Website",
+            Self::SystemAudioInputControl() => "Mic",
+            Self::SystemAudioOutputControl() => "Volume",
+            Self::SoundboardPlaySound() => "Sound",
+            Self::DiscordToggleMute() => "Mute",
+            Self::DiscordToggleDeafen() => "Deafen",
+            Self::DiscordPushToTalk() => "Push to Talk",
+            Self::DiscordPushToMute() => "Push to Mute",
+            Self::DiscordToggleCamera() => "Camera",
+            Self::DiscordToggleStream() => "Stream",
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ReactionMetaTest {}
 impl Reaction for ReactionMetaTest {
-    fn on_press(&self, key: InputKey) -> () {
+    fn on_press(&self, key: InputKey, _: &Sender<SerialCommand>) -> () {
         log::info!("Pressed {:?} !", key);
     }
 
-    fn on_release(&self, key: InputKey) -> () {
+    fn on_release(&self, key: InputKey, _: &Sender<SerialCommand>) -> () {
         log::info!("Released {:?} !", key);
     }
+
+    fn on_turn(&self, key: InputKey, turn: KnobTurn, _: &Sender<SerialCommand>) -> () {
+        log::info!("Turned {:?} by {} ({}/s) !", key, turn.steps, turn.velocity);
+    }
+}
+
+// Keys by name, for the ones that aren't a letter, a digit or an F key
+const KEY_NAMES: [(&str, u8); 19] = [
+    ("Enter", 0x28),
+    ("Esc", 0x29),
+    ("Backspace", 0x2A),
+    ("Tab", 0x2B),
+    ("Space", 0x2C),
+    ("Insert", 0x49),
+    ("Home", 0x4A),
+    ("PageUp", 0x4B),
+    ("Delete", 0x4C),
+    ("End", 0x4D),
+    ("PageDown", 0x4E),
+    ("Right", 0x4F),
+    ("Left", 0x50),
+    ("Down", 0x51),
+    ("Up", 0x52),
+    ("Ctrl", 0xE0),
+    ("Shift", 0xE1),
+    ("Alt", 0xE2),
+    ("Super", 0xE3),
+];
+
+// the keyboard usage for a key name, any case, or a raw usage like 0x46
+fn key_usage(name: &str) -> Option<u8> {
+    let lower = name.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        return u8::from_str_radix(hex, 16).ok();
+    }
+    if let Some((_, u)) = KEY_NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+        return Some(*u);
+    }
+
+    match lower.as_bytes() {
+        [c @ b'a'..=b'z'] => Some(0x04 + c - b'a'),
+        [b'0'] => Some(0x27),
+        [c @ b'1'..=b'9'] => Some(0x1E + c - b'1'),
+        _ => match function_key(lower.strip_prefix('f')?.parse().ok()?) {
+            HidUsage::Key(k) => Some(k),
+            _ => None,
+        },
+    }
+}
+
+fn key_name(usage: u8) -> String {
+    if let Some((n, _)) = KEY_NAMES.iter().find(|(_, u)| *u == usage) {
+        return n.to_string();
+    }
+
+    match usage {
+        0x04..=0x1D => ((b'A' + usage - 0x04) as char).to_string(),
+        0x1E..=0x26 => ((b'1' + usage - 0x1E) as char).to_string(),
+        0x27 => "0".to_string(),
+        _ => (1..=24)
+            .find(|n| function_key(*n) == HidUsage::Key(usage))
+            .map_or(format!("0x{:02X}", usage), |n| format!("F{}", n)),
+    }
+}
+
+// Keys pressed together, written like Ctrl+Shift+F5
+pub fn parse_keys(s: &str) -> Result<Vec<u8>> {
+    let mut keys = Vec::new();
+    for name in s.split('+').map(str::trim).filter(|n| !n.is_empty()) {
+        match key_usage(name) {
+            Some(k) => keys.push(k),
+            None => bail!("unknown key \"{}\"", name),
+        }
+    }
+
+    if keys.is_empty() {
+        bail!("no keys given");
+    }
+    if keys.len() > HID_KEYBOARD_MAX_KEYS {
+        bail!(
+            "at most {} keys can be pressed together",
+            HID_KEYBOARD_MAX_KEYS
+        );
+    }
+    Ok(keys)
+}
+
+pub fn format_keys(keys: &[u8]) -> String {
+    keys.iter()
+        .map(|k| key_name(*k))
+        .collect::<Vec<_>>()
+        .join("+")
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReactionInputPressKey {
+    pub keys: Vec<u8>, // keyboard usages pressed together, modifiers included
+}
+impl Reaction for ReactionInputPressKey {
+    // held for as long as the input is
+    fn on_press(&self, _: InputKey, device: &Sender<SerialCommand>) -> () {
+        let mut s = HidSequence::new();
+        let _ = s.push(HidReport::keyboard(&self.keys), 0);
+        let _ = device.send(SerialCommand::HidSequence(s));
+    }
+
+    fn on_release(&self, _: InputKey, device: &Sender<SerialCommand>) -> () {
+        let mut s = HidSequence::new();
+        let _ = s.push(HidReport::keyboard(&[]), 0);
+        let _ = device.send(SerialCommand::HidSequence(s));
+    }
+
+    // tapped once per detent
+    fn on_turn(&self, _: InputKey, turn: KnobTurn, device: &Sender<SerialCommand>) -> () {
+        let mut s = HidSequence::new();
+        for _ in 0..turn.steps.min(MAX_TAPS) {
+            let _ = s.push(HidReport::keyboard(&self.keys), TAP_HOLD_MS);
+            let _ = s.push(HidReport::keyboard(&[]), TAP_HOLD_MS);
+        }
+        let _ = device.send(SerialCommand::HidSequence(s));
+    }
 }
 
-fn run_key(reaction_config: &ReactionConfig, key: InputKey, pressed: bool) {
+fn run_key(
+    reaction_config: &ReactionConfig,
+    key: InputKey,
+    pressed: bool,
+    device: &Sender<SerialCommand>,
+) {
     // we cannot allow any panics to proceed past this point.
     // TODO: figure out how to do that
 
     match reaction_config {
         ReactionConfig::MetaTest(v) => match pressed {
-            true => v.on_press(key),
-            false => v.on_release(key),
+            true => v.on_press(key, device),
+            false => v.on_release(key, device),
+        },
+        ReactionConfig::InputPressKey(v) => match pressed {
+            true => v.on_press(key, device),
+            false => v.on_release(key, device),
         },
-        _ => todo!(),
+        _ => {} // not implemented yet
+    }
+}
+
+fn run_turn(
+    reaction_config: &ReactionConfig,
+    key: InputKey,
+    turn: KnobTurn,
+    device: &Sender<SerialCommand>,
+) {
+    match reaction_config {
+        ReactionConfig::MetaTest(v) => v.on_turn(key, turn, device),
+        ReactionConfig::InputPressKey(v) => v.on_turn(key, turn, device),
+        // a turn is one press and release to reactions that don't handle turns themselves
+        _ => {
+            run_key(reaction_config, key, true, device);
+            run_key(reaction_config, key, false, device);
+        }
+    }
+}
+
+// reacts to the inputs that went down or came up since the last report
+fn run_keys(
+    profile: &HashMap<InputKey, ReactionConfig>,
+    prevkeys: &HashSet<InputKey>,
+    keys: &HashSet<InputKey>,
+    device: &Sender<SerialCommand>,
+) {
+    for p in keys.difference(prevkeys) {
+        if let Some(r) = profile.get(p) {
+            run_key(r, *p, true, device);
+        }
+    }
+
+    for p in prevkeys.difference(keys) {
+        if let Some(r) = profile.get(p) {
+            run_key(r, *p, false, device);
+        }
+    }
+}
+
+fn run_turns(
+    profile: &HashMap<InputKey, ReactionConfig>,
+    turns: HashMap<InputKey, KnobTurn>,
+    device: &Sender<SerialCommand>,
+) {
+    for (k, t) in turns {
+        if let Some(r) = profile.get(&k) {
+            run_turn(r, k, t, device);
+        }
     }
 }
 
@@ -189,6 +438,7 @@ pub fn reaction_task(
     brkr: Arc<AtomicBool>,
     s_evnt_rx: Receiver<SerialEvent>,
     r_evnt_tx: Sender<SerialEvent>,
+    s_cmd_tx: Sender<SerialCommand>,
     config: Arc<Mutex<JukeBoxConfig>>,
 ) -> Result<()> {
     let mut prevkeys = HashSet::<InputKey>::new();
@@ -212,28 +462,22 @@ pub fn reaction_task(
             match evnt {
                 SerialEvent::GetInputKeys(keys) => {
                     let c = config.lock().unwrap();
-                    let profiles = c.profiles.clone();
-                    let current = c.current_profile.clone();
+                    let profile = c.profiles.get(&c.current_profile).cloned();
                     drop(c);
 
-                    let pressed = keys.difference(&prevkeys);
-                    let released = prevkeys.difference(&keys);
-
-                    for p in pressed {
-                        let c = profiles.get(&current).unwrap();
-                        if let Some(r) = c.get(&p) {
-                            let _ = run_key(r, *p, true);
-                        }
+                    if let Some(profile) = profile {
+                        run_keys(&profile, &prevkeys, &keys, &s_cmd_tx);
                     }
+                    prevkeys = keys;
+                }
+                SerialEvent::GetKnobTurns(turns) => {
+                    let c = config.lock().unwrap();
+                    let profile = c.profiles.get(&c.current_profile).cloned();
+                    drop(c);
 
-                    for p in released {
-                        let c = profiles.get(&current).unwrap();
-                        if let Some(r) = c.get(&p) {
-                            let _ = run_key(r, *p, false);
-                        }
+                    if let Some(profile) = profile {
+                        run_turns(&profile, turns, &s_cmd_tx);
                     }
-
-                    prevkeys = keys;
                 }
                 _ => {}
             }
@@ -242,3 +486,110 @@ pub fn reaction_task(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use jukebox_util::peripheral::SwitchPosition;
+
+    use super::*;
+
+    // goes through the reports like the reaction thread does, returning the keyboard reports the
+    // device was asked to send
+    fn react(
+        profile: &HashMap<InputKey, ReactionConfig>,
+        reports: &[EncoderBlock],
+    ) -> Vec<Vec<u8>> {
+        let (tx, rx) = channel();
+        let mut prevkeys = HashSet::new();
+        for e in reports {
+            let keys = InputKey::trans_knobs(e);
+            run_keys(profile, &prevkeys, &keys, &tx);
+            prevkeys = keys;
+            run_turns(profile, InputKey::trans_knob_turns(e), &tx);
+        }
+
+        rx.try_iter()
+            .flat_map(|cmd| match cmd {
+                SerialCommand::HidSequence(s) => s.steps().to_vec(),
+                _ => Vec::new(),
+            })
+            .map(|step| match step.report {
+                HidReport::Keyboard(k) => k.into_iter().filter(|k| *k != 0).collect(),
+                _ => panic!("only keys were set up"),
+            })
+            .collect()
+    }
+
+    fn turned(steps: i16) -> EncoderBlock {
+        let mut e = EncoderBlock::new(2);
+        e.encoders[0].steps = steps;
+        e
+    }
+
+    #[test]
+    fn one_detent_is_one_tap() {
+        let profile = HashMap::from([(
+            InputKey::KnobLeftClockwise,
+            ReactionConfig::InputPressKey(ReactionInputPressKey { keys: vec![0x80] }),
+        )]);
+
+        let sent = react(&profile, &[turned(1), turned(0)]);
+        assert_eq!(sent, vec![vec![0x80], vec![]]);
+
+        let sent = react(&profile, &[turned(3), turned(0)]);
+        let taps: Vec<Vec<u8>> = (0..3).flat_map(|_| [vec![0x80], vec![]]).collect();
+        assert_eq!(sent, taps);
+
+        // the other way isn't set up
+        assert!(react(&profile, &[turned(-1), turned(0)]).is_empty());
+    }
+
+    #[test]
+    fn knob_switch_is_held() {
+        let profile = HashMap::from([(
+            InputKey::KnobLeftSwitch,
+            ReactionConfig::InputPressKey(ReactionInputPressKey { keys: vec![0x7F] }),
+        )]);
+        let mut pressed = turned(0);
+        pressed.encoders[0].switch = SwitchPosition::Down;
+
+        let sent = react(&profile, &[pressed, pressed, turned(0)]);
+        assert_eq!(sent, vec![vec![0x7F], vec![]]);
+    }
+
+    #[test]
+    fn unhandled_turns_do_nothing() {
+        let profile =
+            HashMap::from([(InputKey::KnobLeftClockwise, ReactionConfig::SystemLaunch())]);
+        assert!(react(&profile, &[turned(2), turned(0)]).is_empty());
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        let keys = parse_keys("Ctrl+Shift+F5").unwrap();
+        assert_eq!(keys, vec![0xE0, 0xE1, 0x3E]);
+        assert_eq!(format_keys(&keys), "Ctrl+Shift+F5");
+
+        assert_eq!(
+            parse_keys(" a + 1 + 0 + f24 ").unwrap(),
+            vec![0x04, 0x1E, 0x27, 0x73]
+        );
+        assert_eq!(format_keys(&[0x04, 0x1E, 0x27, 0x73]), "A+1+0+F24");
+
+        for usage in 0..=u8::MAX {
+            assert_eq!(parse_keys(&format_keys(&[usage])).unwrap(), vec![usage]);
+        }
+    }
+
+    #[test]
+    fn keys_reject_bad_input() {
+        assert!(parse_keys("").is_err());
+        assert!(parse_keys("+").is_err());
+        assert!(parse_keys("Ctrl+Hyper").is_err());
+        assert!(parse_keys("F25").is_err());
+        assert!(parse_keys("0x100").is_err());
+        assert!(parse_keys("A+B+C+D+E+F+G+H+I").is_err());
+    }
+}
@@ -1,8 +1,9 @@
 // Serial communication
 
-use crate::reaction::InputKey;
+use crate::animation::Animation;
+use crate::reaction::{InputKey, KnobTurn};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::Read;
 use std::sync::atomic::AtomicBool;
 use std::sync::mpsc::{Receiver, Sender};
@@ -11,25 +12,55 @@ use std::thread::{sleep, yield_now};
 use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail, Context, Result};
+use jukebox_util::animation::{encode_frame, ANIM_CHUNK_END, ANIM_CHUNK_START};
+use jukebox_util::diagnostics::{DiagnosticsReport, DiagnosticsTest};
+use jukebox_util::hid::{HidSequence, HID_SEQUENCE_MAX_SIZE};
 use jukebox_util::peripheral::{
-    KeyInputs, KnobInputs, PedalInputs, IDENT_KEY_INPUT, IDENT_KNOB_INPUT, IDENT_PEDAL_INPUT,
+    AxisBlock, DeviceSettings, DeviceType, InputBlock, InputReport, KeyLabels, PedalConfig,
+    PeripheralDescriptor, RgbConfig, ScreenOrientation, ScreenPowerConfig, ScreenText,
     IDENT_UNKNOWN_INPUT,
 };
 use jukebox_util::protocol::{
-    CMD_DISCONNECT, CMD_END, CMD_GET_INPUT_KEYS, CMD_GREET, CMD_NEGATIVE_ACK, CMD_UPDATE,
-    RSP_DISCONNECTED, RSP_END, RSP_INPUT_HEADER, RSP_LINK_DELIMITER, RSP_LINK_HEADER, RSP_UNKNOWN,
+    decode_payload_len, encode_payload_header, ResetReason, CMD_ANIMATION_FRAME,
+    CMD_DEVICE_SETTINGS, CMD_DIAGNOSTICS, CMD_DISCONNECT, CMD_END, CMD_GET_INPUT_KEYS,
+    CMD_GET_PERIPHERALS, CMD_GREET, CMD_HID_SEQUENCE, CMD_KEY_LABELS, CMD_NEGATIVE_ACK,
+    CMD_PAYLOAD_MAX_SIZE, CMD_PEDAL_CONFIG, CMD_RGB_CONFIG, CMD_SCREEN_ORIENTATION,
+    CMD_SCREEN_POWER, CMD_SCREEN_TEXT, CMD_UPDATE, RSP_ANIMATION_FRAME_HEADER,
+    RSP_DEVICE_SETTINGS_HEADER, RSP_DIAGNOSTICS_HEADER, RSP_DISCONNECTED, RSP_END,
+    RSP_HID_SEQUENCE_HEADER, RSP_INPUT_HEADER, RSP_KEY_LABELS_HEADER, RSP_LINK_DELIMITER,
+    RSP_LINK_HEADER, RSP_PEDAL_CONFIG_HEADER, RSP_PERIPHERALS_HEADER, RSP_RGB_CONFIG_HEADER,
+    RSP_SCREEN_ORIENTATION_HEADER, RSP_SCREEN_POWER_HEADER, RSP_SCREEN_TEXT_HEADER, RSP_UNKNOWN,
 };
-use serialport::SerialPort;
+use serialport::{ClearBuffer, SerialPort};
+
+// time given to streaming animation frames each time round the loop, so inputs stay responsive
+const ANIMATION_SEND_BUDGET: Duration = Duration::from_millis(15);
+// how long texts and key labels stay up before the animation carries on over them
+const ANIMATION_PAUSE: Duration = Duration::from_secs(3);
+// tries at a hid sequence the device keeps refusing before it's dropped, a second of polling
+const HID_SEQUENCE_ATTEMPTS: u32 = 40;
 
 #[derive(PartialEq, Clone)]
 pub struct SerialConnectionDetails {
     pub input_identifier: u8,
     pub firmware_version: String,
     pub device_uid: String,
+    pub reset_reason: ResetReason,
 }
 
 pub enum SerialCommand {
-    // GetPeripherals,
+    GetPeripherals,
+    Diagnostics(DiagnosticsTest),
+    PedalConfig(PedalConfig),
+    ScreenOrientation(ScreenOrientation),
+    ScreenPower(ScreenPowerConfig),
+    ScreenText(ScreenText),
+    KeyLabels(KeyLabels),
+    PlayAnimation(Arc<Animation>, u8), // frames per second at most
+    StopAnimation,
+    RgbConfig(RgbConfig),
+    HidSequence(HidSequence), // reports for the device to send on its own hid interfaces
+    DeviceSettings(DeviceSettings), // saved to the device, which restarts into them
     UpdateDevice,
     DisconnectDevice,
     // TestFunction,
@@ -39,7 +70,10 @@ pub enum SerialCommand {
 pub enum SerialEvent {
     Connected(SerialConnectionDetails),
     GetInputKeys(HashSet<InputKey>),
-    // GetPeripherals(HashSet<Peripheral>),
+    GetKnobTurns(HashMap<InputKey, KnobTurn>),
+    GetPedalPositions(AxisBlock),
+    GetPeripherals(PeripheralDescriptor),
+    Diagnostics(DiagnosticsReport),
     LostConnection,
     Disconnected,
 }
@@ -70,12 +104,65 @@ fn get_serial_string(f: &mut Box<dyn SerialPort>) -> Result<Vec<u8>> {
     }
 }
 
+fn get_serial_bytes(f: &mut Box<dyn SerialPort>, len: usize) -> Result<Vec<u8>> {
+    let timeout = Instant::now() + Duration::from_secs(3);
+    let mut buf = vec![0u8; len];
+    let mut read = 0;
+
+    while read < len {
+        if Instant::now() >= timeout {
+            bail!("read timed out");
+        }
+
+        if let Ok(s) = f.read(&mut buf[read..]) {
+            read += s;
+        }
+    }
+
+    Ok(buf)
+}
+
+fn get_serial_sized(f: &mut Box<dyn SerialPort>, header: u8) -> Result<Vec<u8>> {
+    // responses with binary data say how long they are, so the data can't be mistaken for RSP_END
+    let head = get_serial_bytes(f, 3)?;
+    if head[0] != header {
+        send_negative_ack(f)?;
+        bail!(
+            "response header mismatch (expected {}, got {})",
+            header,
+            head[0]
+        );
+    }
+
+    let len = decode_payload_len(head[1], head[2]);
+    let data = get_serial_bytes(f, len)?;
+
+    let end = get_serial_bytes(f, RSP_END.len())?;
+    if end != RSP_END {
+        send_negative_ack(f)?;
+        bail!("response missing end (got {:?})", end);
+    }
+
+    Ok(data)
+}
+
 fn send_cmd(f: &mut Box<dyn SerialPort>, c: u8) -> Result<()> {
     let mut cmd = vec![c];
     cmd.extend_from_slice(CMD_END);
     send_bytes(f, cmd.as_slice()).with_context(|| format!("failed to send cmd {}", c))
 }
 
+fn send_cmd_payload(f: &mut Box<dyn SerialPort>, c: u8, payload: &[u8]) -> Result<()> {
+    if payload.len() > CMD_PAYLOAD_MAX_SIZE {
+        bail!("payload for cmd {} too large ({} bytes)", c, payload.len());
+    }
+
+    let mut cmd = encode_payload_header(c, payload.len()).to_vec();
+    cmd.extend_from_slice(payload);
+    cmd.extend_from_slice(CMD_END);
+    send_bytes(f, cmd.as_slice()).with_context(|| format!("failed to send cmd {}", c))
+}
+
 fn send_bytes(f: &mut Box<dyn SerialPort>, bytes: &[u8]) -> Result<()> {
     f.write_all(bytes)
         .with_context(|| format!("failed to write message {:?}", bytes))?;
@@ -107,6 +194,16 @@ fn expect_string(f: &mut Box<dyn SerialPort>, expect: &[u8]) -> Result<()> {
     Ok(())
 }
 
+fn expect_echo(f: &mut Box<dyn SerialPort>, header: u8, echo: &[u8]) -> Result<()> {
+    // the device sends back what it was given, once it's been applied
+    let resp = get_serial_sized(f, header)?;
+    if resp != echo {
+        bail!("echo mismatch (expected {:?}, got {:?})", echo, resp);
+    }
+
+    Ok(())
+}
+
 fn send_expect(f: &mut Box<dyn SerialPort>, send: &[u8], expect: &[u8]) -> Result<()> {
     send_bytes(f, send).with_context(|| format!("failed to send bytes {:?}", send))?;
     expect_string(f, expect).with_context(|| format!("failed to get bytes {:?}", expect))?;
@@ -133,6 +230,7 @@ fn greet_host(f: &mut Box<dyn SerialPort>) -> Result<SerialConnectionDetails> {
     let mut input_identifier = None;
     let mut firmware_version = None;
     let mut device_uid = None;
+    let mut reset_reason = ResetReason::Unknown;
     for (i, s) in resp.split(|c| *c == RSP_LINK_DELIMITER).enumerate() {
         if i == 1 {
             input_identifier = Some(s.get(0).unwrap_or(&IDENT_UNKNOWN_INPUT));
@@ -140,6 +238,8 @@ fn greet_host(f: &mut Box<dyn SerialPort>) -> Result<SerialConnectionDetails> {
             firmware_version = Some(s);
         } else if i == 3 {
             device_uid = Some(s);
+        } else if i == 4 {
+            reset_reason = ResetReason::decode(*s.get(0).unwrap_or(&0));
         }
     }
 
@@ -167,59 +267,157 @@ fn greet_host(f: &mut Box<dyn SerialPort>) -> Result<SerialConnectionDetails> {
         input_identifier: *input_identifier.unwrap(),
         firmware_version: firmware_version,
         device_uid: device_uid,
+        reset_reason: reset_reason,
     })
 }
 
-fn transmit_get_input_keys(f: &mut Box<dyn SerialPort>) -> Result<HashSet<InputKey>> {
+struct InputKeys {
+    keys: HashSet<InputKey>,
+    knob_turns: HashMap<InputKey, KnobTurn>,
+    pedal_positions: Option<AxisBlock>,
+}
+
+fn transmit_get_input_keys(
+    f: &mut Box<dyn SerialPort>,
+    device_type: DeviceType,
+) -> Result<InputKeys> {
     send_cmd(f, CMD_GET_INPUT_KEYS).context("failed to send get input keys")?;
+    let resp = get_serial_sized(f, RSP_INPUT_HEADER).context("failed to read input keys")?;
+    let report =
+        InputReport::decode(&resp).map_err(|_| anyhow!("failed to decode input report"))?;
+
+    let mut result = InputKeys {
+        keys: HashSet::new(),
+        knob_turns: HashMap::new(),
+        pedal_positions: None,
+    };
+    for block in report.iter() {
+        match block {
+            InputBlock::Keys(k) => result.keys.extend(InputKey::trans_keys(device_type, k)),
+            InputBlock::Encoders(e) => {
+                result.keys.extend(InputKey::trans_knobs(e));
+                result.knob_turns.extend(InputKey::trans_knob_turns(e));
+            }
+            InputBlock::Axes(a) => {
+                if device_type == DeviceType::PedalPad {
+                    result.pedal_positions = Some(*a);
+                }
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn transmit_get_peripherals(f: &mut Box<dyn SerialPort>) -> Result<PeripheralDescriptor> {
+    send_cmd(f, CMD_GET_PERIPHERALS).context("failed to send get peripherals")?;
+    let resp = get_serial_sized(f, RSP_PERIPHERALS_HEADER).context("failed to read peripherals")?;
+
+    PeripheralDescriptor::decode(&resp).map_err(|_| anyhow!("failed to decode peripherals"))
+}
+
+fn transmit_diagnostics(
+    f: &mut Box<dyn SerialPort>,
+    test: DiagnosticsTest,
+) -> Result<DiagnosticsReport> {
+    send_cmd_payload(f, CMD_DIAGNOSTICS, &[test.encode()]).context("failed to send diagnostics")?;
+    let resp = get_serial_sized(f, RSP_DIAGNOSTICS_HEADER).context("failed to read diagnostics")?;
+
+    DiagnosticsReport::decode(&resp).map_err(|_| anyhow!("failed to decode diagnostics report"))
+}
+
+fn transmit_pedal_config(f: &mut Box<dyn SerialPort>, config: PedalConfig) -> Result<()> {
+    send_cmd_payload(f, CMD_PEDAL_CONFIG, &config.encode())
+        .context("failed to send pedal config")?;
+
+    expect_echo(f, RSP_PEDAL_CONFIG_HEADER, &config.encode())
+        .context("failed to confirm pedal config")
+}
+
+fn transmit_screen_orientation(
+    f: &mut Box<dyn SerialPort>,
+    orientation: ScreenOrientation,
+) -> Result<()> {
+    send_cmd_payload(f, CMD_SCREEN_ORIENTATION, &orientation.encode())
+        .context("failed to send screen orientation")?;
+
+    expect_echo(f, RSP_SCREEN_ORIENTATION_HEADER, &orientation.encode())
+        .context("failed to confirm screen orientation")
+}
+
+fn transmit_screen_power(f: &mut Box<dyn SerialPort>, config: ScreenPowerConfig) -> Result<()> {
+    send_cmd_payload(f, CMD_SCREEN_POWER, &config.encode())
+        .context("failed to send screen power config")?;
+
+    expect_echo(f, RSP_SCREEN_POWER_HEADER, &config.encode())
+        .context("failed to confirm screen power config")
+}
+
+fn transmit_rgb_config(f: &mut Box<dyn SerialPort>, config: RgbConfig) -> Result<()> {
+    send_cmd_payload(f, CMD_RGB_CONFIG, &config.encode()).context("failed to send rgb config")?;
+
+    expect_echo(f, RSP_RGB_CONFIG_HEADER, &config.encode()).context("failed to confirm rgb config")
+}
+
+fn transmit_screen_text(f: &mut Box<dyn SerialPort>, text: ScreenText) -> Result<()> {
+    let mut payload = [0u8; CMD_PAYLOAD_MAX_SIZE];
+    let size = text
+        .encode(&mut payload)
+        .map_err(|_| anyhow!("failed to encode screen text"))?;
+    send_cmd_payload(f, CMD_SCREEN_TEXT, &payload[..size]).context("failed to send screen text")?;
+
+    let mut rsp = vec![RSP_SCREEN_TEXT_HEADER];
+    rsp.extend_from_slice(RSP_END);
+    expect_string(f, &rsp).context("failed to confirm screen text")
+}
+
+fn transmit_key_labels(f: &mut Box<dyn SerialPort>, labels: KeyLabels) -> Result<()> {
+    let mut payload = [0u8; CMD_PAYLOAD_MAX_SIZE];
+    let size = labels
+        .encode(&mut payload)
+        .map_err(|_| anyhow!("failed to encode key labels"))?;
+    send_cmd_payload(f, CMD_KEY_LABELS, &payload[..size]).context("failed to send key labels")?;
+
+    let mut rsp = vec![RSP_KEY_LABELS_HEADER];
+    rsp.extend_from_slice(RSP_END);
+    expect_string(f, &rsp).context("failed to confirm key labels")
+}
+
+// Ok(false) if the device was still drawing the last chunk and this one needs sending again
+fn transmit_animation_chunk(f: &mut Box<dyn SerialPort>, chunk: &[u8]) -> Result<bool> {
+    send_cmd_payload(f, CMD_ANIMATION_FRAME, chunk).context("failed to send animation frame")?;
     let resp = get_serial_string(f)?;
 
-    if *resp.iter().nth(0).unwrap_or(&0) != RSP_INPUT_HEADER {
-        log::info!("rsp input: {:?}", resp);
+    if resp.len() != 2 + RSP_END.len() || resp[0] != RSP_ANIMATION_FRAME_HEADER {
         send_negative_ack(f)?;
-        bail!("failed to parse input keys (command character mismatch)");
+        bail!("failed to confirm animation frame (got {:?})", resp);
     }
+    Ok(resp[1] != 0)
+}
 
-    let mut result = HashSet::new();
-    let mut i = resp.iter();
-    loop {
-        match i.next() {
-            Some(c) => match *c {
-                IDENT_KEY_INPUT => {
-                    let w2 = i.next();
-                    let w1 = i.next();
-                    if w2.is_none() || w1.is_none() {
-                        bail!("failed to parse input keys (missing keyboard words)");
-                    }
-                    let keypad = KeyInputs::decode(&[*c, *w2.unwrap(), *w1.unwrap()])
-                        .map_err(|_| anyhow!("failed to decode key inputs"))?;
-                    result.extend(InputKey::trans_keys(keypad));
-                }
-                IDENT_KNOB_INPUT => {
-                    let w = i.next();
-                    if w.is_none() {
-                        bail!("failed to parse input keys (missing knob 1 word)");
-                    }
-                    let knobpad = KnobInputs::decode(&[*c, *w.unwrap()])
-                        .map_err(|_| anyhow!("failed to decode knob inputs"))?;
-                    result.extend(InputKey::trans_knob(knobpad));
-                }
-                IDENT_PEDAL_INPUT => {
-                    let w = i.next();
-                    if w.is_none() {
-                        bail!("failed to parse input keys (missing pedal 1 word)");
-                    }
-                    let pedalpad = PedalInputs::decode(&[*c, *w.unwrap()])
-                        .map_err(|_| anyhow!("failed to decode pedal inputs"))?;
-                    result.extend(InputKey::trans_pedals(pedalpad));
-                }
-                _ => {}
-            },
-            None => break,
-        }
+// Ok(false) if the device's queue was full and the sequence needs sending again
+fn transmit_hid_sequence(f: &mut Box<dyn SerialPort>, sequence: &HidSequence) -> Result<bool> {
+    let mut payload = [0u8; HID_SEQUENCE_MAX_SIZE];
+    let size = sequence
+        .encode(&mut payload)
+        .map_err(|_| anyhow!("failed to encode hid sequence"))?;
+    send_cmd_payload(f, CMD_HID_SEQUENCE, &payload[..size])
+        .context("failed to send hid sequence")?;
+    let resp = get_serial_string(f)?;
+
+    if resp.len() != 2 + RSP_END.len() || resp[0] != RSP_HID_SEQUENCE_HEADER {
+        send_negative_ack(f)?;
+        bail!("failed to confirm hid sequence (got {:?})", resp);
     }
+    Ok(resp[1] != 0)
+}
 
-    Ok(result)
+fn transmit_device_settings(f: &mut Box<dyn SerialPort>, settings: DeviceSettings) -> Result<()> {
+    send_cmd_payload(f, CMD_DEVICE_SETTINGS, &settings.encode())
+        .context("failed to send device settings")?;
+
+    expect_echo(f, RSP_DEVICE_SETTINGS_HEADER, &settings.encode())
+        .context("failed to confirm device settings")
 }
 
 fn transmit_update_signal(f: &mut Box<dyn SerialPort>) -> Result<()> {
@@ -247,7 +445,9 @@ pub fn serial_get_device() -> Result<Box<dyn SerialPort>> {
     let ports: Vec<_> = ports
         .iter()
         .filter(|p| match &p.port_type {
-            serialport::SerialPortType::UsbPort(p) => p.vid == 0x1209 && p.pid == 0xF20A,
+            serialport::SerialPortType::UsbPort(p) => {
+                p.vid == 0x1209 && DeviceType::is_usb_pid(p.pid)
+            }
             _ => false,
         })
         .collect();
@@ -267,6 +467,86 @@ pub fn serial_get_device() -> Result<Box<dyn SerialPort>> {
         .context("failed to open serial port")?)
 }
 
+// Streams an animation to the device, a frame at a time. Frames are picked by how long it's been
+// playing, so a slow link drops frames rather than falling behind, and each one only carries the
+// pixels that changed since the last one sent.
+struct AnimationPlayer {
+    animation: Arc<Animation>,
+    frame_time: Duration,
+    start: Instant,
+    next_frame: Instant,
+    last_frame: Option<usize>, // what the device has, None if it needs a whole frame
+    chunks: VecDeque<Vec<u8>>,
+}
+impl AnimationPlayer {
+    fn new(animation: Arc<Animation>, fps: u8) -> Self {
+        let now = Instant::now();
+        AnimationPlayer {
+            animation: animation,
+            frame_time: Duration::from_secs(1) / fps.max(1) as u32,
+            start: now,
+            next_frame: now,
+            last_frame: None,
+            chunks: VecDeque::new(),
+        }
+    }
+
+    // something else drew over the screen, leave it up for a bit then start again from a whole frame
+    fn pause(&mut self) {
+        self.next_frame = Instant::now() + ANIMATION_PAUSE;
+        self.last_frame = None;
+        self.chunks.clear();
+    }
+
+    fn queue_frame(&mut self, n: usize) {
+        let frame = &self.animation.frames[n].pixels;
+        let prev = self
+            .last_frame
+            .map(|p| &self.animation.frames[p].pixels[..]);
+
+        // pack whole ops into chunks, each starting with its flags
+        let mut chunks = vec![vec![0u8]];
+        encode_frame(prev, frame, |op| {
+            if chunks.last().unwrap().len() + op.len() > CMD_PAYLOAD_MAX_SIZE {
+                chunks.push(vec![0u8]);
+            }
+            chunks.last_mut().unwrap().extend_from_slice(op);
+        });
+        chunks.first_mut().unwrap()[0] |= ANIM_CHUNK_START;
+        chunks.last_mut().unwrap()[0] |= ANIM_CHUNK_END;
+
+        self.chunks = chunks.into();
+        self.last_frame = Some(n);
+    }
+
+    fn update(&mut self, f: &mut Box<dyn SerialPort>) -> Result<()> {
+        let now = Instant::now();
+        let deadline = now + ANIMATION_SEND_BUDGET;
+
+        if self.chunks.is_empty() && now >= self.next_frame {
+            let n = self.animation.frame_at(now - self.start);
+            if self.last_frame != Some(n) {
+                self.queue_frame(n);
+            }
+            self.next_frame = now + self.frame_time;
+        }
+
+        while let Some(chunk) = self.chunks.front() {
+            if Instant::now() >= deadline {
+                break;
+            }
+            if transmit_animation_chunk(f, chunk)? {
+                self.chunks.pop_front();
+            } else {
+                // the device is still drawing the last chunk
+                sleep(Duration::from_millis(1));
+            }
+        }
+
+        Ok(())
+    }
+}
+
 pub fn serial_comms(
     f: &mut Box<dyn SerialPort>,
     serialcommand_rx: &Receiver<SerialCommand>,
@@ -274,14 +554,29 @@ pub fn serial_comms(
 ) -> Result<()> {
     // Flush serial command queue
     while let Ok(_) = serialcommand_rx.try_recv() {}
+    // anything left in the port from before (the device or the computer sleeping, say) would
+    // throw off the greeting
+    f.clear(ClearBuffer::All)
+        .context("failed to clear serial buffers")?;
 
     // Greet and link up
     let device_info = greet_host(f)?;
+    if device_info.reset_reason.is_watchdog() {
+        log::warn!(
+            "Device recovered from a stall (reset reason: {:?})",
+            device_info.reset_reason
+        );
+    }
+    let device_type = DeviceType::decode(device_info.input_identifier);
     // TODO: check that firmware version is ok
     serialevent_tx
         .send(SerialEvent::Connected(device_info))
         .context("failed to send device info")?;
 
+    let mut player: Option<AnimationPlayer> = None;
+    // sent in order, the ones the device had no room for are tried again next time round
+    let mut hid_sequences: VecDeque<HidSequence> = VecDeque::new();
+    let mut hid_refusals = 0; // times the one at the front has been refused
     let mut timer = Instant::now();
     'forv: loop {
         if Instant::now() < timer {
@@ -290,13 +585,75 @@ pub fn serial_comms(
         }
         timer = Instant::now() + Duration::from_millis(25);
 
-        let keys = transmit_get_input_keys(f)?;
+        let report = transmit_get_input_keys(f, device_type)?;
         serialevent_tx
-            .send(SerialEvent::GetInputKeys(keys))
+            .send(SerialEvent::GetInputKeys(report.keys))
             .context("failed to send input info")?;
+        if !report.knob_turns.is_empty() {
+            serialevent_tx
+                .send(SerialEvent::GetKnobTurns(report.knob_turns))
+                .context("failed to send knob turns")?;
+        }
+        if let Some(p) = report.pedal_positions {
+            serialevent_tx
+                .send(SerialEvent::GetPedalPositions(p))
+                .context("failed to send pedal positions")?;
+        }
 
         while let Ok(cmd) = serialcommand_rx.try_recv() {
             match cmd {
+                SerialCommand::GetPeripherals => {
+                    let peripherals = transmit_get_peripherals(f)?;
+                    serialevent_tx
+                        .send(SerialEvent::GetPeripherals(peripherals))
+                        .context("failed to send peripherals")?;
+                }
+                SerialCommand::Diagnostics(test) => {
+                    let report = transmit_diagnostics(f, test)?;
+                    serialevent_tx
+                        .send(SerialEvent::Diagnostics(report))
+                        .context("failed to send diagnostics report")?;
+                }
+                SerialCommand::PedalConfig(config) => {
+                    transmit_pedal_config(f, config)?;
+                }
+                SerialCommand::ScreenOrientation(orientation) => {
+                    transmit_screen_orientation(f, orientation)?;
+                }
+                SerialCommand::ScreenPower(config) => {
+                    transmit_screen_power(f, config)?;
+                }
+                SerialCommand::ScreenText(text) => {
+                    transmit_screen_text(f, text)?;
+                    if let Some(p) = &mut player {
+                        p.pause();
+                    }
+                }
+                SerialCommand::KeyLabels(labels) => {
+                    transmit_key_labels(f, labels)?;
+                    if let Some(p) = &mut player {
+                        p.pause();
+                    }
+                }
+                SerialCommand::PlayAnimation(animation, fps) => {
+                    player = Some(AnimationPlayer::new(animation, fps));
+                }
+                SerialCommand::StopAnimation => {
+                    player = None;
+                }
+                SerialCommand::RgbConfig(config) => {
+                    transmit_rgb_config(f, config)?;
+                }
+                SerialCommand::HidSequence(sequence) => {
+                    hid_sequences.push_back(sequence);
+                }
+                SerialCommand::DeviceSettings(settings) => {
+                    transmit_device_settings(f, settings)?;
+                    serialevent_tx
+                        .send(SerialEvent::Disconnected)
+                        .context("failed to send disconnect info")?;
+                    break 'forv; // The device restarts to apply them, it'll be found again after.
+                }
                 SerialCommand::UpdateDevice => {
                     transmit_update_signal(f)?;
                     serialevent_tx
@@ -313,6 +670,29 @@ pub fn serial_comms(
                 }
             }
         }
+
+        while let Some(sequence) = hid_sequences.front() {
+            if transmit_hid_sequence(f, sequence)? {
+                hid_sequences.pop_front();
+                hid_refusals = 0;
+                continue;
+            }
+
+            hid_refusals += 1;
+            if hid_refusals >= HID_SEQUENCE_ATTEMPTS {
+                log::warn!(
+                    "Device refused a hid sequence {} times, dropping it",
+                    hid_refusals
+                );
+                hid_sequences.pop_front();
+                hid_refusals = 0;
+            }
+            break;
+        }
+
+        if let Some(p) = &mut player {
+            p.update(f)?;
+        }
     }
 
     Ok(())